@@ -0,0 +1,82 @@
+use rltk::Point;
+use specs::prelude::*;
+
+use super::Map;
+
+// The part of the screen the map is drawn in, the UI box starts right below it.
+// Fixed to fit the 80x50 console, the view doesn't resize with the window. Levels
+// are built at this same size for now, so it only scrolls once a level is bigger
+pub const VIEW_WIDTH: i32 = 80;
+pub const VIEW_HEIGHT: i32 = 43;
+
+// The map tile shown in the view's top-left corner. The view is centred on center,
+// but held back at the map's edges so it never scrolls past them
+pub fn view_origin(map: &Map, center: Point) -> Point {
+    let clamp = |center: i32, view: i32, size: i32| i32::max(0, i32::min(center - view / 2, size - view));
+    Point::new(
+        clamp(center.x, VIEW_WIDTH, map.width),
+        clamp(center.y, VIEW_HEIGHT, map.height),
+    )
+}
+
+// where the view is this frame, following the player
+pub fn current_origin(ecs: &World) -> Point {
+    view_origin(&ecs.fetch::<Map>(), *ecs.fetch::<Point>())
+}
+
+// the screen cell a map tile is drawn in, if it's in view at all
+pub fn map_to_screen(origin: Point, tile: Point) -> Option<Point> {
+    let screen = Point::new(tile.x - origin.x, tile.y - origin.y);
+    if in_view(screen) {
+        Some(screen)
+    } else {
+        None
+    }
+}
+
+// the map tile a screen cell shows, nothing over the UI
+pub fn screen_to_map(origin: Point, screen: Point) -> Option<Point> {
+    if in_view(screen) {
+        Some(Point::new(screen.x + origin.x, screen.y + origin.y))
+    } else {
+        None
+    }
+}
+
+fn in_view(screen: Point) -> bool {
+    screen.x >= 0 && screen.x < VIEW_WIDTH && screen.y >= 0 && screen.y < VIEW_HEIGHT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // only the size matters to the camera
+    fn map_of_size(width: i32, height: i32) -> Map {
+        Map { width, height, ..Map::default() }
+    }
+
+    #[test]
+    fn a_map_that_fits_never_scrolls() {
+        let map = map_of_size(VIEW_WIDTH, VIEW_HEIGHT);
+        assert_eq!(view_origin(&map, Point::new(0, 0)), Point::new(0, 0));
+        assert_eq!(view_origin(&map, Point::new(VIEW_WIDTH - 1, VIEW_HEIGHT - 1)), Point::new(0, 0));
+    }
+
+    #[test]
+    fn a_big_map_scrolls_but_stops_at_its_edges() {
+        let map = map_of_size(120, 70);
+        assert_eq!(view_origin(&map, Point::new(3, 2)), Point::new(0, 0));
+        assert_eq!(view_origin(&map, Point::new(60, 35)), Point::new(60 - VIEW_WIDTH / 2, 35 - VIEW_HEIGHT / 2));
+        let far_corner = view_origin(&map, Point::new(118, 68));
+        assert_eq!(far_corner, Point::new(120 - VIEW_WIDTH, 70 - VIEW_HEIGHT));
+
+        // the map's last tile lands in the view's last cell, tiles left of the origin are off screen
+        let last_tile = Point::new(119, 69);
+        assert_eq!(map_to_screen(far_corner, last_tile), Some(Point::new(VIEW_WIDTH - 1, VIEW_HEIGHT - 1)));
+        assert_eq!(map_to_screen(far_corner, Point::new(far_corner.x - 1, 69)), None);
+        assert_eq!(screen_to_map(far_corner, Point::new(VIEW_WIDTH - 1, VIEW_HEIGHT - 1)), Some(last_tile));
+        // the UI box below the view shows no map at all
+        assert_eq!(screen_to_map(far_corner, Point::new(0, VIEW_HEIGHT)), None);
+    }
+}
@@ -1,12 +1,12 @@
 
 use specs::prelude::*;
 use specs_derive::*;
-use rltk::{RGB};
-use crate::Map;
+use rltk::{Point, RGB};
 use specs::saveload::ConvertSaveload;
 use specs::error::NoError;
 use specs::saveload::Marker;
 use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
 
 
 #[derive(Component, ConvertSaveload, Clone)]
@@ -47,6 +47,11 @@ pub struct Name {
 #[derive(Component, Serialize, Deserialize, Clone)]
 pub struct BlocksTile {}
 
+// creatures big enough to hide whatever is behind them,
+// only used when the creatures_block_vision setting is on
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct BlocksVision {}
+
 #[derive(Component, Debug, ConvertSaveload, Clone)]
 pub struct CombatStats {
     pub max_hp: i32,
@@ -61,22 +66,63 @@ pub struct WantsToMelee {
     pub target: Entity,
 }
 
+// what kind of harm a hit does, resistances and vulnerabilities go by this
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug, Default)]
+pub enum DamageType {
+    #[default]
+    Physical,
+    Fire,
+    Cold,
+    Poison,
+}
+
+impl DamageType {
+    // how the log refers to a hit of this kind
+    pub fn noun(self) -> &'static str {
+        match self {
+            DamageType::Physical => "blow",
+            DamageType::Fire => "flames",
+            DamageType::Cold => "frost",
+            DamageType::Poison => "poison",
+        }
+    }
+}
+
+// each hit is the damage, its kind and whether the player dealt it, so kills can be credited
 #[derive(Component, Debug, ConvertSaveload, Clone)]
 pub struct SufferDamage {
-    pub amount: Vec<i32>,
+    pub amount: Vec<(i32, DamageType, bool)>,
 }
 
 impl SufferDamage {
-    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32) {
+    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32, from_player: bool) {
+        SufferDamage::new_typed_damage(store, victim, amount, DamageType::Physical, from_player);
+    }
+
+    pub fn new_typed_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32, kind: DamageType, from_player: bool) {
         if let Some(suffering) = store.get_mut(victim) {
-            suffering.amount.push(amount);
+            suffering.amount.push((amount, kind, from_player));
         } else {
-            let dmg = SufferDamage { amount: vec![amount]};
+            let dmg = SufferDamage { amount: vec![(amount, kind, from_player)]};
             store.insert(victim, dmg).expect("Unable to insert damage");
         }
     }
 }
 
+// takes half damage from hits of these kinds
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Resistances {
+    #[serde(default)]
+    pub types: Vec<DamageType>
+}
+
+// takes double damage from hits of these kinds
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Vulnerabilities {
+    #[serde(default)]
+    pub types: Vec<DamageType>
+}
+
 
 #[derive(Component, Debug, Serialize, Deserialize, Clone)]
 pub struct Item{}
@@ -123,9 +169,12 @@ pub struct Ranged {
     pub range: i32,
 }
 
-#[derive(Component, Debug, ConvertSaveload, Clone)]
+// saves from before damage had a kind load it as physical
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
 pub struct InflictsDamage {
     pub damage: i32,
+    #[serde(default)]
+    pub kind: DamageType,
 }
 
 #[derive(Component, Debug, ConvertSaveload, Clone)]
@@ -133,6 +182,16 @@ pub struct AreaOfEffect {
     pub radius: i32,
 }
 
+// potions that can be thrown, splashing their effect over an area where they land
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct ShattersOnThrow {
+    pub radius: i32,
+}
+
+// AoE items leave their user out of the blast unless they have this
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct HarmsCaster {}
+
 #[derive(Component, Debug, ConvertSaveload, Clone)]
 pub struct Confusion {
     pub turns: i32,
@@ -143,6 +202,9 @@ pub struct Confusion {
 pub enum EquipmentSlot{
     Melee,
     Shield,
+    Head,
+    Body,
+    Ring,
 }
 
 #[derive(Component, Serialize, Deserialize, Clone)]
@@ -158,21 +220,375 @@ pub struct Equipped {
 }
 
 
+// cursed gear can't be taken off once equipped
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Cursed {}
+
+// a weapon that needs the shield hand as well
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct TwoHanded {}
+
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct MeleePowerBonus {
     pub power: i32
 }
 
 
+// extra chance in a hundred of landing a critical hit, on a fighter or the weapons it wields
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct CritChance {
+    pub percent: i32
+}
+
+
 #[derive(Component, ConvertSaveload, Clone)]
 pub struct DefenseBonus {
     pub defense: i32
 }
 
+// lets a monster sense creatures within this many tiles even without seeing them
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct AggroRange {
+    pub tiles: i32
+}
+
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum HungerState {
+    WellFed,
+    Normal,
+    Hungry,
+    Starving,
+}
+
+// counts down every turn, moving to the next hunger state when it runs out
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct HungerClock {
+    pub state: HungerState,
+    pub duration: i32
+}
+
+// spends the turn looking for secret doors nearby
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct WantsToSearch {}
+
+// what the player has earned from kills, every level takes level * 1000 more xp
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Experience {
+    pub xp: i32,
+    pub level: i32
+}
+
+// set on anything that changed tiles this turn, entry triggers only go off for these
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct EntityMoved {}
+
+// goes off when something steps onto its tile
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct EntryTrigger {}
+
+// not drawn or listed in tooltips until revealed
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Hidden {}
+
+// removed after it has gone off once
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct SingleActivation {}
+
+// a short lived visual effect, deleted once lifetime_ms runs out. never saved
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct ParticleLifetime {
+    pub lifetime_ms: f32
+}
+
+// lets whoever carries it see at least radius tiles, tinting what it lights
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct LightSource {
+    pub radius: i32,
+    pub color: RGB
+}
+
+// counts down to the holder's next action, speed is how many turns apart those are
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Initiative {
+    pub current: i32,
+    pub speed: i32
+}
+
+// drinking it hastes the drinker for this many of their actions
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct ProvidesHaste {
+    pub turns: i32
+}
+
+// acting twice as often, for this many more actions
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Hasted {
+    pub turns: i32
+}
+
+// acting half as often, for this many more actions, on a trap it's what it does to its victim
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Slowed {
+    pub turns: i32
+}
+
+// how much gold the holder has picked up
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Wallet {
+    pub gold: i32
+}
+
+// drops between min and max gold, inclusive, where it dies
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct GoldDrop {
+    pub min: i32,
+    pub max: i32
+}
+
+// a pile of gold on the floor, picked up by walking onto it
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Gold {
+    pub amount: i32
+}
+
+// how heavy an item is to carry around
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Weight {
+    pub pounds: f32
+}
+
+// stuck in place for this many tries at moving, on a web this is what it does to its victim
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Rooted {
+    pub turns: i32
+}
+
+// calls up a spawn_name next to itself every so many turns while it can see a foe,
+// cooldown counts down the turns until it can do so again
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Summoner {
+    pub spawn_name: String,
+    pub every: i32,
+    pub cooldown: i32
+}
+
+// a summoning the monster AI picked a free tile for, spawned once the systems are done
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct WantsToSummon {
+    pub spawn_name: String,
+    pub x: i32,
+    pub y: i32
+}
+
+// called up by a summoner rather than found on the level, so it carries no gold and is worth no xp
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Summoned {}
+
+// given out by the initiative system to whoever may act this turn
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct MyTurn {}
+
+// where something waits on a level the player isn't on, instead of a Position
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct OtherLevelPosition {
+    pub x: i32,
+    pub y: i32,
+    pub depth: i32
+}
+
+// what an unidentified item is shown as instead of its Name
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct ObfuscatedName {
+    pub name: String
+}
+
+// reading it identifies the kind of item the reader picked
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Identify {}
+
+// the item an Identify scroll being read is used on
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct WantsToIdentify {
+    pub item: Entity
+}
+
+// moves the user somewhere else, to the chosen tile when it also has Ranged
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct Teleport {}
+
+// reading it reveals the whole level
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct MagicMapper {}
+
+// eating this resets the eater's hunger clock
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct ProvidesFood {}
+
+// the monster runs from its enemies once its hp drops below this percentage of max_hp,
+// fleeing remembers whether it already is so the log only announces it once
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct CanFlee {
+    pub threshold_pct: i32,
+    pub fleeing: bool
+}
+
+// hurts every turn until turns runs out, announced tells whether the player
+// has already been told it's taking hold. from_player gives the player the kill
+// if it's the poison that finishes the victim off
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct DamageOverTime {
+    pub damage: i32,
+    pub turns: i32,
+    pub announced: bool,
+    #[serde(default)]
+    pub from_player: bool,
+}
+
+// heals amount every turn until turns runs out, never past max_hp
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Regeneration {
+    pub amount: i32,
+    pub turns: i32
+}
+
+// gear that keeps its wearer regenerating for as long as it's equipped
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct GrantsRegeneration {
+    pub amount: i32
+}
+
+// melee hits poison the target, either on the creature's own bite or on the weapon it wields
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Venomous {
+    pub damage: i32,
+    pub turns: i32
+}
+
+// lets a monster shoot at targets it can see instead of closing in,
+// it still prefers melee once adjacent
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct RangedAttack {
+    pub range: i32,
+    pub damage: i32
+}
+
+// gear wears down with use and breaks once current hits zero
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Durability {
+    pub current: i32,
+    pub max: i32
+}
+
+// A creature bigger than one tile, its Position is the top-left corner
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Size {
+    pub w: i32,
+    pub h: i32
+}
+
+impl Size {
+    // every tile covered with the top-left corner at (x, y)
+    pub fn tiles(&self, x: i32, y: i32) -> Vec<Point> {
+        let mut tiles = Vec::new();
+        for ty in y..y + self.h {
+            for tx in x..x + self.w {
+                tiles.push(Point::new(tx, ty));
+            }
+        }
+        tiles
+    }
+}
+
+// the tiles an entity at (x, y) takes up, just the one unless it has a Size
+pub fn footprint(size: Option<&Size>, x: i32, y: i32) -> Vec<Point> {
+    match size {
+        Some(size) => size.tiles(x, y),
+        None => vec![Point::new(x, y)],
+    }
+}
+
+// the area effect spreads through walls instead of being stopped by them, like a gas
+#[derive(Component, Debug, Serialize, Deserialize, Clone)]
+pub struct IgnoresWalls {}
+
+// hurts whoever lands a melee hit on the bearer, either on the creature
+// itself or on a piece of equipment it's wearing
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Thorns {
+    pub amount: i32
+}
+
+// A map feature the player can bump into to heal,
+// it runs dry once all uses are spent
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct Fountain {
+    pub uses: i32
+}
+
+// free-form labels like "undead" or "flammable" that systems can check for,
+// instead of a new marker component for every concept
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Tags {
+    pub set: HashSet<String>
+}
+
+impl Tags {
+    pub fn new(tags: &[&str]) -> Tags {
+        Tags { set: tags.iter().map(|tag| tag.to_string()).collect() }
+    }
+
+    pub fn has(&self, tag: &str) -> bool {
+        self.set.contains(tag)
+    }
+}
+
+pub fn has_tag(ecs: &World, entity: Entity, tag: &str) -> bool {
+    ecs.read_storage::<Tags>().get(entity).is_some_and(|tags| tags.has(tag))
+}
+
+// creatures of different factions are hostile to each other
+#[derive(Component, Debug, ConvertSaveload, Clone)]
+pub struct Faction {
+    pub name: String
+}
+
+// How the run has gone so far, for the game over screen. A resource rather than
+// a component, saved along with the map
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Stats {
+    pub monsters_slain: i32,
+    pub deepest_level: i32,
+    // tells this run's saves apart from other runs', 0 for saves from before it was kept
+    #[serde(default)]
+    pub run_id: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats{ monsters_slain: 0, deepest_level: 1, run_id: 0 }
+    }
+}
+
+impl Stats {
+    // a fresh run, with an id no earlier run has had
+    pub fn new_run() -> Stats {
+        let run_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(1, |since| since.as_nanos() as u64);
+        Stats{ run_id, ..Stats::default() }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SerializeMe;
 
 #[derive(Component, Debug, Serialize, Deserialize, Clone)]
 pub struct SerializationHelper {
     pub map: super::map::Map,
+    pub log: super::gamelog::GameLog,
+    pub turns: u64,
+    pub masks: super::identification::MagicItemMasks,
+    pub dungeon: super::master_dungeon::MasterDungeon,
+    pub stats: Stats,
 }
\ No newline at end of file
@@ -1,24 +1,148 @@
 use specs::prelude::*;
-use crate::{Name, Player, RunState, gamelog::GameLog, gui};
+use crate::{footprint, inventory_system::name_of, spawner, DamageType, Resistances, Vulnerabilities, GoldDrop, Experience, Map, Monster, Name, Player, Position, RunState, Size, Stats, Summoned, Tags, gamelog::{GameLog, LogCategory}};
 
 use super::{CombatStats, SufferDamage};
 
+// a kill is worth this much per point of the victim's max hp
+const XP_PER_MAX_HP: i32 = 20;
+// the player needs level * this to go up a level
+const XP_PER_LEVEL: i32 = 1000;
+
 pub struct DamageSystem{}
 
 impl<'a> System<'a> for DamageSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
+        Entities<'a>,
         WriteStorage<'a, CombatStats>,
         WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, Player>,
+        WriteExpect<'a, GameLog>,
+        WriteExpect<'a, Map>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Experience>,
+        ReadExpect<'a, Entity>,
+        ReadStorage<'a, Resistances>,
+        ReadStorage<'a, Vulnerabilities>,
+        ReadStorage<'a, Monster>,
+        WriteExpect<'a, Stats>,
+        ReadStorage<'a, Summoned>,
+        ReadStorage<'a, Tags>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut stats, mut damage) = data;
+        let (
+            entities,
+            mut stats,
+            mut damage,
+            names,
+            players,
+            mut log,
+            mut map,
+            positions,
+            mut experience,
+            player_entity,
+            resistances,
+            vulnerabilities,
+            monsters,
+            mut run_stats,
+            summoned,
+            tags,
+        ) = data;
+
+        let mut xp_gained = 0;
+        for (entity, stats, damage) in (&entities, &mut stats, &damage).join() {
+            let mut amount = 0;
+            for (hit, kind, _from_player) in damage.amount.iter() {
+                let effect = hit_effect(*kind, resistances.get(entity), vulnerabilities.get(entity), tags.get(entity));
+                amount += effect.scale(*hit);
+
+                let seen = positions.get(entity).is_some_and(|pos| map.visible_tiles[map.xy_idx(pos.x, pos.y)]);
+                if effect == HitEffect::Normal || !seen {
+                    continue;
+                }
+                let message = match (effect, players.get(entity).is_some()) {
+                    (HitEffect::Resisted, true) => format!("You resist the {}.", kind.noun()),
+                    (HitEffect::Resisted, false) => format!("The {} resists the {}.", name_of(&names, entity), kind.noun()),
+                    (_, true) => format!("The {} hurt you badly!", kind.noun()),
+                    (_, false) => format!("The {} is badly hurt by the {}!", name_of(&names, entity), kind.noun()),
+                };
+                log.push(LogCategory::Combat, message);
+            }
+            let was_alive = stats.hp > 0;
+            stats.hp -= amount;
 
-        for (mut stats, damage) in (&mut stats, &damage).join() {
-            stats.hp -= damage.amount.iter().sum::<i32>();
+            // the player gets the credit if any of the blows that killed it were theirs
+            let by_player = damage.amount.iter().any(|(_amount, _kind, from_player)| *from_player);
+            if was_alive && stats.hp < 1 && by_player && entity != *player_entity {
+                // a summoner could otherwise be farmed for as many minions as the player liked
+                if summoned.get(entity).is_none() {
+                    xp_gained += stats.max_hp * XP_PER_MAX_HP;
+                }
+                if monsters.get(entity).is_some() {
+                    run_stats.monsters_slain += 1;
+                }
+            }
+
+            if amount > 0 {
+                if let Some(pos) = positions.get(entity) {
+                    let idx = map.xy_idx(pos.x, pos.y);
+                    map.bloodstains.insert(idx);
+                }
+            }
         }
 
         damage.clear();
+
+        if xp_gained > 0 {
+            if let (Some(exp), Some(player_stats)) = (experience.get_mut(*player_entity), stats.get_mut(*player_entity)) {
+                exp.xp += xp_gained;
+                while exp.xp >= exp.level * XP_PER_LEVEL {
+                    exp.xp -= exp.level * XP_PER_LEVEL;
+                    exp.level += 1;
+                    player_stats.max_hp += 10;
+                    player_stats.hp = player_stats.max_hp;
+                    player_stats.power += 1;
+                    player_stats.defense += 1;
+                    log.push_colored(
+                        LogCategory::System,
+                        format!("Welcome to level {}!", exp.level),
+                        rltk::RGB::named(rltk::MAGENTA),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum HitEffect {
+    Normal,
+    Resisted,
+    Vulnerable,
+}
+
+impl HitEffect {
+    pub fn scale(self, amount: i32) -> i32 {
+        match self {
+            HitEffect::Normal => amount,
+            HitEffect::Resisted => amount / 2,
+            HitEffect::Vulnerable => amount * 2,
+        }
+    }
+}
+
+// How a hit of kind lands on its victim, being both resistant and vulnerable cancels out.
+// A flammable tag counts the same as a listed weakness to fire
+pub fn hit_effect(kind: DamageType, resistances: Option<&Resistances>, vulnerabilities: Option<&Vulnerabilities>, tags: Option<&Tags>) -> HitEffect {
+    let resists = resistances.is_some_and(|resist| resist.types.contains(&kind));
+    let vulnerable = vulnerabilities.is_some_and(|vulnerable| vulnerable.types.contains(&kind))
+        || (kind == DamageType::Fire && tags.is_some_and(|tags| tags.has("flammable")));
+    match (resists, vulnerable) {
+        (true, false) => HitEffect::Resisted,
+        (false, true) => HitEffect::Vulnerable,
+        _ => HitEffect::Normal,
     }
 }
 
@@ -36,20 +160,18 @@ pub fn delete_the_dead(ecs: &mut World) {
 
         for (entity, stats) in (&entities, &combat_stats).join() {
             if stats.hp < 1 {
-                log.entries.push("Delete the dead called".to_string());
-
                 // check if current entity is a player
                 let player = players.get(entity);
                 match player {
                     None => { // not the player, a mob
                         let victim_name = names.get(entity);
                         if let Some(victim_name) = victim_name {
-                            log.entries.push(format!("{} is dead", &victim_name.name));
+                            log.push(LogCategory::Combat, format!("{} is dead", &victim_name.name));
                         }
                         dead.push(entity);
                     }
                     Some(_) => {
-                        log.entries.push("Yee".to_string());
+                        log.push(LogCategory::Flavor, "Yee");
                         let mut runstate = ecs.write_resource::<RunState>();
                         *runstate = RunState::GameOver;
                     }
@@ -58,9 +180,136 @@ pub fn delete_the_dead(ecs: &mut World) {
         }
     }
 
+    // roll what gold the victims leave behind before they're gone
+    let mut gold_drops: Vec<(i32, i32, i32)> = Vec::new();
+    {
+        let positions = ecs.read_storage::<Position>();
+        let drops = ecs.read_storage::<GoldDrop>();
+        let mut rng = ecs.write_resource::<rltk::RandomNumberGenerator>();
+        for victim in dead.iter() {
+            if let (Some(pos), Some(drop)) = (positions.get(*victim), drops.get(*victim)) {
+                gold_drops.push((pos.x, pos.y, rng.range(drop.min, drop.max + 1)));
+            }
+        }
+    }
+
+    // the map index only catches up next turn, so free the victims' tiles now
+    // or the player can't step where something just died
+    {
+        let mut map = ecs.write_resource::<Map>();
+        let positions = ecs.read_storage::<Position>();
+        let sizes = ecs.read_storage::<Size>();
+        for victim in dead.iter() {
+            if let Some(pos) = positions.get(*victim) {
+                for tile in footprint(sizes.get(*victim), pos.x, pos.y) {
+                    let idx = map.xy_idx(tile.x, tile.y);
+                    map.blocked[idx] = false;
+                    map.tile_content[idx].retain(|entity| entity != victim);
+                }
+            }
+        }
+    }
+
     for victim in dead {
         ecs.delete_entity(victim).expect("Unable to delete");
     }
 
+    for (x, y, amount) in gold_drops {
+        spawner::gold_pile(ecs, x, y, amount);
+    }
+
+
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{damage_queued, give, logged, open_map, refresh_map, spawn_at, test_world, use_item};
+
+    // how many kills the run has on record after a goblin takes a fatal hit
+    fn slain_after_a_killing_blow(from_player: bool) -> i32 {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 11, 10, "Goblin");
+        SufferDamage::new_damage(&mut ecs.write_storage::<SufferDamage>(), goblin, 100, from_player);
+        DamageSystem {}.run_now(&ecs);
+        delete_the_dead(&mut ecs);
+        let slain = ecs.fetch::<Stats>().monsters_slain;
+        slain
+    }
+
+    #[test]
+    fn only_the_player_kills_count_toward_the_score() {
+        assert_eq!(slain_after_a_killing_blow(true), 1);
+        assert_eq!(slain_after_a_killing_blow(false), 0);
+    }
+
+    #[test]
+    fn summoned_goblins_carry_no_gold_and_give_no_xp() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        ecs.write_storage::<crate::WantsToSummon>()
+            .insert(player, crate::WantsToSummon{ spawn_name: "Goblin".to_string(), x: 11, y: 10 })
+            .unwrap();
+        spawner::summon_minions(&mut ecs);
+        let goblin = ecs.entities().join().max_by_key(|entity| entity.id()).unwrap();
+        assert!(ecs.read_storage::<Summoned>().get(goblin).is_some());
+        assert!(ecs.read_storage::<GoldDrop>().get(goblin).is_none());
 
-}
\ No newline at end of file
+        SufferDamage::new_damage(&mut ecs.write_storage::<SufferDamage>(), goblin, 100, true);
+        DamageSystem {}.run_now(&ecs);
+        delete_the_dead(&mut ecs);
+        assert_eq!(ecs.read_storage::<Experience>().get(player).unwrap().xp, 0);
+        assert_eq!(ecs.fetch::<Stats>().monsters_slain, 1);
+    }
+
+    fn hp(ecs: &World, entity: Entity) -> i32 {
+        ecs.read_storage::<CombatStats>().get(entity).unwrap().hp
+    }
+
+    #[test]
+    fn ice_golems_resist_the_frost() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let golem = spawn_at(&mut ecs, 14, 10, "Ice Golem");
+        refresh_map(&mut ecs);
+        let scroll = give(&mut ecs, player, "Frost Bolt Scroll");
+        use_item(&mut ecs, player, scroll, Some(rltk::Point::new(14, 10)));
+        assert_eq!(damage_queued(&ecs, golem), 12);
+
+        let before = hp(&ecs, golem);
+        DamageSystem {}.run_now(&ecs);
+        assert_eq!(before - hp(&ecs, golem), 6);
+        assert!(logged(&ecs, "The Ice Golem resists the frost."));
+    }
+
+    #[test]
+    fn goblins_are_badly_hurt_by_fire() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 12, 10, "Goblin");
+        refresh_map(&mut ecs);
+        let before = hp(&ecs, goblin);
+        SufferDamage::new_typed_damage(&mut ecs.write_storage::<SufferDamage>(), goblin, 3, DamageType::Fire, true);
+        SufferDamage::new_damage(&mut ecs.write_storage::<SufferDamage>(), goblin, 1, true);
+        DamageSystem {}.run_now(&ecs);
+        // the fire doubles, the blow beside it doesn't
+        assert_eq!(before - hp(&ecs, goblin), 7);
+        assert!(logged(&ecs, "The Goblin is badly hurt by the flames!"));
+    }
+
+    // what an orc loses to a hit of kind, with or without a flammable tag
+    fn orc_loses(kind: DamageType, flammable: bool) -> i32 {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let orc = spawn_at(&mut ecs, 12, 10, "Orc");
+        if flammable {
+            ecs.write_storage::<Tags>().insert(orc, Tags::new(&["flammable"])).unwrap();
+        }
+        let before = hp(&ecs, orc);
+        SufferDamage::new_typed_damage(&mut ecs.write_storage::<SufferDamage>(), orc, 3, kind, true);
+        DamageSystem {}.run_now(&ecs);
+        before - hp(&ecs, orc)
+    }
+
+    #[test]
+    fn a_flammable_tag_doubles_fire_damage() {
+        assert_eq!(orc_loses(DamageType::Fire, false), 3);
+        assert_eq!(orc_loses(DamageType::Fire, true), 6);
+        assert_eq!(orc_loses(DamageType::Physical, true), 3);
+    }
+}
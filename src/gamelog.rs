@@ -1,4 +1,86 @@
+use rltk::RGB;
+use serde::{Deserialize, Serialize};
 
+// oldest entries are dropped past this, so a long game doesn't keep every line
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum LogCategory {
+    Combat,
+    System,
+    Flavor,
+}
+
+impl LogCategory {
+    // what entries of this category are drawn in unless they ask for something else
+    pub fn color(self) -> RGB {
+        match self {
+            LogCategory::Combat => RGB::named(rltk::TOMATO),
+            LogCategory::System => RGB::named(rltk::WHITE),
+            LogCategory::Flavor => RGB::named(rltk::LIGHTGRAY),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub text: String,
+    pub category: LogCategory,
+    // the turn the entry was written on
+    pub turn: u64,
+    pub color: RGB,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GameLog {
-    pub entries: Vec<String>
-}
\ No newline at end of file
+    pub entries: Vec<LogEntry>,
+    // only entries of this category are shown in the bottom panel, None shows everything
+    pub filter: Option<LogCategory>,
+    // new entries get stamped with this, kept in step with the TurnCount resource
+    pub turn: u64,
+}
+
+impl GameLog {
+    pub fn new() -> GameLog {
+        GameLog {
+            entries: Vec::new(),
+            filter: None,
+            turn: 0,
+        }
+    }
+
+    pub fn push<S: ToString>(&mut self, category: LogCategory, text: S) {
+        self.push_colored(category, text, category.color());
+    }
+
+    // for lines that should stand out from the rest of their category
+    pub fn push_colored<S: ToString>(&mut self, category: LogCategory, text: S, color: RGB) {
+        self.entries.push(LogEntry {
+            text: text.to_string(),
+            category,
+            turn: self.turn,
+            color,
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            let excess = self.entries.len() - MAX_ENTRIES;
+            self.entries.drain(..excess);
+        }
+    }
+
+    // All -> Combat -> System -> Flavor -> All
+    pub fn cycle_filter(&mut self) {
+        self.filter = match self.filter {
+            None => Some(LogCategory::Combat),
+            Some(LogCategory::Combat) => Some(LogCategory::System),
+            Some(LogCategory::System) => Some(LogCategory::Flavor),
+            Some(LogCategory::Flavor) => None,
+        };
+    }
+
+    pub fn is_shown(&self, entry: &LogEntry) -> bool {
+        match self.filter {
+            None => true,
+            Some(category) => entry.category == category,
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use crate::{Equipped, InBackpack, Map, Name, Position, RunState, State, Viewshed, gamelog::GameLog};
-use rltk::{Console, Point, Rltk, RGB, VirtualKeyCode};
-use specs::{prelude::*, rayon::iter::Positions};
+use crate::{camera::{current_origin, map_to_screen, screen_to_map, view_origin}, AreaOfEffect, CritChance, DamageType, DefenseBonus, GrantsRegeneration, Identify, Item, LightSource, MagicMapper, MeleePowerBonus, ProvidesFood, ProvidesHaste, ProvidesHealing, Ranged, Teleport, TwoHanded, Venomous, Vulnerabilities, Confusion, Consumable, DamageOverTime, ObfuscatedName, identification::display_name, Durability, Equipped, Faction, Hidden, HungerClock, HungerState, IgnoresWalls, InBackpack, Monster, Size, footprint, has_tag, inventory_system::{area_of_effect_tiles, carried_weight, carry_capacity}, InflictsDamage, Map, Name, Position, RunState, Settings, ShattersOnThrow, State, Stats, TurnCount, Viewshed, Wallet, Weight, gamelog::{GameLog, LogEntry}};
+use rltk::{Point, Rltk, RGB, VirtualKeyCode};
+use specs::prelude::*;
 
 use super::{CombatStats, Player};
+use crate::saveload_system::{SaveSlot, SlotEntry};
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum MainMenuSelection {
@@ -28,6 +29,13 @@ pub enum GameOverResult {
     QuitToMenu
 }
 
+#[derive(PartialEq, Copy, Clone)]
+pub enum ConfirmResult {
+    NoResponse,
+    Confirmed,
+    Cancelled
+}
+
 
 pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
     let map = ecs.fetch::<Map>();
@@ -47,7 +55,9 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
     let combat_stats = ecs.read_storage::<CombatStats>();
     let players = ecs.read_storage::<Player>();
 
-    for (_player, stats) in (&players, &combat_stats).join() {
+    let damage_over_time = ecs.read_storage::<DamageOverTime>();
+
+    for (player, _p, stats) in (&ecs.entities(), &players, &combat_stats).join() {
         let health = format!("HP: {} / {} ", stats.hp, stats.max_hp);
         ctx.print_color(
             12,
@@ -59,22 +69,54 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         ctx.draw_bar_horizontal(
             28,
             43,
-            51,
+            40,
             stats.hp,
             stats.max_hp,
-            RGB::named(rltk::RED),
+            // the bar turns green while poison is eating away at it
+            if damage_over_time.get(player).is_some() { RGB::named(rltk::GREEN) } else { RGB::named(rltk::RED) },
             RGB::named(rltk::BLACK),
         );
     }
 
+    // nothing to say while the player is just normally fed
+    let hunger_clocks = ecs.read_storage::<HungerClock>();
+    for (_player, clock) in (&players, &hunger_clocks).join() {
+        match clock.state {
+            HungerState::WellFed => ctx.print_color(70, 43, RGB::named(rltk::GREEN), RGB::named(rltk::BLACK), "Well Fed"),
+            HungerState::Normal => {}
+            HungerState::Hungry => ctx.print_color(70, 43, RGB::named(rltk::ORANGE), RGB::named(rltk::BLACK), "Hungry"),
+            HungerState::Starving => ctx.print_color(70, 43, RGB::named(rltk::RED), RGB::named(rltk::BLACK), "Starving"),
+        }
+    }
+
     let log = ecs.fetch::<GameLog>();
+    let timestamps = ecs.fetch::<Settings>().log_timestamps;
 
-    let mut y = 44;
-    for s in log.entries.iter().rev() {
-        if y < 49 {
-            ctx.print(2, y, s);
-        }
-        y += 1;
+    let shown = log.entries.iter().rev().filter(|entry| log.is_shown(entry));
+    for (y, entry) in (44..49).zip(shown) {
+        ctx.print_color(2, y, entry.color, RGB::named(rltk::BLACK), log_line(entry, timestamps));
+    }
+
+    let filter = match log.filter {
+        None => "All".to_string(),
+        Some(category) => format!("{:?}", category),
+    };
+    ctx.print_color(
+        2,
+        49,
+        RGB::named(rltk::GREY),
+        RGB::named(rltk::BLACK),
+        format!("[Tab] Log: {}  [L] History", filter),
+    );
+
+    // right aligned against the box's corner, however long the run gets
+    let turn = format!("Turn: {}", ecs.fetch::<TurnCount>().0);
+    ctx.print_color(77 - turn.len() as i32, 49, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), &turn);
+
+    let wallets = ecs.read_storage::<Wallet>();
+    for (_player, wallet) in (&players, &wallets).join() {
+        let gold = format!("Gold: {}", wallet.gold);
+        ctx.print_color(75 - (turn.len() + gold.len()) as i32, 49, RGB::named(rltk::GOLD), RGB::named(rltk::BLACK), &gold);
     }
 
     let mouse_pos = ctx.mouse_pos();
@@ -83,24 +125,108 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
     draw_tooltips(ecs, ctx);
 }
 
+fn log_line(entry: &LogEntry, timestamps: bool) -> String {
+    if timestamps {
+        format!("[T{}] {}", entry.turn, entry.text)
+    } else {
+        entry.text.clone()
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum LogViewResult {
+    NoResponse,
+    Close,
+    Scroll(usize),
+}
+
+// how many log lines fit in the history view
+const LOG_PAGE: usize = 46;
+
+// the whole log over the full screen, scroll counts lines back from the newest entry
+pub fn show_log(ecs: &World, ctx: &mut Rltk, scroll: usize) -> LogViewResult {
+    let log = ecs.fetch::<GameLog>();
+    let timestamps = ecs.fetch::<Settings>().log_timestamps;
+
+    let shown: Vec<&LogEntry> = log.entries.iter().filter(|entry| log.is_shown(entry)).collect();
+    let max_scroll = shown.len().saturating_sub(LOG_PAGE);
+    let scroll = scroll.min(max_scroll);
+
+    ctx.draw_box(0, 0, 79, 49, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(2, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Message History");
+    ctx.print_color(
+        2,
+        49,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "PgUp/PgDn to scroll, ESC to close.",
+    );
+
+    // newest at the bottom, like the panel under the map
+    let end = shown.len() - scroll;
+    let start = end.saturating_sub(LOG_PAGE);
+    for (y, entry) in (2..).zip(shown[start..end].iter()) {
+        ctx.print_color(2, y, entry.color, RGB::named(rltk::BLACK), log_line(entry, timestamps));
+    }
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) | Some(VirtualKeyCode::L) => LogViewResult::Close,
+        Some(VirtualKeyCode::PageUp) => LogViewResult::Scroll((scroll + LOG_PAGE).min(max_scroll)),
+        Some(VirtualKeyCode::PageDown) => LogViewResult::Scroll(scroll.saturating_sub(LOG_PAGE)),
+        Some(VirtualKeyCode::Up) => LogViewResult::Scroll((scroll + 1).min(max_scroll)),
+        Some(VirtualKeyCode::Down) => LogViewResult::Scroll(scroll.saturating_sub(1)),
+        _ => LogViewResult::NoResponse,
+    }
+}
+
 fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
     let map = ecs.fetch::<Map>();
     let names = ecs.read_storage::<Name>();
     let positions = ecs.read_storage::<Position>();
+    let sizes = ecs.read_storage::<Size>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let hidden = ecs.read_storage::<Hidden>();
+    let obfuscated = ecs.read_storage::<ObfuscatedName>();
+    let items = ecs.read_storage::<Item>();
+    let vulnerabilities = ecs.read_storage::<Vulnerabilities>();
 
+    // the tooltip is drawn next to the mouse on screen, but describes the map tile under it
     let mouse_pos = ctx.mouse_pos();
+    let mouse_tile = match screen_to_map(current_origin(ecs), Point::new(mouse_pos.0, mouse_pos.1)) {
+        Some(tile) => tile,
+        None => return,
+    };
 
     // check out of bounds
-    if mouse_pos.0 <= 0 || mouse_pos.0 >= map.width || mouse_pos.1 <= 0 || mouse_pos.1 >= map.height
+    if mouse_tile.x <= 0 || mouse_tile.x >= map.width || mouse_tile.y <= 0 || mouse_tile.y >= map.height
     {
         return;
     }
 
     let mut tooltip: Vec<String> = Vec::new();
-    for (name, position) in (&names, &positions).join() {
-        let idx = map.xy_idx(position.x, position.y);
-        if position.x == mouse_pos.0 && position.y == mouse_pos.1 && map.visible_tiles[idx] {
-            tooltip.push(name.name.to_string());
+    for (entity, _name, position, _hidden) in (&ecs.entities(), &names, &positions, !&hidden).join() {
+        let idx = map.xy_idx(mouse_tile.x, mouse_tile.y);
+        let covers_mouse = footprint(sizes.get(entity), position.x, position.y)
+            .contains(&mouse_tile);
+        if covers_mouse && map.visible_tiles[idx] {
+            let mut label = display_name(&names, obfuscated.get(entity), entity);
+            if let Some(stats) = combat_stats.get(entity) {
+                label = format!("{} {}/{} hp", label, stats.hp, stats.max_hp);
+            }
+            // hint at what fire will do to it
+            let weak_to_fire = vulnerabilities.get(entity).is_some_and(|vulnerable| vulnerable.types.contains(&DamageType::Fire));
+            if weak_to_fire || has_tag(ecs, entity, "flammable") {
+                label = format!("{} (flammable)", label);
+            }
+            tooltip.push(label);
+
+            // items say what they do on the line below their name
+            if items.get(entity).is_some() {
+                let description = item_description(ecs, entity);
+                if !description.is_empty() {
+                    tooltip.push(description);
+                }
+            }
         }
     }
 
@@ -116,8 +242,7 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
         if mouse_pos.0 > 40 {
             let arrow_pos = Point::new(mouse_pos.0 - 2, mouse_pos.1);
             let left_x = mouse_pos.0 - width;
-            let mut y = mouse_pos.1;
-            for s in tooltip.iter() {
+            for (y, s) in (mouse_pos.1..).zip(tooltip.iter()) {
                 ctx.print_color(
                     left_x,
                     y,
@@ -132,24 +257,22 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                         y,
                         RGB::named(rltk::YELLOW),
                         RGB::named(rltk::GREY),
-                        &" ".to_string(),
+                        " ",
                     );
                 }
-                y += 1;
             }
             ctx.print_color(
                 arrow_pos.x,
                 arrow_pos.y,
                 RGB::named(rltk::YELLOW),
                 RGB::named(rltk::GREY),
-                &"->".to_string(),
+                "->",
             );
         } else {
             let arrow_pos = Point::new(mouse_pos.0 + 1, mouse_pos.1);
             let left_x = mouse_pos.0 + 3;
-            let mut y = mouse_pos.1;
 
-            for s in tooltip.iter() {
+            for (y, s) in (mouse_pos.1..).zip(tooltip.iter()) {
                 ctx.print_color(
                     left_x + 1,
                     y,
@@ -165,144 +288,414 @@ fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                         y,
                         RGB::named(rltk::YELLOW),
                         RGB::named(rltk::GREY),
-                        &" ".to_string(),
+                        " ",
                     );
                 }
-                y += 1;
             }
             ctx.print_color(
                 arrow_pos.x,
                 arrow_pos.y,
                 RGB::named(rltk::YELLOW),
                 RGB::named(rltk::GREY),
-                &"<-".to_string(),
+                "<-",
             );
         }
     }
 }
 
-#[derive(PartialEq, Copy, Clone)]
+// Blends a monster's colour from green (barely scratches the player) to
+// red (a hit takes a fifth or more of the player's max hp)
+pub fn threat_color(base: RGB, monster: &CombatStats, player: &CombatStats, player_defense_bonus: i32) -> RGB {
+    let hit = i32::max(0, monster.power - (player.defense + player_defense_bonus));
+    let hard_hit = f32::max(1.0, player.max_hp as f32 * 0.2);
+    let threat = f32::min(1.0, hit as f32 / hard_hit);
+
+    let tint = RGB::named(rltk::GREEN).lerp(RGB::named(rltk::RED), threat);
+    base.lerp(tint, 0.75)
+}
+
+// A short bar over every hurt monster in view, green at full health to red near death.
+// It runs one tile past the creature on each side and is cut off at the map edges,
+// leaving gaps where it would cover something
+pub fn draw_entity_health_bars(ecs: &World, ctx: &mut Rltk) {
+    let map = ecs.fetch::<Map>();
+    let monsters = ecs.read_storage::<Monster>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let positions = ecs.read_storage::<Position>();
+    let sizes = ecs.read_storage::<Size>();
+    let origin = current_origin(ecs);
+
+    for (entity, _monster, stats, pos) in (&ecs.entities(), &monsters, &combat_stats, &positions).join() {
+        if stats.hp >= stats.max_hp || stats.max_hp < 1 {
+            continue;
+        }
+        let visible = footprint(sizes.get(entity), pos.x, pos.y)
+            .iter()
+            .any(|tile| map.visible_tiles[map.xy_idx(tile.x, tile.y)]);
+        if !visible {
+            continue;
+        }
+
+        let width = sizes.get(entity).map_or(1, |size| size.w) + 2;
+        let fraction = f32::max(0.0, stats.hp as f32 / stats.max_hp as f32);
+        let filled = (fraction * width as f32).ceil() as i32;
+        let color = RGB::named(rltk::RED).lerp(RGB::named(rltk::GREEN), fraction);
+        let y = i32::max(0, pos.y - 1);
+
+        for i in 0..width {
+            let x = pos.x - 1 + i;
+            if x < 0 || x >= map.width {
+                continue;
+            }
+            // walls, doors and anything standing or lying there stay visible, the bar goes round them
+            let idx = map.xy_idx(x, y);
+            if map.is_impassable(idx) || !map.tile_content[idx].is_empty() {
+                continue;
+            }
+            let fg = if i < filled { color } else { RGB::named(rltk::DARK_GRAY) };
+            if let Some(screen) = map_to_screen(origin, Point::new(x, y)) {
+                ctx.set(screen.x, screen.y, fg, RGB::named(rltk::BLACK), rltk::to_cp437('─'));
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum ItemMenuResult {
     Cancel,
     NoResponse,
     Selected,
 }
 
-pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
-    let player_entity = gs.ecs.fetch::<Entity>();
-    let names = gs.ecs.read_storage::<Name>();
-    let backpack = gs.ecs.read_storage::<InBackpack>();
-    let entities = gs.ecs.entities();
+// an item's name as listed in menus, with how worn it is for gear that can break
+fn item_label(ecs: &World, item: Entity, name: &str) -> String {
+    match ecs.read_storage::<Durability>().get(item) {
+        Some(durability) => format!("{} ({}/{})", name, durability.current, durability.max),
+        None => name.to_string(),
+    }
+}
 
-    // only show items that are owned by the player
-    let inventory = (&backpack, &names)
+// What an item does in a few words, like "Deals 20 fire damage (radius 3, range 6)".
+// Unidentified items give nothing away, an empty string means there's nothing to say
+pub fn item_description(ecs: &World, item: Entity) -> String {
+    if ecs.read_storage::<ObfuscatedName>().get(item).is_some() {
+        return String::new();
+    }
+
+    let mut effects: Vec<String> = Vec::new();
+    if let Some(healing) = ecs.read_storage::<ProvidesHealing>().get(item) {
+        effects.push(format!("Heals {} HP", healing.heal_amount));
+    }
+    if let Some(damage) = ecs.read_storage::<InflictsDamage>().get(item) {
+        let kind = match damage.kind {
+            DamageType::Physical => "",
+            DamageType::Fire => "fire ",
+            DamageType::Cold => "cold ",
+            DamageType::Poison => "poison ",
+        };
+        effects.push(format!("Deals {} {}damage", damage.damage, kind));
+    }
+    if let Some(confusion) = ecs.read_storage::<Confusion>().get(item) {
+        effects.push(format!("Confuses for {} turns", confusion.turns));
+    }
+    if let Some(haste) = ecs.read_storage::<ProvidesHaste>().get(item) {
+        effects.push(format!("Hastes for {} turns", haste.turns));
+    }
+    if ecs.read_storage::<ProvidesFood>().get(item).is_some() {
+        effects.push("Satisfies hunger".to_string());
+    }
+    if ecs.read_storage::<Teleport>().get(item).is_some() {
+        effects.push("Teleports the reader".to_string());
+    }
+    if ecs.read_storage::<MagicMapper>().get(item).is_some() {
+        effects.push("Reveals the level".to_string());
+    }
+    if ecs.read_storage::<Identify>().get(item).is_some() {
+        effects.push("Identifies an item".to_string());
+    }
+    if let Some(bonus) = ecs.read_storage::<MeleePowerBonus>().get(item) {
+        effects.push(format!("+{} melee power", bonus.power));
+    }
+    if let Some(crit) = ecs.read_storage::<CritChance>().get(item) {
+        effects.push(format!("+{}% critical chance", crit.percent));
+    }
+    if let Some(bonus) = ecs.read_storage::<DefenseBonus>().get(item) {
+        effects.push(format!("+{} defense", bonus.defense));
+    }
+    if let Some(venom) = ecs.read_storage::<Venomous>().get(item) {
+        effects.push(format!("Poisons for {} a turn over {} turns", venom.damage, venom.turns));
+    }
+    if let Some(regen) = ecs.read_storage::<GrantsRegeneration>().get(item) {
+        effects.push(format!("Regenerates {} HP a turn", regen.amount));
+    }
+    if let Some(light) = ecs.read_storage::<LightSource>().get(item) {
+        effects.push(format!("Lights radius {}", light.radius));
+    }
+    if ecs.read_storage::<TwoHanded>().get(item).is_some() {
+        effects.push("Two-handed".to_string());
+    }
+
+    let mut reach: Vec<String> = Vec::new();
+    if let Some(area) = ecs.read_storage::<AreaOfEffect>().get(item) {
+        reach.push(format!("radius {}", area.radius));
+    } else if let Some(throwable) = ecs.read_storage::<ShattersOnThrow>().get(item) {
+        reach.push(format!("radius {} when thrown", throwable.radius));
+    }
+    if let Some(ranged) = ecs.read_storage::<Ranged>().get(item) {
+        reach.push(format!("range {}", ranged.range));
+    }
+
+    let description = effects.join(", ");
+    if reach.is_empty() {
+        description
+    } else {
+        format!("{} ({})", description, reach.join(", ")).trim_start().to_string()
+    }
+}
+
+// The player's backpack as menu lines, identical consumables share one line like
+// "Health Potion (x5)" and stand for whichever of them comes first. Anything else,
+// like gear that wears down, gets a line of its own
+fn stacked_backpack(ecs: &World) -> Vec<(Entity, String)> {
+    let player_entity = ecs.fetch::<Entity>();
+    let names = ecs.read_storage::<Name>();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let consumables = ecs.read_storage::<Consumable>();
+    let obfuscated = ecs.read_storage::<ObfuscatedName>();
+    let entities = ecs.entities();
+
+    let mut stacks: Vec<(Entity, String, i32)> = Vec::new();
+    for (entity, _pack) in (&entities, &backpack)
         .join()
-        .filter(|item| item.0.owner == *player_entity);
-    let count = inventory.count();
+        .filter(|item| item.1.owner == *player_entity)
+    {
+        let name = display_name(&names, obfuscated.get(entity), entity);
+        let stackable = consumables.get(entity).is_some();
+        match stacks.iter_mut().find(|stack| stackable && stack.1 == name && consumables.get(stack.0).is_some()) {
+            Some(stack) => stack.2 += 1,
+            None => stacks.push((entity, name, 1)),
+        }
+    }
+
+    stacks
+        .into_iter()
+        .map(|(entity, name, count)| {
+            let label = item_label(ecs, entity, &name);
+            if count > 1 {
+                (entity, format!("{} (x{})", label, count))
+            } else {
+                (entity, label)
+            }
+        })
+        .collect()
+}
+
+// shown instead of an empty list, only Escape gets out of it
+fn empty_backpack_menu(ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+    ctx.draw_box(15, 23, 31, 3, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(18, 24, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "Your backpack is empty.");
+    ctx.print_color(18, 26, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel.");
+
+    pick_item(ctx.key, &[])
+}
+
+// what key does in a menu listing items from a down, letters past the end do nothing
+fn pick_item(key: Option<VirtualKeyCode>, items: &[(Entity, String)]) -> (ItemMenuResult, Option<Entity>) {
+    match key {
+        None => (ItemMenuResult::NoResponse, None),
+        Some(VirtualKeyCode::Escape) => (ItemMenuResult::Cancel, None),
+        Some(key) => {
+            let selection = rltk::letter_to_option(key);
+            if selection > -1 && selection < items.len() as i32 {
+                return (ItemMenuResult::Selected, Some(items[selection as usize].0));
+            }
+            (ItemMenuResult::NoResponse, None)
+        }
+    }
+}
+
+pub fn show_inventory(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+    let inventory = stacked_backpack(&gs.ecs);
+    let count = inventory.len();
+    if count == 0 {
+        return empty_backpack_menu(ctx);
+    }
 
+    // what each item does is listed next to it, the box widens and moves left to make room
+    let descriptions: Vec<String> = inventory.iter().map(|(entity, _label)| item_description(&gs.ecs, *entity)).collect();
+    let label_width = inventory.iter().map(|(_entity, label)| label.len()).max().unwrap_or(0) as i32;
+    let description_width = descriptions.iter().map(|description| description.len()).max().unwrap_or(0) as i32;
+    let width = if description_width > 0 { i32::min(78, label_width + description_width + 9) } else { 31 };
+    let width = i32::max(31, width);
+    let x = i32::min(15, 79 - width);
 
     // draw an appropriately sized box for the inventory
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(
-        15,
+        x,
         y - 2,
-        31,
+        width,
         (count + 3) as i32,
         RGB::named(rltk::WHITE),
         RGB::named(rltk::BLACK),
     );
     ctx.print_color(
-        18,
-        -2,
+        x + 3,
+        y - 2,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
         "Inventory",
     );
     ctx.print_color(
-        18,
+        x + 3,
         y + count as i32 + 1,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
         "ESC to cancel.",
     );
 
-    let mut equippable: Vec<Entity> = Vec::new();
+    // how much is carried against how much could be
+    {
+        let player_entity = gs.ecs.fetch::<Entity>();
+        let combat_stats = gs.ecs.read_storage::<CombatStats>();
+        if let Some(stats) = combat_stats.get(*player_entity) {
+            let load = format!(
+                "{:.1}/{:.0} lbs",
+                carried_weight(
+                    *player_entity,
+                    &gs.ecs.read_storage::<Weight>(),
+                    &gs.ecs.read_storage::<InBackpack>(),
+                    &gs.ecs.read_storage::<Equipped>(),
+                ),
+                carry_capacity(stats)
+            );
+            ctx.print_color(x + width - 1 - load.len() as i32, y + count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), &load);
+        }
+    }
 
     // draw the inventory contents
-    let mut j = 0;
-    for (entity, _pack, name) in (&entities, &backpack, &names)
+    let description_x = x + label_width + 8;
+    for (j, (_entity, label)) in inventory.iter().enumerate() {
+        ctx.set(x + 2, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(x + 3, y + j as i32, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
+        ctx.set(x + 4, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+
+        ctx.print(x + 6, y + j as i32, label);
+        let room = i32::max(0, x + width - description_x) as usize;
+        let description: String = descriptions[j].chars().take(room).collect();
+        ctx.print_color(description_x, y + j as i32, RGB::named(rltk::GREY), RGB::named(rltk::BLACK), &description);
+    }
+
+    pick_item(ctx.key, &inventory)
+}
+
+pub fn show_drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+    let inventory = stacked_backpack(&gs.ecs);
+    let count = inventory.len();
+    if count == 0 {
+        return empty_backpack_menu(ctx);
+    }
+
+    let y = (25 - (count / 2)) as i32;
+    ctx.draw_box(15, y-2, 31, (count + 3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Drop which item?");
+    ctx.print_color(18, y + count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel");
+
+    for (j, (_entity, label)) in inventory.iter().enumerate() {
+        ctx.set(17, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        // show letters a-z between each item
+        ctx.set(18, y + j as i32, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97 + j as rltk::FontCharType);
+        ctx.set(19, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+
+        ctx.print(21, y + j as i32, label);
+    }
+
+    // only the one item of the stack goes
+    pick_item(ctx.key, &inventory)
+}
+
+
+// picks the backpack item an Identify scroll is read on, only ones still unknown are offered
+pub fn show_identify_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+    let player_entity = gs.ecs.fetch::<Entity>();
+    let backpack = gs.ecs.read_storage::<InBackpack>();
+    let obfuscated = gs.ecs.read_storage::<ObfuscatedName>();
+    let entities = gs.ecs.entities();
+
+    let unknown: Vec<(Entity, String)> = (&entities, &backpack, &obfuscated)
         .join()
         .filter(|item| item.1.owner == *player_entity)
-    {
-        ctx.set(17, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
-        ctx.set(18, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
-        ctx.set(19, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+        .map(|(entity, _pack, mask)| (entity, mask.name.clone()))
+        .collect();
+    let count = unknown.len();
 
-        ctx.print(21, y, &name.name.to_string());
-        equippable.push(entity);
-        y += 1;
-        j += 1;
+    let y = (25 - (count / 2)) as i32;
+    ctx.draw_box(15, y-2, 31, (count + 3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Identify which item?");
+    ctx.print_color(18, y + count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel");
+
+    for (j, (_entity, look)) in unknown.iter().enumerate() {
+        ctx.set(17, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(18, y + j as i32, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97 + j as rltk::FontCharType);
+        ctx.set(19, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+
+        ctx.print(21, y + j as i32, look);
     }
 
     match ctx.key {
         None => (ItemMenuResult::NoResponse, None),
+        Some(VirtualKeyCode::Escape) => (ItemMenuResult::Cancel, None),
         Some(key) => {
-            match key {
-                rltk::VirtualKeyCode::Escape => (ItemMenuResult::Cancel, None),
-                _ => {
-                    let selection = rltk::letter_to_option(key);
-                    if selection > -1 && selection < count as i32 {
-                        return (ItemMenuResult::Selected, Some(equippable[selection as usize]));
-                    }
-                    (ItemMenuResult::NoResponse, None)
-                }
+            let selection = rltk::letter_to_option(key);
+            if selection > -1 && selection < count as i32 {
+                return (ItemMenuResult::Selected, Some(unknown[selection as usize].0));
             }
+            (ItemMenuResult::NoResponse, None)
         }
     }
 }
 
-pub fn show_drop_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
+
+pub fn show_throw_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Option<Entity>) {
     let player_entity = gs.ecs.fetch::<Entity>();
     let names = gs.ecs.read_storage::<Name>();
     let backpack = gs.ecs.read_storage::<InBackpack>();
+    let throwables = gs.ecs.read_storage::<ShattersOnThrow>();
+    let obfuscated = gs.ecs.read_storage::<ObfuscatedName>();
     let entities = gs.ecs.entities();
 
-    // filter to only get the player-owned items
-    let inventory = (&backpack, &names).join().filter(
+    // only the player's potions that shatter can be thrown
+    let inventory = (&backpack, &names, &throwables).join().filter(
         |item| item.0.owner == *player_entity
     );
     let count = inventory.count();
 
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(15, y-2, 31, (count + 3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
-    ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Drop which item?");
-    ctx.print_color(18, y+count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel");
+    ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Throw which item?");
+    ctx.print_color(18, y + count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel");
 
-    let mut equippable: Vec<Entity> = Vec::new();
-    let mut j = 0;
-    for (entity, _pack, name) in (&entities, &backpack, &names).join().filter(
+    let mut throwable: Vec<Entity> = Vec::new();
+    for (j, (entity, _pack, _throwable)) in (&entities, &backpack, &throwables).join().filter(
         |item| item.1.owner == *player_entity
-    ) {
-        ctx.set(17, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
-        // show letters a-z between each item
-        ctx.set(18, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97 + j as rltk::FontCharType);
-        ctx.set(19, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+    ).enumerate() {
+        ctx.set(17, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(18, y + j as i32, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97 + j as rltk::FontCharType);
+        ctx.set(19, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
 
-        ctx.print(21, y, &name.name.to_string());
-        equippable.push(entity);
-        y += 1;
-        j += 1;
+        ctx.print(21, y + j as i32, display_name(&names, obfuscated.get(entity), entity));
+        throwable.push(entity);
     }
 
     match ctx.key {
         None => (ItemMenuResult::NoResponse, None),
         Some(key) => {
             match key {
-                rltk::VirtualKeyCode::Escape => (ItemMenuResult::Cancel, None),
+                VirtualKeyCode::Escape => (ItemMenuResult::Cancel, None),
                 _ => {
                     let selection = rltk::letter_to_option(key);
                     if selection > -1 && selection < count as i32 {
-                        return (ItemMenuResult::Selected, Some(equippable[selection as usize]));
+                        return (ItemMenuResult::Selected, Some(throwable[selection as usize]));
                     }
                     (ItemMenuResult::NoResponse, None)
                 }
@@ -323,24 +716,21 @@ pub fn remove_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Opti
     );
     let count = inventory.count();
 
-    let mut y = (25 - (count / 2)) as i32;
+    let y = (25 - (count / 2)) as i32;
     ctx.draw_box(15, y-2, 31, (count+3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
     ctx.print_color(18, y-2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Remove Which Item?");
     ctx.print_color(18, y + count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel.");
 
     let mut equippable: Vec<Entity> = Vec::new();
-    let mut j = 0;
-    for (entity, _pack, name) in (&entities, &equipped, &names).join().filter(
+    for (j, (entity, _pack, name)) in (&entities, &equipped, &names).join().filter(
         |item| item.1.owner == *player_entity
-    ) {
-        ctx.set(17, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
-        ctx.set(18, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
-        ctx.set(19, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+    ).enumerate() {
+        ctx.set(17, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(18, y + j as i32, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
+        ctx.set(19, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
 
-        ctx.print(21, y, &name.name.to_string());
+        ctx.print(21, y + j as i32, item_label(&gs.ecs, entity, &name.name));
         equippable.push(entity);
-        y += 1;
-        j += 1;
     }
 
     match ctx.key {
@@ -363,13 +753,52 @@ pub fn remove_item_menu(gs: &mut State, ctx: &mut Rltk) -> (ItemMenuResult, Opti
 }
 
 
-pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32) -> (ItemMenuResult, Option<Point>) {
+// tints a map tile's background, wherever the camera has put it on screen
+fn set_map_bg(ctx: &mut Rltk, origin: Point, tile: Point, color: RGB) {
+    if let Some(screen) = map_to_screen(origin, tile) {
+        ctx.set_bg(screen.x, screen.y, color);
+    }
+}
+
+pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32, item: Entity) -> (ItemMenuResult, Option<Point>) {
 
     let player_entity = gs.ecs.fetch::<Entity>();
     let player_pos = gs.ecs.fetch::<Point>();
     let viewsheds = gs.ecs.read_storage::<Viewshed>();
-
-    ctx.print_color(5, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::YELLOW), "Select target:");
+    let map = gs.ecs.fetch::<Map>();
+    let aoe = gs.ecs.read_storage::<AreaOfEffect>();
+    let throwables = gs.ecs.read_storage::<ShattersOnThrow>();
+    let factions = gs.ecs.read_storage::<Faction>();
+    let combat_stats = gs.ecs.read_storage::<CombatStats>();
+    let inflicts_damage = gs.ecs.read_storage::<InflictsDamage>();
+    let confusion = gs.ecs.read_storage::<Confusion>();
+    let ignores_walls = gs.ecs.read_storage::<IgnoresWalls>();
+    let origin = view_origin(&map, *player_pos);
+
+    let offensive = inflicts_damage.get(item).is_some() || confusion.get(item).is_some();
+    // creatures on a tile, split into whether they're on the player's side
+    let creatures_at = |tile: &Point| -> Vec<bool> {
+        let idx = map.xy_idx(tile.x, tile.y);
+        map.tile_content[idx]
+            .iter()
+            .filter(|entity| **entity != *player_entity && combat_stats.get(**entity).is_some())
+            .map(|entity| match (factions.get(*player_entity), factions.get(*entity)) {
+                (Some(own), Some(theirs)) => own.name == theirs.name,
+                _ => false,
+            })
+            .collect()
+    };
+
+    // how far the effect splashes around the chosen tile, if at all
+    let splash_radius = match aoe.get(item) {
+        Some(area_effect) => Some(area_effect.radius),
+        None => throwables.get(item).map(|throwable| throwable.radius),
+    };
+
+    // remind the player what they're about to use
+    let item_name = display_name(&gs.ecs.read_storage::<Name>(), gs.ecs.read_storage::<ObfuscatedName>().get(item), item);
+    let header = format!("Aiming: {} (range {})", item_name, range);
+    ctx.print_color(5, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), &header);
 
     // highlight targetable cells
     let mut targetable_cells = Vec::new();
@@ -379,7 +808,21 @@ pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32) -> (ItemMenuRes
         for idx in visible.visible_tiles.iter() {
             let distance = rltk::DistanceAlg::Pythagoras.distance2d(*player_pos, *idx);
             if distance <= range as f32 {
-                ctx.set_bg(idx.x, idx.y, RGB::named(rltk::BLUE));
+                // seen around a corner doesn't mean there's a clear shot
+                if !map.has_line_of_sight(*player_pos, *idx) {
+                    set_map_bg(ctx, origin, *idx, RGB::named(rltk::RED) * 0.5);
+                    continue;
+                }
+                // allies and enemies in range stand out from the empty cells
+                let creatures = creatures_at(idx);
+                let color = if creatures.contains(&true) {
+                    rltk::DARK_GREEN
+                } else if creatures.contains(&false) {
+                    rltk::DARK_RED
+                } else {
+                    rltk::BLUE
+                };
+                set_map_bg(ctx, origin, *idx, RGB::named(color));
                 targetable_cells.push(idx);
             }
         }
@@ -388,16 +831,26 @@ pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32) -> (ItemMenuRes
     }
 
     let mouse_pos = ctx.mouse_pos();
-    let mut valid_target = false;
-    for idx in targetable_cells.iter() {
-        if idx.x == mouse_pos.0 && idx.y == mouse_pos.1 {
-            valid_target = true;
+    let mouse_tile = screen_to_map(origin, Point::new(mouse_pos.0, mouse_pos.1));
+    let target = mouse_tile.filter(|tile| targetable_cells.contains(&tile));
+    if let Some(target) = target {
+        let mut affected = vec![target];
+        if let Some(radius) = splash_radius {
+            let splash = area_of_effect_tiles(&map, target, radius, ignores_walls.get(item).is_some());
+            // a faint wash over the blast, brighter where something would get caught in it
+            for tile in splash.iter() {
+                let shade = if creatures_at(tile).is_empty() { 0.4 } else { 1.0 };
+                set_map_bg(ctx, origin, *tile, RGB::named(rltk::RED) * shade);
+            }
+            affected = splash;
+        }
+
+        if offensive && affected.iter().any(|tile| creatures_at(tile).contains(&true)) {
+            ctx.print_color(7 + header.len() as i32, 0, RGB::named(rltk::RED), RGB::named(rltk::BLACK), "Careful, that would hit an ally!");
         }
-    }
-    if valid_target {
         ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::CYAN));
         if ctx.left_click {
-            return (ItemMenuResult::Selected, Some(Point::new(mouse_pos.0, mouse_pos.1)));
+            return (ItemMenuResult::Selected, Some(target));
         }
     } else {
         ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::RED));
@@ -410,11 +863,70 @@ pub fn ranged_target(gs: &mut State, ctx: &mut Rltk, range: i32) -> (ItemMenuRes
 }
 
 
-pub fn game_over(ctx: &mut Rltk) -> GameOverResult {
+// Lists the save slots with the depth and level stored in each. Saving offers every
+// manual slot, loading only the ones with something readable in them plus the autosave
+pub fn save_slot_menu(ctx: &mut Rltk, saving: bool, listing: &[SlotEntry]) -> (ItemMenuResult, Option<SaveSlot>) {
+    let slots: Vec<&SlotEntry> = listing
+        .iter()
+        .filter(|(slot, _summary)| !saving || *slot != SaveSlot::Autosave)
+        .collect();
+    let count = slots.len();
+
+    let y = (25 - (count / 2)) as i32;
+    ctx.draw_box(15, y - 2, 40, (count + 3) as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(18, y - 2, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), if saving { "Save to which slot?" } else { "Load which slot?" });
+    ctx.print_color(18, y + count as i32 + 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESC to cancel.");
+
+    let mut loadable: Vec<bool> = Vec::new();
+    for (j, (slot, summary)) in slots.iter().enumerate() {
+        let title = match slot {
+            SaveSlot::Manual(number) => format!("Slot {}", number + 1),
+            SaveSlot::Autosave => "Autosave".to_string(),
+        };
+        let (label, fg, readable) = match summary {
+            None => (format!("{}: empty", title), RGB::named(rltk::GRAY), false),
+            Some(Ok(summary)) => (
+                format!("{}: depth {}, level {}", title, summary.depth, summary.level),
+                RGB::named(rltk::WHITE),
+                true,
+            ),
+            Some(Err(error)) => (format!("{}: {}", title, error.message()), RGB::named(rltk::RED), false),
+        };
+        loadable.push(readable);
+
+        ctx.set(17, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437('('));
+        ctx.set(18, y + j as i32, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), 97+j as rltk::FontCharType);
+        ctx.set(19, y + j as i32, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), rltk::to_cp437(')'));
+        ctx.print_color(21, y + j as i32, fg, RGB::named(rltk::BLACK), &label);
+    }
+
+    match ctx.key {
+        None => (ItemMenuResult::NoResponse, None),
+        Some(VirtualKeyCode::Escape) => (ItemMenuResult::Cancel, None),
+        Some(key) => {
+            let selection = rltk::letter_to_option(key);
+            if selection > -1 && selection < count as i32 && (saving || loadable[selection as usize]) {
+                return (ItemMenuResult::Selected, Some(slots[selection as usize].0));
+            }
+            (ItemMenuResult::NoResponse, None)
+        }
+    }
+}
+
+
+pub fn game_over(gs: &State, ctx: &mut Rltk) -> GameOverResult {
+    let stats = gs.ecs.fetch::<Stats>();
+    let turns = gs.ecs.fetch::<TurnCount>().0;
+    let players = gs.ecs.read_storage::<Player>();
+    let wallets = gs.ecs.read_storage::<Wallet>();
+    let gold = (&players, &wallets).join().map(|(_player, wallet)| wallet.gold).next().unwrap_or(0);
+
     ctx.print_color_centered(15,RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Your journey has ended!");
-    ctx.print_color_centered(17, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "One day, we'll tell you all about how you did.");
-    ctx.print_color_centered(18, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "That day, sadly, is not here yet...");
-    ctx.print_color_centered(20, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), "Press any key to return to the menu.");
+    ctx.print_color_centered(17, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), format!("You reached level {} of the dungeon.", stats.deepest_level));
+    ctx.print_color_centered(18, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), format!("You survived for {} turns.", turns));
+    ctx.print_color_centered(19, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), format!("You slew {} monsters.", stats.monsters_slain));
+    ctx.print_color_centered(20, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), format!("You gathered {} gold.", gold));
+    ctx.print_color_centered(22, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), "Press any key to return to the menu.");
 
     match ctx.key {
         None => GameOverResult::NoSelection,
@@ -423,6 +935,34 @@ pub fn game_over(ctx: &mut Rltk) -> GameOverResult {
 }
 
 
+// asks before throwing away an unsaved run, only an explicit answer closes it
+pub fn confirm_quit(ctx: &mut Rltk) -> ConfirmResult {
+    ctx.draw_box(18, 18, 44, 4, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color_centered(19, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Unsaved progress will be lost. Continue?");
+    ctx.print_color_centered(21, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "(Y)es / (N)o");
+
+    match ctx.key {
+        Some(VirtualKeyCode::Y) => ConfirmResult::Confirmed,
+        Some(VirtualKeyCode::N) | Some(VirtualKeyCode::Escape) => ConfirmResult::Cancelled,
+        _ => ConfirmResult::NoResponse
+    }
+}
+
+
+// taking the stairs up from the first level ends the run
+pub fn confirm_leave(ctx: &mut Rltk) -> ConfirmResult {
+    ctx.draw_box(18, 18, 44, 4, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color_centered(19, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Leave the dungeon?");
+    ctx.print_color_centered(21, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "(Y)es / (N)o");
+
+    match ctx.key {
+        Some(VirtualKeyCode::Y) => ConfirmResult::Confirmed,
+        Some(VirtualKeyCode::N) | Some(VirtualKeyCode::Escape) => ConfirmResult::Cancelled,
+        _ => ConfirmResult::NoResponse
+    }
+}
+
+
 pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
     let save_exists = super::saveload_system::save_exists();
     let runstate = gs.ecs.fetch::<RunState>();
@@ -451,27 +991,29 @@ pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
             ctx.print_color_centered(26, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "Quit");
         }
 
+        if let Some(error) = gs.ecs.fetch::<super::saveload_system::LastLoadError>().0 {
+            ctx.print_color_centered(28, RGB::named(rltk::RED), RGB::named(rltk::BLACK), error.message());
+        }
+
         match ctx.key {
             None => return MainMenuResult::NoSelection{selected: selection},
             Some(key) => {
                 match key {
                     VirtualKeyCode::Escape => {return MainMenuResult::NoSelection{selected: selection}},
                     VirtualKeyCode::Up => {
-                        let new_selection;
-                        match selection {
-                            MainMenuSelection::NewGame => new_selection = MainMenuSelection::Quit,
-                            MainMenuSelection::LoadGame => new_selection = MainMenuSelection::NewGame,
-                            MainMenuSelection::Quit => new_selection = MainMenuSelection::LoadGame,
-                        }
+                        let new_selection = match selection {
+                            MainMenuSelection::NewGame => MainMenuSelection::Quit,
+                            MainMenuSelection::LoadGame => MainMenuSelection::NewGame,
+                            MainMenuSelection::Quit => MainMenuSelection::LoadGame,
+                        };
                         return MainMenuResult::NoSelection{selected: new_selection}
                     }
                     VirtualKeyCode::Down => {
-                        let new_selection;
-                        match selection {
-                            MainMenuSelection::NewGame => new_selection = MainMenuSelection::LoadGame,
-                            MainMenuSelection::LoadGame => new_selection = MainMenuSelection::Quit,
-                            MainMenuSelection::Quit => new_selection = MainMenuSelection::NewGame,
-                        }
+                        let new_selection = match selection {
+                            MainMenuSelection::NewGame => MainMenuSelection::LoadGame,
+                            MainMenuSelection::LoadGame => MainMenuSelection::Quit,
+                            MainMenuSelection::Quit => MainMenuSelection::NewGame,
+                        };
                         return MainMenuResult::NoSelection{selected: new_selection}
                     }
                     VirtualKeyCode::Return => return MainMenuResult::Selected{selected: selection},
@@ -482,4 +1024,51 @@ pub fn main_menu(gs: &mut State, ctx: &mut Rltk) -> MainMenuResult {
     }
 
     MainMenuResult::NoSelection{selected: MainMenuSelection::NewGame}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{give, open_map, test_world};
+
+    #[test]
+    fn empty_backpack_only_lets_you_leave() {
+        let (ecs, _player) = test_world(open_map(), 10, 10);
+        let inventory = stacked_backpack(&ecs);
+
+        assert!(inventory.is_empty());
+        assert_eq!(pick_item(Some(VirtualKeyCode::A), &inventory), (ItemMenuResult::NoResponse, None));
+        assert_eq!(pick_item(Some(VirtualKeyCode::Escape), &inventory), (ItemMenuResult::Cancel, None));
+    }
+
+    #[test]
+    fn letters_pick_items_in_order() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let dagger = give(&mut ecs, player, "Dagger");
+        let inventory = stacked_backpack(&ecs);
+        assert_eq!(pick_item(Some(VirtualKeyCode::A), &inventory), (ItemMenuResult::Selected, Some(dagger)));
+        assert_eq!(pick_item(Some(VirtualKeyCode::B), &inventory), (ItemMenuResult::NoResponse, None));
+
+        // potions stack onto one letter, so the shield comes right after them
+        give(&mut ecs, player, "Health Potion");
+        give(&mut ecs, player, "Health Potion");
+        let shield = give(&mut ecs, player, "Shield");
+        let inventory = stacked_backpack(&ecs);
+        assert_eq!(inventory.len(), 3);
+        assert_eq!(pick_item(Some(VirtualKeyCode::C), &inventory), (ItemMenuResult::Selected, Some(shield)));
+    }
+
+    #[test]
+    fn descriptions_sum_up_what_items_do() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let fireball = give(&mut ecs, player, "Fireball Scroll");
+        let dagger = give(&mut ecs, player, "Dagger");
+        let potion = give(&mut ecs, player, "Health Potion");
+
+        // an unidentified potion gives nothing away
+        assert_eq!(item_description(&ecs, potion), "");
+        ecs.write_storage::<ObfuscatedName>().remove(potion);
+        assert!(item_description(&ecs, potion).starts_with("Heals "));
+        assert_eq!(item_description(&ecs, fireball), "Deals 20 fire damage (radius 3, range 6)");
+        assert_eq!(item_description(&ecs, dagger), "+2 melee power, +10% critical chance");
+    }
+}
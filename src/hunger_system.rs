@@ -0,0 +1,73 @@
+use specs::prelude::*;
+
+use crate::{gamelog::{GameLog, LogCategory}, HungerClock, HungerState, OtherLevelPosition, Player, RunState, SufferDamage};
+
+// how many turns each hunger state lasts before getting worse, starving lasts until you eat
+pub const WELL_FED_TURNS: i32 = 20;
+const NORMAL_TURNS: i32 = 200;
+const HUNGRY_TURNS: i32 = 200;
+
+pub struct HungerSystem {}
+
+impl<'a> System<'a> for HungerSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, HungerClock>,
+        ReadStorage<'a, Player>,
+        ReadExpect<'a, RunState>,
+        WriteStorage<'a, SufferDamage>,
+        WriteExpect<'a, GameLog>,
+        ReadStorage<'a, OtherLevelPosition>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut hunger_clocks, players, runstate, mut inflict_damage, mut log, away) = data;
+
+        // resting runs the systems once as Resting for each turn spent
+        let new_turn = matches!(*runstate, RunState::PlayerTurn | RunState::Resting { .. });
+        if !new_turn {
+            return;
+        }
+
+        // nobody gets hungrier on a level the player isn't on
+        for (entity, clock, _here) in (&entities, &mut hunger_clocks, !&away).join() {
+            let is_player = players.get(entity).is_some();
+
+            clock.duration -= 1;
+            if clock.duration > 0 {
+                continue;
+            }
+
+            match clock.state {
+                HungerState::WellFed => {
+                    clock.state = HungerState::Normal;
+                    clock.duration = NORMAL_TURNS;
+                    if is_player {
+                        log.push(LogCategory::System, "You are no longer well fed.");
+                    }
+                }
+                HungerState::Normal => {
+                    clock.state = HungerState::Hungry;
+                    clock.duration = HUNGRY_TURNS;
+                    if is_player {
+                        log.push(LogCategory::System, "You are hungry.");
+                    }
+                }
+                HungerState::Hungry => {
+                    clock.state = HungerState::Starving;
+                    clock.duration = 0;
+                    if is_player {
+                        log.push(LogCategory::System, "You are starving!");
+                    }
+                }
+                HungerState::Starving => {
+                    // keeps hurting every turn until something is eaten
+                    if is_player {
+                        SufferDamage::new_damage(&mut inflict_damage, entity, 1, false);
+                    }
+                }
+            }
+        }
+    }
+}
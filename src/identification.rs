@@ -0,0 +1,83 @@
+use std::collections::{HashMap, HashSet};
+
+use rltk::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use super::{Name, ObfuscatedName};
+
+// items that look the same until one of them has been used or identified
+const UNIDENTIFIED_ITEMS: &[&str] = &["Health Potion", "Fire Potion", "Haste Potion"];
+
+const POTION_LOOKS: &[&str] = &[
+    "fizzy blue potion",
+    "murky green potion",
+    "bubbling red potion",
+    "cloudy yellow potion",
+    "oily black potion",
+    "glowing pink potion",
+];
+
+// What each unidentified kind of item looks like this run, and which kinds the
+// player has worked out. Shuffled anew for every run and saved along with it
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct MagicItemMasks {
+    masks: HashMap<String, String>,
+    identified: HashSet<String>,
+}
+
+impl MagicItemMasks {
+    pub fn new(rng: &mut RandomNumberGenerator) -> MagicItemMasks {
+        let mut looks: Vec<&str> = POTION_LOOKS.to_vec();
+        let mut masks = HashMap::new();
+        for item in UNIDENTIFIED_ITEMS.iter() {
+            let look = looks.remove(rng.range(0, looks.len()));
+            masks.insert(item.to_string(), look.to_string());
+        }
+        MagicItemMasks { masks, identified: HashSet::new() }
+    }
+
+    // what a newly spawned item called name should show instead, if anything
+    pub fn mask_for(&self, name: &str) -> Option<ObfuscatedName> {
+        if self.identified.contains(name) {
+            return None;
+        }
+        self.masks.get(name).map(|look| ObfuscatedName { name: look.clone() })
+    }
+
+    pub fn identify(&mut self, name: &str) {
+        self.identified.insert(name.to_string());
+    }
+}
+
+// reveals every item called the same as item, wherever it is
+pub fn identify_kind(
+    item: Entity,
+    masks: &mut MagicItemMasks,
+    names: &ReadStorage<Name>,
+    obfuscated: &mut WriteStorage<ObfuscatedName>,
+    entities: &Entities,
+) {
+    let real_name = match names.get(item) {
+        Some(name) => name.name.clone(),
+        None => return,
+    };
+    masks.identify(&real_name);
+
+    let same_kind: Vec<Entity> = (entities, names, &*obfuscated)
+        .join()
+        .filter(|(_entity, name, _mask)| name.name == real_name)
+        .map(|(entity, _name, _mask)| entity)
+        .collect();
+    for entity in same_kind {
+        obfuscated.remove(entity);
+    }
+}
+
+// the name to show the player for an item, which may not be its real one yet
+pub fn display_name(names: &ReadStorage<Name>, mask: Option<&ObfuscatedName>, item: Entity) -> String {
+    match mask {
+        Some(mask) => mask.name.clone(),
+        None => names.get(item).map_or_else(|| "something".to_string(), |name| name.name.clone()),
+    }
+}
@@ -0,0 +1,100 @@
+use specs::prelude::*;
+
+use crate::{
+    gamelog::{GameLog, LogCategory},
+    Hasted, Initiative, MyTurn, Player, RunState, Slowed,
+};
+
+// initiative counts down this many times a turn, so haste can act twice in one
+pub const TICKS_PER_TURN: i32 = 2;
+
+// Counts everyone's initiative down once per monster turn. Whoever reaches zero
+// gets MyTurn until the next count and starts over from their speed, halved while
+// hasted and doubled while slowed, so a lower speed means acting more often
+pub struct InitiativeSystem {}
+
+impl<'a> System<'a> for InitiativeSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Initiative>,
+        WriteStorage<'a, MyTurn>,
+        ReadExpect<'a, RunState>,
+        WriteStorage<'a, Hasted>,
+        WriteStorage<'a, Slowed>,
+        ReadStorage<'a, Player>,
+        WriteExpect<'a, GameLog>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut initiatives, mut my_turn, runstate, mut hasted, mut slowed, players, mut log) = data;
+
+        if *runstate != RunState::MonsterTurn {
+            return;
+        }
+
+        my_turn.clear();
+        for (entity, initiative) in (&entities, &mut initiatives).join() {
+            initiative.current -= 1;
+            if initiative.current > 0 {
+                continue;
+            }
+            my_turn.insert(entity, MyTurn{}).expect("Unable to insert turn");
+
+            let is_player = players.get(entity).is_some();
+            let mut speed = initiative.speed;
+
+            // both wear off one action at a time
+            if let Some(haste) = hasted.get_mut(entity) {
+                speed = i32::max(1, speed / 2);
+                haste.turns -= 1;
+                if haste.turns <= 0 {
+                    hasted.remove(entity);
+                    if is_player {
+                        log.push(LogCategory::System, "You slow back down.");
+                    }
+                }
+            }
+            if let Some(slow) = slowed.get_mut(entity) {
+                speed *= 2;
+                slow.turns -= 1;
+                if slow.turns <= 0 {
+                    slowed.remove(entity);
+                    if is_player {
+                        log.push(LogCategory::System, "You feel up to speed again.");
+                    }
+                }
+            }
+
+            initiative.current = speed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{open_map, test_world};
+
+    // what the player's initiative starts over from after acting at a speed of 10
+    fn reset_to(hasted: bool) -> i32 {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        ecs.insert(RunState::MonsterTurn);
+        ecs.write_storage::<Initiative>().insert(player, Initiative{ current: 1, speed: 10 }).unwrap();
+        if hasted {
+            ecs.write_storage::<Hasted>().insert(player, Hasted{ turns: 3 }).unwrap();
+        }
+
+        InitiativeSystem {}.run_now(&ecs);
+
+        assert!(ecs.read_storage::<MyTurn>().get(player).is_some());
+        let current = ecs.read_storage::<Initiative>().get(player).unwrap().current;
+        current
+    }
+
+    #[test]
+    fn haste_halves_the_wait_for_the_next_turn() {
+        assert_eq!(reset_to(false), 10);
+        assert_eq!(reset_to(true), 5);
+    }
+}
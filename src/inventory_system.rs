@@ -1,10 +1,52 @@
 use crate::{
-    map, AreaOfEffect, CombatStats, Confusion, Consumable, Equippable, Equipped, InflictsDamage,
-    Map, ProvidesHealing, SufferDamage, WantsToDropItem, WantsToRemoveItem, WantsToUseItem,
+    footprint, BlocksTile, LightSource, Ranged, Size, Tags, damage_system::hit_effect, hunger_system::WELL_FED_TURNS, particle_system::{ParticleBuilder, PARTICLE_LIFETIME_MS}, AreaOfEffect, CombatStats, Confusion, Consumable, Cursed, Equippable, EquipmentSlot,
+    Equipped, HarmsCaster, Hasted, EntityMoved, HungerClock, HungerState, Identify, MagicMapper, ObfuscatedName, Teleport, Viewshed, WantsToIdentify, IgnoresWalls, InflictsDamage, Map, ProvidesFood, ProvidesHaste, ProvidesHealing, ShattersOnThrow, SufferDamage, Resistances, Vulnerabilities, WantsToDropItem, TwoHanded, Weight,
+    WantsToRemoveItem, WantsToUseItem,
 };
+use rltk::{Point, RandomNumberGenerator, RGB};
 use specs::prelude::*;
+use specs::storage::MaskedStorage;
 
-use super::{gamelog::GameLog, InBackpack, Item, Name, Position, WantsToPickUpItem};
+use super::{
+    gamelog::{GameLog, LogCategory},
+    identification::{display_name, identify_kind, MagicItemMasks},
+    random_neighbour, InBackpack, Name, Position, WantsToPickUpItem,
+};
+
+// how many spots a teleport tries before giving up
+const MAX_TELEPORT_TRIES: i32 = 30;
+
+// pounds anyone can carry, before their strength is counted
+const BASE_CARRY_CAPACITY: f32 = 30.0;
+const CARRY_PER_POWER: f32 = 5.0;
+
+// stronger creatures haul more
+pub fn carry_capacity(stats: &CombatStats) -> f32 {
+    BASE_CARRY_CAPACITY + stats.power as f32 * CARRY_PER_POWER
+}
+
+// everything owner has in their backpack or on them, dropped items stop counting right away
+pub fn carried_weight<B>(
+    owner: Entity,
+    weights: &ReadStorage<Weight>,
+    backpack: &Storage<InBackpack, B>,
+    equipped: &ReadStorage<Equipped>,
+) -> f32
+where
+    B: std::ops::Deref<Target = MaskedStorage<InBackpack>>,
+{
+    let packed: f32 = (backpack, weights)
+        .join()
+        .filter(|(pack, _weight)| pack.owner == owner)
+        .map(|(_pack, weight)| weight.pounds)
+        .sum();
+    let worn: f32 = (equipped, weights)
+        .join()
+        .filter(|(worn, _weight)| worn.owner == owner)
+        .map(|(_worn, weight)| weight.pounds)
+        .sum();
+    packed + worn
+}
 
 pub struct InventorySystem {}
 
@@ -17,13 +59,43 @@ impl<'a> System<'a> for InventorySystem {
         WriteStorage<'a, Position>,
         ReadStorage<'a, Name>,
         WriteStorage<'a, InBackpack>,
+        ReadStorage<'a, ObfuscatedName>,
+        ReadStorage<'a, Weight>,
+        ReadStorage<'a, Equipped>,
+        ReadStorage<'a, CombatStats>,
+        ReadStorage<'a, LightSource>,
+        WriteStorage<'a, Viewshed>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (player_entity, mut gamelog, mut wants_pickup, mut positions, names, mut backpack) =
-            data;
+        let (
+            player_entity,
+            mut gamelog,
+            mut wants_pickup,
+            mut positions,
+            names,
+            mut backpack,
+            obfuscated,
+            weights,
+            equipped,
+            combat_stats,
+            lights,
+            mut viewsheds,
+        ) = data;
 
         for pickup in wants_pickup.join() {
+            // whatever would tip the load over stays where it is
+            if let Some(stats) = combat_stats.get(pickup.collected_by) {
+                let item_weight = weights.get(pickup.item).map_or(0.0, |weight| weight.pounds);
+                let load = carried_weight(pickup.collected_by, &weights, &backpack, &equipped);
+                if load + item_weight > carry_capacity(stats) {
+                    if pickup.collected_by == *player_entity {
+                        gamelog.push(LogCategory::System, "It's too heavy to carry.");
+                    }
+                    continue;
+                }
+            }
+
             positions.remove(pickup.item);
             backpack
                 .insert(
@@ -33,24 +105,58 @@ impl<'a> System<'a> for InventorySystem {
                     },
                 )
                 .expect("Unable to insert into backpack");
+            // a light in the pack reaches further than the eyes alone
+            if lights.get(pickup.item).is_some() {
+                if let Some(viewshed) = viewsheds.get_mut(pickup.collected_by) {
+                    viewshed.dirty = true;
+                }
+            }
 
             if pickup.collected_by == *player_entity {
-                gamelog.entries.push(format!(
+                gamelog.push_colored(LogCategory::System, format!(
                     "You pick up the {}.",
-                    names.get(pickup.item).unwrap().name
-                ));
+                    display_name(&names, obfuscated.get(pickup.item), pickup.item)
+                ), RGB::named(rltk::LIGHT_GREEN));
             }
         }
         wants_pickup.clear();
     }
 }
 
+// The tiles a blast centred on target reaches. Normally walls stop it (line of effect),
+// effects that ignore walls fill the whole radius
+pub fn area_of_effect_tiles(map: &Map, target: Point, radius: i32, ignores_walls: bool) -> Vec<Point> {
+    let mut tiles = if ignores_walls {
+        let mut tiles = Vec::new();
+        for y in target.y - radius..=target.y + radius {
+            for x in target.x - radius..=target.x + radius {
+                let tile = Point::new(x, y);
+                if rltk::DistanceAlg::Pythagoras.distance2d(target, tile) <= radius as f32 {
+                    tiles.push(tile);
+                }
+            }
+        }
+        tiles
+    } else {
+        rltk::field_of_view(target, radius, map)
+    };
+    tiles.retain(|p| !map.is_out_of_bounds(p.x, p.y));
+    tiles
+}
+
+// names for log messages, without trusting that the entity still has one
+pub fn name_of(names: &ReadStorage<Name>, entity: Entity) -> String {
+    names
+        .get(entity)
+        .map_or_else(|| "something".to_string(), |name| name.name.clone())
+}
+
 pub struct ItemUseSystem {}
 
 impl<'a> System<'a> for ItemUseSystem {
     #[allow(clippy::type_complexity)]
     type SystemData = (
-        ReadExpect<'a, Map>,
+        WriteExpect<'a, Map>,
         ReadExpect<'a, Entity>,
         WriteExpect<'a, GameLog>,
         Entities<'a>,
@@ -66,6 +172,36 @@ impl<'a> System<'a> for ItemUseSystem {
         ReadStorage<'a, Equippable>,
         WriteStorage<'a, Equipped>,
         WriteStorage<'a, InBackpack>,
+        WriteStorage<'a, Position>,
+        ReadStorage<'a, Cursed>,
+        ReadStorage<'a, HarmsCaster>,
+        ReadStorage<'a, ShattersOnThrow>,
+        ReadStorage<'a, Resistances>,
+        ReadStorage<'a, IgnoresWalls>,
+        ReadStorage<'a, ProvidesFood>,
+        WriteStorage<'a, HungerClock>,
+        WriteExpect<'a, ParticleBuilder>,
+        // a system can only take 26 of these, so the later effects share one
+        (
+            ReadStorage<'a, MagicMapper>,
+            ReadStorage<'a, Teleport>,
+            WriteStorage<'a, Viewshed>,
+            WriteExpect<'a, Point>,
+            WriteExpect<'a, RandomNumberGenerator>,
+            WriteStorage<'a, EntityMoved>,
+            ReadStorage<'a, Identify>,
+            WriteStorage<'a, WantsToIdentify>,
+            WriteStorage<'a, ObfuscatedName>,
+            WriteExpect<'a, MagicItemMasks>,
+            ReadStorage<'a, ProvidesHaste>,
+            WriteStorage<'a, Hasted>,
+            ReadStorage<'a, TwoHanded>,
+            ReadStorage<'a, Vulnerabilities>,
+            ReadStorage<'a, Ranged>,
+            ReadStorage<'a, BlocksTile>,
+            ReadStorage<'a, Size>,
+            ReadStorage<'a, Tags>,
+        ),
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -86,6 +222,35 @@ impl<'a> System<'a> for ItemUseSystem {
             equippable,
             mut equipped,
             mut backpack,
+            mut positions,
+            cursed,
+            harms_caster,
+            shatters,
+            resistances,
+            ignores_walls,
+            provides_food,
+            mut hunger_clocks,
+            mut particle_builder,
+            (
+                magic_mapper,
+                teleports,
+                mut viewsheds,
+                mut player_pos,
+                mut rng,
+                mut entity_moved,
+                identify,
+                mut wants_identify,
+                mut obfuscated,
+                mut masks,
+                provides_haste,
+                mut hasted,
+                two_handed,
+                vulnerabilities,
+                ranged,
+                blockers,
+                sizes,
+                tags,
+            ),
         ) = data;
 
         for (entity, useitem) in (&entities, &wants_use_item).join() {
@@ -96,32 +261,185 @@ impl<'a> System<'a> for ItemUseSystem {
                 // if no target, apply to player
                 None => targets.push(*player_entity),
                 Some(target) => {
-                    let area_effect = aoe.get(useitem.item);
-                    match area_effect {
+                    // thrown potions splash like an area effect
+                    let splash_radius = match aoe.get(useitem.item) {
+                        Some(area_effect) => Some(area_effect.radius),
+                        None => shatters.get(useitem.item).map(|s| s.radius),
+                    };
+                    match splash_radius {
                         None => {
                             //Single target in tile
                             let idx = map.xy_idx(target.x, target.y);
                             for mob in map.tile_content[idx].iter() {
-                                targets.push(*mob);
+                                // tile_content can lag behind deaths earlier in the frame
+                                if entities.is_alive(*mob) {
+                                    targets.push(*mob);
+                                }
                             }
                         }
-                        Some(area_effect) => {
-                            let mut affected_tiles =
-                                rltk::field_of_view(target, area_effect.radius, &*map);
-                            affected_tiles.retain(|p| {
-                                p.x > 0 && p.x < map.width - 1 && p.y > 0 && p.y < map.height - 1
-                            });
+                        Some(radius) => {
+                            let affected_tiles = area_of_effect_tiles(
+                                &map,
+                                target,
+                                radius,
+                                ignores_walls.get(useitem.item).is_some(),
+                            );
                             for tile_idx in affected_tiles.iter() {
                                 let idx = map.xy_idx(tile_idx.x, tile_idx.y);
                                 for mob in map.tile_content[idx].iter() {
-                                    targets.push(*mob);
+                                    // big creatures stand on several tiles but only get hit once
+                                    if entities.is_alive(*mob) && !targets.contains(mob) {
+                                        targets.push(*mob);
+                                    }
                                 }
                             }
+
+                            // don't catch the user in their own blast unless the item says so,
+                            // or they aimed it at their own feet
+                            let aimed_at_self = positions
+                                .get(entity)
+                                .is_some_and(|pos| pos.x == target.x && pos.y == target.y);
+                            if harms_caster.get(useitem.item).is_none() && !aimed_at_self {
+                                targets.retain(|mob| *mob != entity);
+                            }
                         }
                     }
                 }
             }
 
+            // a thrown potion breaks on landing whether or not it hit anything
+            let shattered = useitem.target.is_some() && shatters.get(useitem.item).is_some();
+            if shattered {
+                // a trail from the thrower to where it lands, fading from the thrower's end first
+                if let (Some(from), Some(to)) = (positions.get(entity), useitem.target) {
+                    let trail = rltk::line2d(rltk::LineAlg::Bresenham, Point::new(from.x, from.y), to);
+                    for (step, tile) in trail.iter().enumerate().skip(1) {
+                        particle_builder.request(
+                            tile.x,
+                            tile.y,
+                            RGB::named(rltk::CYAN),
+                            rltk::to_cp437('*'),
+                            PARTICLE_LIFETIME_MS * 0.5 + step as f32 * 30.0,
+                        );
+                    }
+                }
+                gamelog.push(
+                    LogCategory::Flavor,
+                    format!("The {} shatters!", name_of(&names, useitem.item)),
+                );
+            }
+
+            // aimed at a tile it tries there first, otherwise anywhere open on the level,
+            // either way a taken spot means rolling again close by or somewhere else
+            if teleports.get(useitem.item).is_some() {
+                let user_pos = positions.get(entity).map(|pos| Point::new(pos.x, pos.y));
+                let mut destination = None;
+                for attempt in 0..MAX_TELEPORT_TRIES {
+                    let candidate = match useitem.target {
+                        Some(target) if attempt == 0 => target,
+                        Some(target) => random_neighbour(&mut rng, target),
+                        None => Point::new(rng.range(1, map.width - 1), rng.range(1, map.height - 1)),
+                    };
+                    if map.is_out_of_bounds(candidate.x, candidate.y) || map.is_impassable(map.xy_idx(candidate.x, candidate.y)) {
+                        continue;
+                    }
+                    // the blocked tiles can be a turn out of date, where everyone stands now is what counts
+                    let occupied = (&entities, &positions, &blockers).join().any(|(other, pos, _blocks)| {
+                        other != entity && footprint(sizes.get(other), pos.x, pos.y).contains(&candidate)
+                    });
+                    // a rerolled spot has to be somewhere the user could have aimed at in the first place
+                    let reachable = match (useitem.target, user_pos) {
+                        (Some(_target), Some(from)) => {
+                            ranged.get(useitem.item).is_none_or(|ranged| {
+                                rltk::DistanceAlg::Pythagoras.distance2d(from, candidate) <= ranged.range as f32
+                            }) && map.has_line_of_sight(from, candidate)
+                                && viewsheds.get(entity).is_none_or(|viewshed| viewshed.visible_tiles.contains(&candidate))
+                        }
+                        _ => true,
+                    };
+                    if !occupied && reachable {
+                        destination = Some(candidate);
+                        break;
+                    }
+                }
+
+                match (destination, positions.get_mut(entity)) {
+                    (Some(destination), Some(pos)) => {
+                        pos.x = destination.x;
+                        pos.y = destination.y;
+                        entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+                        if let Some(viewshed) = viewsheds.get_mut(entity) {
+                            viewshed.dirty = true;
+                        }
+                        if entity == *player_entity {
+                            *player_pos = destination;
+                            gamelog.push(LogCategory::System, "You blink across the dungeon.");
+                        }
+                    }
+                    _ => {
+                        used_item = false;
+                        if entity == *player_entity {
+                            gamelog.push(LogCategory::System, "The magic fizzles, there's nowhere to go.");
+                        }
+                    }
+                }
+            }
+
+            // works out what the chosen item is, along with everything else like it
+            if identify.get(useitem.item).is_some() {
+                match wants_identify.get(entity) {
+                    Some(chosen) => {
+                        if entity == *player_entity {
+                            gamelog.push(LogCategory::System, format!(
+                                "The {} is a {}.",
+                                display_name(&names, obfuscated.get(chosen.item), chosen.item),
+                                name_of(&names, chosen.item)
+                            ));
+                        }
+                        identify_kind(chosen.item, &mut masks, &names, &mut obfuscated, &entities);
+                    }
+                    None => used_item = false,
+                }
+                wants_identify.remove(entity);
+            }
+
+            // the whole level becomes known, though only what's in sight is shown as it is now
+            if magic_mapper.get(useitem.item).is_some() {
+                for revealed in map.revealed_tiles.iter_mut() {
+                    *revealed = true;
+                }
+                gamelog.push(LogCategory::System, "The map is revealed to you!");
+            }
+
+            // eating fills you right up, whatever state you were in
+            if provides_food.get(useitem.item).is_some() {
+                used_item = false;
+                for target in targets.iter() {
+                    if let Some(clock) = hunger_clocks.get_mut(*target) {
+                        clock.state = HungerState::WellFed;
+                        clock.duration = WELL_FED_TURNS;
+                        if entity == *player_entity {
+                            gamelog.push(LogCategory::System, format!(
+                                "You eat the {}.",
+                                name_of(&names, useitem.item)
+                            ));
+                        }
+                        used_item = true;
+                    }
+                }
+            }
+
+            if let Some(haste) = provides_haste.get(useitem.item) {
+                for target in targets.iter() {
+                    hasted
+                        .insert(*target, Hasted{ turns: haste.turns })
+                        .expect("Unable to insert haste");
+                    if *target == *player_entity {
+                        gamelog.push(LogCategory::System, "You feel quick!");
+                    }
+                }
+            }
+
             let item_heals = healing.get(useitem.item);
             match item_heals {
                 None => {}
@@ -131,10 +449,20 @@ impl<'a> System<'a> for ItemUseSystem {
                         let stats = combat_stats.get_mut(*target);
                         if let Some(stats) = stats {
                             stats.hp = i32::min(stats.max_hp, stats.hp + healer.heal_amount);
-                            if entity == *player_entity {
-                                gamelog.entries.push(format!(
+                            if let Some(pos) = positions.get(*target) {
+                                particle_builder.request(pos.x, pos.y, RGB::named(rltk::GREEN), rltk::to_cp437('♥'), PARTICLE_LIFETIME_MS);
+                            }
+                            if entity == *player_entity && *target == entity {
+                                gamelog.push(LogCategory::System, format!(
                                     "You use the {}, healing {} hp.",
-                                    names.get(useitem.item).unwrap().name,
+                                    name_of(&names, useitem.item),
+                                    healer.heal_amount
+                                ));
+                            } else if entity == *player_entity {
+                                gamelog.push(LogCategory::System, format!(
+                                    "You use the {} on {}, healing {} hp.",
+                                    name_of(&names, useitem.item),
+                                    name_of(&names, *target),
                                     healer.heal_amount
                                 ));
                             }
@@ -151,13 +479,18 @@ impl<'a> System<'a> for ItemUseSystem {
                 Some(damage) => {
                     used_item = false;
                     for mob in targets.iter() {
-                        SufferDamage::new_damage(&mut suffer_damage, *mob, damage.damage);
-                        if entity == *player_entity && *mob != entity {
-                            let mob_name = names.get(*mob).unwrap();
-                            let item_name = names.get(useitem.item).unwrap();
-                            gamelog.entries.push(format!(
+                        // the damage system does the scaling, this is only so the log tells the truth
+                        let amount = hit_effect(damage.kind, resistances.get(*mob), vulnerabilities.get(*mob), tags.get(*mob)).scale(damage.damage);
+                        SufferDamage::new_typed_damage(&mut suffer_damage, *mob, damage.damage, damage.kind, entity == *player_entity);
+                        if let Some(pos) = positions.get(*mob) {
+                            particle_builder.request(pos.x, pos.y, RGB::named(rltk::ORANGE), rltk::to_cp437('‼'), PARTICLE_LIFETIME_MS);
+                        }
+                        if entity == *player_entity && *mob == entity {
+                            gamelog.push(LogCategory::Combat, format!("The blast also catches you for {} damage!", amount));
+                        } else if entity == *player_entity {
+                            gamelog.push(LogCategory::Combat, format!(
                                 "You use {} on {}, inflicting {} damage.",
-                                item_name.name, mob_name.name, damage.damage
+                                name_of(&names, useitem.item), name_of(&names, *mob), amount
                             ));
                         }
                         used_item = true;
@@ -172,14 +505,16 @@ impl<'a> System<'a> for ItemUseSystem {
                     None => {}
                     Some(confusion) => {
                         used_item = false;
-                        for mob in targets.iter() {
+                        // only creatures can be confused, not the items lying in the blast
+                        for mob in targets.iter().filter(|mob| combat_stats.get(**mob).is_some()) {
                             add_confusion.push((*mob, confusion.turns));
-                            if entity == *player_entity {
-                                let mob_name = names.get(*mob).unwrap();
-                                let item_name = names.get(useitem.item).unwrap();
-                                gamelog.entries.push(format!(
+                            used_item = true;
+                            if entity == *player_entity && *mob == entity {
+                                gamelog.push(LogCategory::Combat, "The blast leaves you confused as well!");
+                            } else if entity == *player_entity {
+                                gamelog.push(LogCategory::Combat, format!(
                                     "You use {} on {}, confusing them.",
-                                    item_name.name, mob_name.name
+                                    name_of(&names, useitem.item), name_of(&names, *mob)
                                 ));
                             }
                         }
@@ -201,23 +536,46 @@ impl<'a> System<'a> for ItemUseSystem {
                     let target_slot = equippable_item.slot;
                     let target = targets[0];
 
+                    // a two-hander and a shield can't be held at once, whichever comes second wins
+                    let needs_both_hands = two_handed.get(useitem.item).is_some();
                     let mut to_unequip: Vec<Entity> = Vec::new();
-                    for (item_entity, already_equipped, name) in
-                        (&entities, &equipped, &names).join()
-                    {
-                        if already_equipped.owner == target && already_equipped.slot == target_slot
-                        {
+                    for (item_entity, already_equipped) in (&entities, &equipped).join() {
+                        if already_equipped.owner != target {
+                            continue;
+                        }
+                        let hands_conflict = (needs_both_hands && already_equipped.slot == EquipmentSlot::Shield)
+                            || (target_slot == EquipmentSlot::Shield && two_handed.get(item_entity).is_some());
+                        if already_equipped.slot == target_slot || hands_conflict {
                             to_unequip.push(item_entity);
-                            if target == *player_entity {
-                                gamelog.entries.push(format!("You unequip {}", name.name));
-                            }
                         }
                     }
+
+                    // a cursed item won't leave its slot, so nothing can replace it
+                    let stuck_item = to_unequip.iter().find(|item| cursed.get(**item).is_some());
+                    if let Some(stuck_item) = stuck_item {
+                        if target == *player_entity {
+                            gamelog.push(
+                                LogCategory::System,
+                                format!(
+                                    "You can't let go of the cursed {}!",
+                                    name_of(&names, *stuck_item)
+                                ),
+                            );
+                        }
+                        continue;
+                    }
+
                     for item in to_unequip.iter() {
                         equipped.remove(*item);
                         backpack
                             .insert(*item, InBackpack { owner: target })
                             .expect("Unable to insert item into backpack");
+                        if target == *player_entity {
+                            gamelog.push(
+                                LogCategory::System,
+                                format!("You unequip {}", name_of(&names, *item)),
+                            );
+                        }
                     }
 
                     equipped
@@ -230,20 +588,25 @@ impl<'a> System<'a> for ItemUseSystem {
                         )
                         .expect("Unable to equip item");
                     backpack.remove(useitem.item);
+                    // the item may have been equipped straight off the floor
+                    positions.remove(useitem.item);
                     if target == *player_entity {
-                        gamelog.entries.push(format!(
-                            "You equip {}.",
-                            names.get(useitem.item).unwrap().name
+                        gamelog.push(LogCategory::System, format!(
+                            "You equip the {}.",
+                            name_of(&names, useitem.item)
                         ));
                     }
                 }
             }
 
-            if used_item {
+            if used_item || shattered {
+                // using something gives away what it was
+                identify_kind(useitem.item, &mut masks, &names, &mut obfuscated, &entities);
+
                 let consumable = consumables.get(useitem.item);
                 match consumable {
                     None => {}
-                    Some(consumable) => {
+                    Some(_consumable) => {
                         entities.delete(useitem.item).expect("Delete failed");
                     }
                 }
@@ -265,6 +628,9 @@ impl<'a> System<'a> for ItemDropSystem {
         ReadStorage<'a, Name>,
         WriteStorage<'a, Position>,
         WriteStorage<'a, InBackpack>,
+        ReadStorage<'a, ObfuscatedName>,
+        ReadStorage<'a, LightSource>,
+        WriteStorage<'a, Viewshed>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -276,6 +642,9 @@ impl<'a> System<'a> for ItemDropSystem {
             names,
             mut positions,
             mut backpack,
+            obfuscated,
+            lights,
+            mut viewsheds,
         ) = data;
 
         for (entity, to_drop) in (&entities, &wants_drop).join() {
@@ -285,19 +654,26 @@ impl<'a> System<'a> for ItemDropSystem {
                 dropper_pos.x = dropped_pos.x;
                 dropper_pos.y = dropped_pos.y;
             }
-            positions.insert(
-                to_drop.item,
-                Position {
-                    x: dropper_pos.x,
-                    y: dropper_pos.y,
-                },
-            );
+            positions
+                .insert(
+                    to_drop.item,
+                    Position {
+                        x: dropper_pos.x,
+                        y: dropper_pos.y,
+                    },
+                )
+                .expect("Unable to insert position");
             backpack.remove(to_drop.item);
+            if lights.get(to_drop.item).is_some() {
+                if let Some(viewshed) = viewsheds.get_mut(entity) {
+                    viewshed.dirty = true;
+                }
+            }
 
             if entity == *player_entity {
-                gamelog.entries.push(format!(
+                gamelog.push(LogCategory::System, format!(
                     "You drop the {}.",
-                    names.get(to_drop.item).unwrap().name
+                    display_name(&names, obfuscated.get(to_drop.item), to_drop.item)
                 ));
             }
         }
@@ -309,7 +685,7 @@ impl<'a> System<'a> for ItemDropSystem {
 pub struct ItemRemoveSystem {}
 
 impl<'a> System<'a> for ItemRemoveSystem {
-    #[allow(clippy::clippy::type_complexity)]
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         Entities<'a>,
         WriteStorage<'a, WantsToRemoveItem>,
@@ -317,22 +693,263 @@ impl<'a> System<'a> for ItemRemoveSystem {
         WriteStorage<'a, InBackpack>,
         ReadStorage<'a, Name>,
         WriteExpect<'a, GameLog>,
+        ReadStorage<'a, Cursed>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut wants_remove, mut equipped, mut backpack, names, mut gamelog) = data;
+        let (entities, mut wants_remove, mut equipped, mut backpack, names, mut gamelog, cursed) =
+            data;
 
         for (entity, to_remove) in (&entities, &wants_remove).join() {
+            if cursed.get(to_remove.item).is_some() {
+                if let Some(item_name) = names.get(to_remove.item) {
+                    gamelog.push(
+                        LogCategory::System,
+                        format!("You can't let go of the cursed {}!", item_name.name),
+                    );
+                }
+                continue;
+            }
+
             equipped.remove(to_remove.item);
             backpack
                 .insert(to_remove.item, InBackpack { owner: entity })
                 .expect("Unable to insert item into backpack");
             let item_name = names.get(to_remove.item);
             if let Some(item_name) = item_name {
-                gamelog.entries.push(format!("You unequip {}", item_name.name));
+                gamelog.push(
+                    LogCategory::System,
+                    format!("You unequip {}", item_name.name),
+                );
             }
         }
 
         wants_remove.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TileType;
+    use crate::test_support::{damage_queued, equip, give, logged, open_map, refresh_map, spawn_at, test_world, use_item};
+
+    #[test]
+    fn blast_next_to_the_user_spares_them() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 12, 10, "Goblin");
+        let fireball = give(&mut ecs, player, "Fireball Scroll");
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, fireball, Some(Point::new(11, 10)));
+
+        assert_eq!(damage_queued(&ecs, player), 0);
+        assert_eq!(damage_queued(&ecs, goblin), 20);
+    }
+
+    #[test]
+    fn thrown_fire_potion_splashes_a_cluster() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let cluster = [
+            spawn_at(&mut ecs, 14, 10, "Goblin"),
+            spawn_at(&mut ecs, 15, 10, "Goblin"),
+            spawn_at(&mut ecs, 14, 11, "Goblin"),
+        ];
+        let outside = spawn_at(&mut ecs, 17, 10, "Goblin");
+        let potion = give(&mut ecs, player, "Fire Potion");
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, potion, Some(Point::new(14, 10)));
+
+        for goblin in cluster.iter() {
+            assert_eq!(damage_queued(&ecs, *goblin), 10);
+        }
+        assert_eq!(damage_queued(&ecs, outside), 0);
+        assert!(!ecs.is_alive(potion));
+    }
+
+    #[test]
+    fn blast_over_a_stale_tile_skips_the_dead() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let dead = spawn_at(&mut ecs, 14, 10, "Goblin");
+        let alive = spawn_at(&mut ecs, 15, 10, "Goblin");
+        let fireball = give(&mut ecs, player, "Fireball Scroll");
+        refresh_map(&mut ecs);
+        // killed after tile_content was built, so it's still listed there
+        ecs.delete_entity(dead).unwrap();
+
+        use_item(&mut ecs, player, fireball, Some(Point::new(14, 10)));
+
+        assert_eq!(damage_queued(&ecs, alive), 20);
+        assert_eq!(ecs.read_storage::<SufferDamage>().count(), 1);
+    }
+
+    #[test]
+    fn confusion_cloud_confuses_the_whole_cluster() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let cluster = [
+            spawn_at(&mut ecs, 14, 10, "Goblin"),
+            spawn_at(&mut ecs, 15, 10, "Goblin"),
+            spawn_at(&mut ecs, 14, 11, "Goblin"),
+        ];
+        let scroll = give(&mut ecs, player, "Stinking Cloud Scroll");
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, scroll, Some(Point::new(14, 10)));
+
+        let confusion = ecs.read_storage::<Confusion>();
+        for goblin in cluster.iter() {
+            assert_eq!(confusion.get(*goblin).map(|c| c.turns), Some(3));
+        }
+        assert!(!ecs.is_alive(scroll));
+    }
+
+    #[test]
+    fn only_some_blasts_reach_round_a_pillar() {
+        let mut map = open_map();
+        let pillar = map.xy_idx(12, 10);
+        map.tiles[pillar] = TileType::Wall;
+        map.populate_blocked();
+        let behind = Point::new(13, 10);
+
+        assert!(!area_of_effect_tiles(&map, Point::new(11, 10), 3, false).contains(&behind));
+        assert!(area_of_effect_tiles(&map, Point::new(11, 10), 3, true).contains(&behind));
+    }
+
+    #[test]
+    fn thrown_potion_hurts_whoever_is_on_the_tile() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 14, 12, "Goblin");
+        let potion = give(&mut ecs, player, "Fire Potion");
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, potion, Some(Point::new(14, 12)));
+
+        assert_eq!(damage_queued(&ecs, goblin), 10);
+        assert_eq!(damage_queued(&ecs, player), 0);
+        assert!(logged(&ecs, "shatters"));
+        assert!(!ecs.is_alive(potion));
+    }
+
+    #[test]
+    fn blinking_onto_a_monster_lands_beside_it_in_range() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let scroll = give(&mut ecs, player, "Blink Scroll");
+        refresh_map(&mut ecs);
+        // it walks up after the tiles were indexed, so only its position says the spot is taken
+        spawn_at(&mut ecs, 18, 10, "Goblin");
+
+        use_item(&mut ecs, player, scroll, Some(Point::new(18, 10)));
+
+        let positions = ecs.read_storage::<Position>();
+        let landed = positions.get(player).unwrap();
+        assert_eq!(landed.x, 17);
+        assert!((9..=11).contains(&landed.y));
+    }
+
+    #[test]
+    fn fireball_at_your_own_feet_hits_everything_in_reach() {
+        let (mut ecs, player) = test_world(open_map(), 20, 20);
+        let caught = [
+            spawn_at(&mut ecs, 21, 20, "Goblin"),
+            spawn_at(&mut ecs, 20, 17, "Goblin"),
+            spawn_at(&mut ecs, 18, 22, "Goblin"),
+        ];
+        let outside = spawn_at(&mut ecs, 24, 20, "Goblin");
+        let fireball = give(&mut ecs, player, "Fireball Scroll");
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, fireball, Some(Point::new(20, 20)));
+
+        for goblin in caught.iter() {
+            assert_eq!(damage_queued(&ecs, *goblin), 20);
+        }
+        assert_eq!(damage_queued(&ecs, outside), 0);
+        assert_eq!(damage_queued(&ecs, player), 20);
+    }
+
+    #[test]
+    fn confusion_scroll_with_a_blast_confuses_every_neighbour() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let cluster = [
+            spawn_at(&mut ecs, 14, 10, "Goblin"),
+            spawn_at(&mut ecs, 15, 10, "Orc"),
+            spawn_at(&mut ecs, 16, 10, "Goblin"),
+        ];
+        let scroll = give(&mut ecs, player, "Confusion Scroll");
+        ecs.write_storage::<AreaOfEffect>().insert(scroll, AreaOfEffect{ radius: 1 }).unwrap();
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, scroll, Some(Point::new(15, 10)));
+
+        let confusion = ecs.read_storage::<Confusion>();
+        for monster in cluster.iter() {
+            assert!(confusion.get(*monster).is_some());
+        }
+        let confused_lines = ecs.fetch::<GameLog>().entries.iter().filter(|entry| entry.text.contains("confusing them")).count();
+        assert_eq!(confused_lines, 3);
+        assert!(logged(&ecs, "on Orc, confusing them"));
+    }
+
+    #[test]
+    fn your_own_fireball_tells_you_it_caught_you() {
+        let (mut ecs, player) = test_world(open_map(), 20, 20);
+        spawn_at(&mut ecs, 21, 20, "Goblin");
+        let fireball = give(&mut ecs, player, "Fireball Scroll");
+        refresh_map(&mut ecs);
+
+        use_item(&mut ecs, player, fireball, Some(Point::new(20, 20)));
+
+        assert!(logged(&ecs, "The blast also catches you for 20 damage!"));
+        assert!(logged(&ecs, "You use Fireball Scroll on Goblin"));
+        assert!(!logged(&ecs, "on Player"));
+    }
+
+    #[test]
+    fn pickups_past_the_carry_limit_are_refused() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        // nothing but the base capacity of 30 pounds, two 12 pound axes fit and a third doesn't
+        ecs.write_storage::<CombatStats>().get_mut(player).unwrap().power = 0;
+        let axes = [
+            spawn_at(&mut ecs, 10, 10, "Greataxe"),
+            spawn_at(&mut ecs, 10, 10, "Greataxe"),
+            spawn_at(&mut ecs, 10, 10, "Greataxe"),
+        ];
+        let pick_up = |ecs: &mut World, item: Entity| {
+            ecs.write_storage::<WantsToPickUpItem>().insert(item, WantsToPickUpItem{ collected_by: player, item }).unwrap();
+            InventorySystem {}.run_now(ecs);
+            ecs.maintain();
+            ecs.read_storage::<InBackpack>().get(item).is_some()
+        };
+
+        assert!(pick_up(&mut ecs, axes[0]));
+        assert!(pick_up(&mut ecs, axes[1]));
+        assert!(!pick_up(&mut ecs, axes[2]));
+        assert!(logged(&ecs, "It's too heavy to carry."));
+
+        // dropping one makes room straight away
+        ecs.write_storage::<WantsToDropItem>().insert(player, WantsToDropItem{ item: axes[0] }).unwrap();
+        ItemDropSystem {}.run_now(&ecs);
+        ecs.maintain();
+        assert!(pick_up(&mut ecs, axes[2]));
+    }
+
+    #[test]
+    fn two_handers_and_shields_push_each_other_off() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let shield = equip(&mut ecs, player, "Shield");
+        let axe = give(&mut ecs, player, "Greataxe");
+
+        use_item(&mut ecs, player, axe, None);
+        assert!(ecs.read_storage::<Equipped>().get(axe).is_some());
+        assert!(ecs.read_storage::<Equipped>().get(shield).is_none());
+        assert_eq!(ecs.read_storage::<InBackpack>().get(shield).map(|pack| pack.owner), Some(player));
+        assert!(logged(&ecs, "You unequip Shield"));
+
+        use_item(&mut ecs, player, shield, None);
+        assert!(ecs.read_storage::<Equipped>().get(shield).is_some());
+        assert!(ecs.read_storage::<Equipped>().get(axe).is_none());
+        assert_eq!(ecs.read_storage::<InBackpack>().get(axe).map(|pack| pack.owner), Some(player));
+        assert!(logged(&ecs, "You unequip Greataxe"));
+    }
+}
@@ -0,0 +1,180 @@
+use rltk::VirtualKeyCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+const KEYBINDINGS_FILE: &str = "./keybindings.json";
+
+// everything the player can do from the map with a single key
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    MoveWest,
+    MoveEast,
+    MoveNorth,
+    MoveSouth,
+    MoveNorthEast,
+    MoveNorthWest,
+    MoveSouthWest,
+    MoveSouthEast,
+    PickUp,
+    Inventory,
+    Drop,
+    Throw,
+    Remove,
+    EquipFromGround,
+    Interact,
+    Descend,
+    Ascend,
+    Wait,
+    Rest,
+    Search,
+    AutoExplore,
+    SaveMenu,
+    Quit,
+    ToggleThreatColors,
+    CycleLogFilter,
+    ShowLog,
+}
+
+// keys keybindings.json can name, written the way VirtualKeyCode prints them
+const BINDABLE_KEYS: &[VirtualKeyCode] = &[
+    VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D, VirtualKeyCode::E,
+    VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H, VirtualKeyCode::I, VirtualKeyCode::J,
+    VirtualKeyCode::K, VirtualKeyCode::L, VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O,
+    VirtualKeyCode::P, VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+    VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X, VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+    VirtualKeyCode::Numpad0, VirtualKeyCode::Numpad1, VirtualKeyCode::Numpad2, VirtualKeyCode::Numpad3,
+    VirtualKeyCode::Numpad4, VirtualKeyCode::Numpad5, VirtualKeyCode::Numpad6, VirtualKeyCode::Numpad7,
+    VirtualKeyCode::Numpad8, VirtualKeyCode::Numpad9,
+    VirtualKeyCode::Left, VirtualKeyCode::Right, VirtualKeyCode::Up, VirtualKeyCode::Down,
+    VirtualKeyCode::Space, VirtualKeyCode::Tab, VirtualKeyCode::Escape, VirtualKeyCode::Return,
+    VirtualKeyCode::Period, VirtualKeyCode::Comma, VirtualKeyCode::Slash, VirtualKeyCode::Semicolon,
+];
+
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS.iter().copied().find(|key| format!("{:?}", key) == name)
+}
+
+// Which key does what on the map. keybindings.json only has to list the actions
+// it changes, like {"MoveNorth": ["W"]}, and those keys stop doing anything else
+pub struct KeyBindings {
+    bindings: HashMap<VirtualKeyCode, Action>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        let defaults = [
+            (VirtualKeyCode::Left, Action::MoveWest),
+            (VirtualKeyCode::Numpad4, Action::MoveWest),
+            (VirtualKeyCode::Right, Action::MoveEast),
+            (VirtualKeyCode::Numpad6, Action::MoveEast),
+            (VirtualKeyCode::Up, Action::MoveNorth),
+            (VirtualKeyCode::Numpad8, Action::MoveNorth),
+            (VirtualKeyCode::Down, Action::MoveSouth),
+            (VirtualKeyCode::Numpad2, Action::MoveSouth),
+            (VirtualKeyCode::Numpad9, Action::MoveNorthEast),
+            (VirtualKeyCode::Numpad7, Action::MoveNorthWest),
+            (VirtualKeyCode::Numpad1, Action::MoveSouthWest),
+            (VirtualKeyCode::Numpad3, Action::MoveSouthEast),
+            (VirtualKeyCode::G, Action::PickUp),
+            (VirtualKeyCode::I, Action::Inventory),
+            (VirtualKeyCode::D, Action::Drop),
+            (VirtualKeyCode::F, Action::Throw),
+            (VirtualKeyCode::R, Action::Remove),
+            (VirtualKeyCode::W, Action::EquipFromGround),
+            (VirtualKeyCode::E, Action::Interact),
+            (VirtualKeyCode::Period, Action::Descend),
+            (VirtualKeyCode::Comma, Action::Ascend),
+            (VirtualKeyCode::Numpad5, Action::Wait),
+            (VirtualKeyCode::Space, Action::Wait),
+            (VirtualKeyCode::Z, Action::Rest),
+            (VirtualKeyCode::S, Action::Search),
+            (VirtualKeyCode::A, Action::AutoExplore),
+            (VirtualKeyCode::Escape, Action::SaveMenu),
+            (VirtualKeyCode::Q, Action::Quit),
+            (VirtualKeyCode::T, Action::ToggleThreatColors),
+            (VirtualKeyCode::Tab, Action::CycleLogFilter),
+            (VirtualKeyCode::L, Action::ShowLog),
+        ];
+        KeyBindings { bindings: defaults.iter().copied().collect() }
+    }
+}
+
+impl KeyBindings {
+    // the bindings keybindings.json asks for, with what was wrong with it if anything,
+    // no file at all just means the defaults
+    pub fn load() -> (KeyBindings, Option<String>) {
+        match fs::read_to_string(KEYBINDINGS_FILE) {
+            Ok(data) => KeyBindings::from_json(&data),
+            Err(_) => (KeyBindings::default(), None),
+        }
+    }
+
+    // The defaults with data's overrides on top. A file that doesn't parse changes
+    // nothing, and keys it names that can't be bound are left out
+    pub fn from_json(data: &str) -> (KeyBindings, Option<String>) {
+        let mut bindings = KeyBindings::default();
+        let overrides: HashMap<Action, Vec<String>> = match serde_json::from_str(data) {
+            Ok(overrides) => overrides,
+            Err(error) => return (bindings, Some(format!("Couldn't read {}: {}", KEYBINDINGS_FILE, error))),
+        };
+
+        let mut unknown: Vec<String> = Vec::new();
+        for (action, names) in overrides.iter() {
+            let mut keys: Vec<VirtualKeyCode> = Vec::new();
+            for name in names.iter() {
+                match key_from_name(name) {
+                    Some(key) => keys.push(key),
+                    None => unknown.push(name.clone()),
+                }
+            }
+            bindings.rebind(*action, &keys);
+        }
+
+        if unknown.is_empty() {
+            (bindings, None)
+        } else {
+            (bindings, Some(format!("{} names keys that can't be bound: {}", KEYBINDINGS_FILE, unknown.join(", "))))
+        }
+    }
+
+    // action now only answers to keys, which are taken away from whatever they did before
+    pub fn rebind(&mut self, action: Action, keys: &[VirtualKeyCode]) {
+        self.bindings.retain(|_key, bound| *bound != action);
+        for key in keys.iter() {
+            self.bindings.insert(*key, action);
+        }
+    }
+
+    pub fn action_for(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_are_read_as_the_keys_of_the_file() {
+        let (bindings, problem) = KeyBindings::from_json(r#"{"PickUp": ["P"], "MoveNorth": ["W", "Up"]}"#);
+        assert!(problem.is_none());
+        assert_eq!(bindings.action_for(VirtualKeyCode::P), Some(Action::PickUp));
+        assert_eq!(bindings.action_for(VirtualKeyCode::G), None);
+        assert_eq!(bindings.action_for(VirtualKeyCode::W), Some(Action::MoveNorth));
+        assert_eq!(bindings.action_for(VirtualKeyCode::Numpad8), None);
+    }
+
+    #[test]
+    fn a_broken_file_keeps_the_defaults_and_says_why() {
+        let (bindings, problem) = KeyBindings::from_json(r#"{"PickUp": "P""#);
+        assert!(problem.is_some_and(|problem| problem.starts_with("Couldn't read")));
+        assert_eq!(bindings.action_for(VirtualKeyCode::G), Some(Action::PickUp));
+
+        let (_bindings, problem) = KeyBindings::from_json(r#"{"PickUp": ["Hyper"]}"#);
+        assert!(problem.is_some_and(|problem| problem.contains("Hyper")));
+    }
+}
@@ -1,7 +1,8 @@
-use gamelog::GameLog;
+use gamelog::{GameLog, LogCategory};
 use inventory_system::{InventorySystem, ItemDropSystem, ItemRemoveSystem, ItemUseSystem};
-use rltk::{GameState, Point, Rltk, VirtualKeyCode, RGB};
-use serde::*;
+use rltk::{GameState, Point, Rltk, RGB};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 use specs::prelude::*;
 use specs::saveload::{SimpleMarker, SimpleMarkerAllocator};
 
@@ -13,9 +14,13 @@ mod spawner;
 pub use components::*;
 
 mod map;
+mod camera;
 
 pub use map::*;
 
+mod map_builders;
+mod master_dungeon;
+
 mod player;
 
 use player::*;
@@ -29,6 +34,10 @@ mod visibility_system;
 use visibility_system::VisibilitySystem;
 
 mod monster_ai_system;
+mod pathfinding;
+mod search_system;
+mod trigger_system;
+mod particle_system;
 
 use monster_ai_system::MonsterAI;
 
@@ -41,27 +50,49 @@ mod melee_combat_system;
 use melee_combat_system::MeleeCombatSystem;
 
 mod damage_system;
+mod hunger_system;
+mod status_effect_system;
+mod initiative_system;
+mod identification;
 
 use crate::gui::MainMenuSelection;
 use damage_system::DamageSystem;
+use hunger_system::HungerSystem;
 
 mod gamelog;
 mod gui;
+mod keybindings;
 mod saveload_system;
+mod settings;
+#[cfg(test)]
+mod test_support;
+
+use settings::Settings;
 
 pub struct State {
     ecs: World,
 }
 
+// how far the player can throw a potion
+const THROW_RANGE: i32 = 6;
+
 #[derive(PartialEq, Copy, Clone)]
 pub enum RunState {
     AwaitingInput,
     PreRun,
     PlayerTurn,
     MonsterTurn,
+    Resting {
+        turns_left: i32,
+    },
+    AutoExploring,
     ShowInventory,
     ShowDropItem,
+    ShowThrowItem,
     ShowRemoveItem,
+    ShowIdentify {
+        scroll: Entity,
+    },
     ShowTargeting {
         range: i32,
         item: Entity,
@@ -69,22 +100,49 @@ pub enum RunState {
     MainMenu {
         menu_selection: gui::MainMenuSelection,
     },
-    SaveGame,
+    SaveSlotMenu {
+        saving: bool,
+    },
+    ConfirmQuit,
+    ConfirmLeave,
+    ShowLog {
+        scroll: usize,
+    },
     NextLevel,
+    PreviousLevel,
     GameOver,
 }
 
+// how many turns the player has taken this run
+pub struct TurnCount(pub u64);
+
 impl State {
     fn run_systems(&mut self) {
         let mut vis = VisibilitySystem {};
         vis.run_now(&self.ecs);
 
+        let mut initiative = initiative_system::InitiativeSystem {};
+        initiative.run_now(&self.ecs);
+
         let mut mob = MonsterAI {};
         mob.run_now(&self.ecs);
+        spawner::summon_minions(&mut self.ecs);
 
         let mut mapindex = MapIndexingSystem {};
         mapindex.run_now(&self.ecs);
 
+        let mut triggers = trigger_system::TriggerSystem {};
+        triggers.run_now(&self.ecs);
+
+        let mut search = search_system::SearchSystem {};
+        search.run_now(&self.ecs);
+
+        let mut hunger = HungerSystem {};
+        hunger.run_now(&self.ecs);
+
+        let mut status_effects = status_effect_system::StatusEffectSystem {};
+        status_effects.run_now(&self.ecs);
+
         let mut melee_comb_system = MeleeCombatSystem {};
         melee_comb_system.run_now(&self.ecs);
 
@@ -103,73 +161,61 @@ impl State {
         let mut remove_item = ItemRemoveSystem {};
         remove_item.run_now(&self.ecs);
 
+        let mut particles = particle_system::ParticleSpawnSystem {};
+        particles.run_now(&self.ecs);
+
         self.ecs.maintain(); // apply any changes queued up by the systems
     }
 
-    fn entities_to_remove_on_level_change(&mut self) -> Vec<Entity> {
-        let entities = self.ecs.entities();
-        let player = self.ecs.read_storage::<Player>();
-        let backpack = self.ecs.read_storage::<InBackpack>();
-        let player_entity = self.ecs.fetch::<Entity>();
-        let equipped = self.ecs.read_storage::<Equipped>();
-
-        let mut to_delete: Vec<Entity> = Vec::new();
-        for entity in entities.join() {
-            let mut should_delete = true;
-
-            // don't delete player
-            let p = player.get(entity);
-            if let Some(_p) = p {
-                should_delete = false;
-            }
-
-            // don't delete player's items
-            let bp = backpack.get(entity);
-            if let Some(bp) = bp {
-                if bp.owner == *player_entity {
-                    should_delete = false;
-                }
-            }
-
-            let eq = equipped.get(entity);
-            if let Some(eq) = eq {
-                if eq.owner == *player_entity {
-                    should_delete = false;
-                }
-            }
-
-            if should_delete {
-                to_delete.push(entity);
-            }
-        }
+    fn go_to_next_level(&mut self) {
+        self.change_level(1);
+    }
 
-        to_delete
+    fn go_to_previous_level(&mut self) {
+        self.change_level(-1);
     }
 
-    fn go_to_next_level(&mut self) {
-        let to_delete = self.entities_to_remove_on_level_change();
-        for target in to_delete {
-            self.ecs
-                .delete_entity(target)
-                .expect("Unable to delete entity");
-        }
+    // Leaves the current level for the one delta below it. Levels already visited
+    // come back as they were left, with the player on the stairs that lead back
+    fn change_level(&mut self, delta: i32) {
+        master_dungeon::freeze_level_entities(&mut self.ecs);
 
-        //Build new worldmap and place player
-        let worldmap;
+        let new_depth = self.ecs.fetch::<Map>().depth + delta;
         {
-            let mut worldmap_resource = self.ecs.write_resource::<Map>();
-            let current_depth = worldmap_resource.depth;
-            *worldmap_resource = Map::new_map_rooms_and_corridors(current_depth + 1);
-            worldmap = worldmap_resource.clone();
+            let mut stats = self.ecs.write_resource::<Stats>();
+            stats.deepest_level = i32::max(stats.deepest_level, new_depth);
         }
+        let visited = {
+            let mut dungeon = self.ecs.write_resource::<master_dungeon::MasterDungeon>();
+            dungeon.store_map(&self.ecs.fetch::<Map>());
+            dungeon.take_map(new_depth)
+        };
+
+        let first_visit = visited.is_none();
+        let (player_x, player_y) = match visited {
+            Some(map) => {
+                let stairs = if delta > 0 { TileType::UpStairs } else { TileType::DownStairs };
+                let arrival = map.arrival_point(stairs);
+                *self.ecs.write_resource::<Map>() = map;
+                master_dungeon::thaw_level_entities(&mut self.ecs, new_depth);
+                arrival
+            }
+            None => {
+                let builder = {
+                    let mut rng = self.ecs.write_resource::<rltk::RandomNumberGenerator>();
+                    let generator = self.ecs.fetch::<Settings>().map_generator;
+                    map_builders::build_validated_level(new_depth, generator, &mut rng)
+                };
+                let Position{ x, y } = builder.starting_position();
+                *self.ecs.write_resource::<Map>() = map_builders::map_with_up_stairs(builder.as_ref());
 
-        // spawn monsters and items
-        for room in worldmap.rooms.iter().skip(1) {
-            spawner::spawn_room(&mut self.ecs, room, worldmap.depth);
-        }
+                // spawn monsters and items
+                builder.spawn_entities(&mut self.ecs);
+                (x, y)
+            }
+        };
 
         //Place player and update resources
-        let (player_x, player_y) = worldmap.rooms[0].center();
         let mut player_position = self.ecs.write_resource::<Point>();
         *player_position = Point::new(player_x, player_y);
         let mut position_components = self.ecs.write_storage::<Position>();
@@ -187,11 +233,19 @@ impl State {
             vs.dirty = true;
         }
 
-        //notify player and give them some health
+        // only a level never seen before is worth a rest, or the stairs would heal forever
         let mut gamelog = self.ecs.fetch_mut::<GameLog>();
-        gamelog
-            .entries
-            .push("You descend to the next level, and take a moment to heal.".to_string());
+        if !first_visit {
+            let verb = if delta > 0 { "descend" } else { "climb" };
+            gamelog.push(LogCategory::System, format!("You {} back to level {}.", verb, new_depth));
+            return;
+        }
+
+        //notify player and give them some health
+        gamelog.push(
+            LogCategory::System,
+            format!("You descend to level {}, and take a moment to heal.", new_depth),
+        );
         let mut player_health_store = self.ecs.write_storage::<CombatStats>();
         let player_health = player_health_store.get_mut(*player_entity);
         if let Some(player_health) = player_health {
@@ -199,6 +253,36 @@ impl State {
         }
     }
 
+    // Monster turns, each one initiative tick, until the player is up again. Hasted
+    // that's after a single tick, slowed it takes a few with monsters acting in each
+    fn run_monster_turns(&mut self) {
+        loop {
+            *self.ecs.write_resource::<RunState>() = RunState::MonsterTurn;
+            self.run_systems();
+
+            let player_entity = *self.ecs.fetch::<Entity>();
+            let ready = self.ecs.read_storage::<MyTurn>().get(player_entity).is_some()
+                || self.ecs.read_storage::<Initiative>().get(player_entity).is_none();
+            if ready {
+                return;
+            }
+            damage_system::delete_the_dead(&mut self.ecs);
+        }
+    }
+
+    // whatever the player chose to do this turn plays out, and the clock moves on
+    fn player_turn(&mut self) {
+        self.advance_turn();
+        self.run_systems();
+        self.ecs.maintain();
+    }
+
+    fn advance_turn(&mut self) {
+        let mut turns = self.ecs.write_resource::<TurnCount>();
+        turns.0 += 1;
+        self.ecs.write_resource::<GameLog>().turn = turns.0;
+    }
+
     fn game_over_cleanup(&mut self) {
         // Delete all the things
         let mut to_delete = Vec::new();
@@ -209,21 +293,28 @@ impl State {
             self.ecs.delete_entity(*del).expect("Deletion failed");
         }
 
+        // a new run gets a new seed, unless the settings pin one
+        let rng = run_rng(&self.ecs.fetch::<Settings>());
+        *self.ecs.write_resource::<rltk::RandomNumberGenerator>() = rng;
+
         // make new map and place player
-        let worldmap;
+        let builder;
         {
             let mut worldmap_resource = self.ecs.write_resource::<Map>();
-            *worldmap_resource = Map::new_map_rooms_and_corridors(1);
-            worldmap = worldmap_resource.clone();
+            let mut rng = self.ecs.write_resource::<rltk::RandomNumberGenerator>();
+            let generator = self.ecs.fetch::<Settings>().map_generator;
+            builder = map_builders::build_validated_level(1, generator, &mut rng);
+            *worldmap_resource = map_builders::map_with_up_stairs(builder.as_ref());
+            // a new run, so potions look different again
+            *self.ecs.write_resource::<identification::MagicItemMasks>() = identification::MagicItemMasks::new(&mut rng);
+            *self.ecs.write_resource::<master_dungeon::MasterDungeon>() = master_dungeon::MasterDungeon::default();
         }
 
         //Spawn monsters and items
-        for room in worldmap.rooms.iter().skip(1) {
-            spawner::spawn_room(&mut self.ecs, room, worldmap.depth);
-        }
+        builder.spawn_entities(&mut self.ecs);
 
         //Place player and update resources
-        let (player_x, player_y) = worldmap.rooms[0].center();
+        let Position{ x: player_x, y: player_y } = builder.starting_position();
         let player_entity = spawner::spawn_player(&mut self.ecs, player_x, player_y);
         let mut player_position = self.ecs.write_resource::<Point>();
         *player_position = Point::new(player_x, player_y);
@@ -236,6 +327,16 @@ impl State {
             player_pos_comp.y = player_y;
         }
 
+        // the new run starts counting from scratch
+        self.ecs.write_resource::<TurnCount>().0 = 0;
+        *self.ecs.write_resource::<Stats>() = Stats::new_run();
+        let filter = self.ecs.fetch::<GameLog>().filter;
+        let mut gamelog = GameLog::new();
+        gamelog.filter = filter;
+        gamelog.push(LogCategory::System, "Welcome to Rusty Roguelike");
+        *self.ecs.write_resource::<GameLog>() = gamelog;
+        *self.ecs.write_resource::<saveload_system::SavedAtTurn>() = saveload_system::SavedAtTurn(None);
+
         // mark player's vis as dirty
         let mut viewshed_components = self.ecs.write_storage::<Viewshed>();
         let vs = viewshed_components.get_mut(player_entity);
@@ -244,11 +345,88 @@ impl State {
         }
 
     }
+
+    // closing the window mid-run suspends it instead of throwing it away
+    fn save_before_quit(&mut self) {
+        let runstate = *self.ecs.fetch::<RunState>();
+        let in_game = !matches!(
+            runstate,
+            RunState::MainMenu { .. } | RunState::SaveSlotMenu { saving: false } | RunState::GameOver
+        );
+        if !in_game {
+            return;
+        }
+
+        // with permadeath a dead character must not come back through the autosave
+        let player_dead = {
+            let player_entity = self.ecs.fetch::<Entity>();
+            let combat_stats = self.ecs.read_storage::<CombatStats>();
+            combat_stats.get(*player_entity).is_none_or(|stats| stats.hp < 1)
+        };
+        if player_dead && self.ecs.fetch::<Settings>().permadeath {
+            return;
+        }
+
+        let saved = saveload_system::save_game(&mut self.ecs, saveload_system::SaveSlot::Autosave);
+        self.report_file_error(saved, "save the game");
+    }
+
+    // a save that can't be written or removed isn't worth ending the run over, it gets a line in the log
+    fn report_file_error(&mut self, result: std::io::Result<()>, what: &str) {
+        if let Err(error) = result {
+            self.ecs.write_resource::<GameLog>().push_colored(
+                LogCategory::System,
+                format!("Couldn't {}: {}", what, error),
+                RGB::named(rltk::RED),
+            );
+        }
+    }
+
+    // loads slot and says where to go next, back to the main menu with the reason if it failed
+    fn load_from(&mut self, slot: saveload_system::SaveSlot) -> RunState {
+        match saveload_system::load_game(&mut self.ecs, slot) {
+            Ok(()) => {
+                self.ecs.write_resource::<saveload_system::LastLoadError>().0 = None;
+                if self.ecs.fetch::<Settings>().permadeath {
+                    let run_id = self.ecs.fetch::<Stats>().run_id;
+                    let deleted = saveload_system::delete_run(slot, run_id);
+                    self.report_file_error(deleted, "remove the loaded save");
+                    *self.ecs.write_resource::<saveload_system::SavedAtTurn>() =
+                        saveload_system::SavedAtTurn(None);
+                }
+                RunState::AwaitingInput
+            }
+            Err(error) => {
+                // a damaged file may have got part way in, so start from a clean world,
+                // which comes with a fresh log, so the reason goes in after that
+                if error.world_touched() {
+                    self.game_over_cleanup();
+                }
+                self.ecs.write_resource::<GameLog>().push_colored(LogCategory::System, error.message(), RGB::named(rltk::RED));
+                self.ecs.write_resource::<saveload_system::LastLoadError>().0 = Some(error);
+                RunState::MainMenu {
+                    menu_selection: gui::MainMenuSelection::NewGame,
+                }
+            }
+        }
+    }
 }
 
 impl GameState for State {
     fn tick(&mut self, ctx: &mut Rltk) {
-        
+        // the close button only reaches us through the event queue
+        let mut close_requested = false;
+        rltk::INPUT.lock().for_each_message(|event| {
+            if let rltk::BEvent::CloseRequested = event {
+                close_requested = true;
+            }
+        });
+        if close_requested {
+            self.save_before_quit();
+            ctx.quit();
+            return;
+        }
+
         let mut new_runstate;
         {
             let runstate = self.ecs.fetch::<RunState>();
@@ -256,28 +434,104 @@ impl GameState for State {
         }
 
         ctx.cls();
+        particle_system::cull_dead_particles(&mut self.ecs, ctx);
 
         match new_runstate {
             RunState::MainMenu { .. } => {}
-            RunState::GameOver { .. } => {}
+            RunState::SaveSlotMenu { saving: false } => {}
+            RunState::GameOver => {}
             _ => {
                 draw_map(&self.ecs, ctx);
 
                 {
                     damage_system::delete_the_dead(&mut self.ecs);
+                    let entities = self.ecs.entities();
                     let positions = self.ecs.read_storage::<Position>();
                     let renderables = self.ecs.read_storage::<Renderable>();
+                    let equipped = self.ecs.read_storage::<Equipped>();
+                    let combat_stats = self.ecs.read_storage::<CombatStats>();
+                    let defense_bonuses = self.ecs.read_storage::<DefenseBonus>();
+                    let monsters = self.ecs.read_storage::<Monster>();
+                    let sizes = self.ecs.read_storage::<Size>();
+                    let items = self.ecs.read_storage::<Item>();
+                    let confused = self.ecs.read_storage::<Confusion>();
+                    let hidden = self.ecs.read_storage::<Hidden>();
+                    let player_entity = self.ecs.fetch::<Entity>();
+                    let settings = self.ecs.fetch::<Settings>();
                     let map = self.ecs.fetch::<Map>();
+                    let origin = camera::current_origin(&self.ecs);
+
+                    // the player takes on the colour of whatever weapon they're wielding
+                    let weapon_fg = (&equipped, &renderables)
+                        .join()
+                        .find(|(eq, _render)| {
+                            eq.owner == *player_entity && eq.slot == EquipmentSlot::Melee
+                        })
+                        .map(|(_eq, render)| render.fg);
+
+                    let player_stats = combat_stats.get(*player_entity);
+                    let player_defense_bonus: i32 = (&equipped, &defense_bonuses)
+                        .join()
+                        .filter(|(eq, _bonus)| eq.owner == *player_entity)
+                        .map(|(_eq, bonus)| bonus.defense)
+                        .sum();
+
+                    // more than one item on a tile is drawn as a pile
+                    let mut item_counts: HashMap<usize, i32> = HashMap::new();
+                    for (_item, pos) in (&items, &positions).join() {
+                        *item_counts.entry(map.xy_idx(pos.x, pos.y)).or_insert(0) += 1;
+                    }
+
+                    let mut data = (&entities, &positions, &renderables, !&hidden)
+                        .join()
+                        .map(|(entity, pos, render, _hidden)| (entity, pos, render))
+                        .collect::<Vec<_>>();
+                    data.sort_by_key(|&a| std::cmp::Reverse(a.2.render_order));
+                    for (entity, pos, render) in data.iter() {
+                        let mut fg = render.fg;
+                        if *entity == *player_entity {
+                            fg = weapon_fg.unwrap_or(fg);
+                        }
+                        if settings.threat_colors && monsters.get(*entity).is_some() {
+                            if let (Some(monster_stats), Some(player_stats)) =
+                                (combat_stats.get(*entity), player_stats)
+                            {
+                                fg = gui::threat_color(
+                                    fg,
+                                    monster_stats,
+                                    player_stats,
+                                    player_defense_bonus,
+                                );
+                            }
+                        }
+
+                        // confused creatures stand out whatever else tints them,
+                        // scrolls carry Confusion too but aren't creatures
+                        if confused.get(*entity).is_some() && combat_stats.get(*entity).is_some() {
+                            fg = RGB::named(rltk::MAGENTA);
+                        }
+
+                        let mut glyph = render.glyph;
+                        if items.get(*entity).is_some()
+                            && item_counts.get(&map.xy_idx(pos.x, pos.y)).copied().unwrap_or(0) > 1
+                        {
+                            glyph = rltk::to_cp437('&');
+                            fg = RGB::named(rltk::WHITE);
+                        }
 
-                    let mut data = (&positions, &renderables).join().collect::<Vec<_>>();
-                    data.sort_by(|&a, &b| b.1.render_order.cmp(&a.1.render_order));
-                    for (pos, render) in data.iter() {
-                        let idx = map.xy_idx(pos.x, pos.y);
-                        if map.visible_tiles[idx] {
-                            ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph);
+                        // large creatures are drawn on every tile they cover
+                        for tile in footprint(sizes.get(*entity), pos.x, pos.y) {
+                            let idx = map.xy_idx(tile.x, tile.y);
+                            if !map.visible_tiles[idx] {
+                                continue;
+                            }
+                            if let Some(screen) = camera::map_to_screen(origin, tile) {
+                                ctx.set(screen.x, screen.y, fg, render.bg, glyph);
+                            }
                         }
                     }
 
+                    gui::draw_entity_health_bars(&self.ecs, ctx);
                     gui::draw_ui(&self.ecs, ctx);
                 }
             }
@@ -293,15 +547,47 @@ impl GameState for State {
                 new_runstate = player_input(self, ctx);
             }
             RunState::PlayerTurn => {
-                self.run_systems();
-                self.ecs.maintain();
+                self.player_turn();
                 new_runstate = RunState::MonsterTurn;
             }
             RunState::MonsterTurn => {
-                self.run_systems();
-                self.ecs.maintain();
+                self.run_monster_turns();
                 new_runstate = RunState::AwaitingInput;
             }
+            RunState::Resting { turns_left } => {
+                match rest_interruption(&self.ecs, turns_left) {
+                    Some(reason) => {
+                        self.ecs.fetch_mut::<GameLog>().push(LogCategory::System, reason);
+                        new_runstate = RunState::AwaitingInput;
+                    }
+                    None => {
+                        // a full player turn and monster turn for every turn spent resting
+                        self.advance_turn();
+                        skip_turn(&mut self.ecs);
+                        self.run_systems();
+                        self.run_monster_turns();
+                        new_runstate = RunState::Resting {
+                            turns_left: turns_left - 1,
+                        };
+                    }
+                }
+            }
+            RunState::AutoExploring => {
+                match explore_step(&self.ecs) {
+                    Err(reason) => {
+                        self.ecs.fetch_mut::<GameLog>().push(LogCategory::System, reason);
+                        new_runstate = RunState::AwaitingInput;
+                    }
+                    Ok((dx, dy)) => {
+                        // every step is an ordinary player turn followed by the monsters' turn
+                        try_move_player(dx, dy, &mut self.ecs);
+                        self.advance_turn();
+                        *self.ecs.write_resource::<RunState>() = RunState::PlayerTurn;
+                        self.run_systems();
+                        self.run_monster_turns();
+                    }
+                }
+            }
             RunState::ShowInventory => {
                 let result = gui::show_inventory(self, ctx);
                 match result.0 {
@@ -311,7 +597,9 @@ impl GameState for State {
                         let item_entity = result.1.unwrap();
                         let ranged = self.ecs.read_storage::<Ranged>();
                         let is_item_ranged = ranged.get(item_entity);
-                        if let Some(is_item_ranged) = is_item_ranged {
+                        if self.ecs.read_storage::<Identify>().get(item_entity).is_some() {
+                            new_runstate = RunState::ShowIdentify { scroll: item_entity };
+                        } else if let Some(is_item_ranged) = is_item_ranged {
                             new_runstate = RunState::ShowTargeting {
                                 range: is_item_ranged.range,
                                 item: item_entity,
@@ -332,6 +620,19 @@ impl GameState for State {
                     }
                 }
             }
+            RunState::ShowThrowItem => {
+                let result = gui::show_throw_item_menu(self, ctx);
+                match result.0 {
+                    gui::ItemMenuResult::Cancel => new_runstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected => {
+                        new_runstate = RunState::ShowTargeting {
+                            range: THROW_RANGE,
+                            item: result.1.unwrap(),
+                        };
+                    }
+                }
+            }
             RunState::ShowDropItem => {
                 let result = gui::show_drop_item_menu(self, ctx);
                 match result.0 {
@@ -350,6 +651,25 @@ impl GameState for State {
                     }
                 }
             }
+            RunState::ShowIdentify { scroll } => {
+                let result = gui::show_identify_menu(self, ctx);
+                match result.0 {
+                    gui::ItemMenuResult::Cancel => new_runstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected => {
+                        let player_entity = *self.ecs.fetch::<Entity>();
+                        self.ecs
+                            .write_storage::<WantsToIdentify>()
+                            .insert(player_entity, WantsToIdentify { item: result.1.unwrap() })
+                            .expect("Unable to insert intent");
+                        self.ecs
+                            .write_storage::<WantsToUseItem>()
+                            .insert(player_entity, WantsToUseItem { item: scroll, target: None })
+                            .expect("Unable to insert intent");
+                        new_runstate = RunState::PlayerTurn;
+                    }
+                }
+            }
             RunState::ShowRemoveItem => {
                 let result = gui::remove_item_menu(self, ctx);
                 match result.0 {
@@ -369,7 +689,7 @@ impl GameState for State {
                 }
             }
             RunState::ShowTargeting { range, item } => {
-                let result = gui::ranged_target(self, ctx, range);
+                let result = gui::ranged_target(self, ctx, range, item);
                 match result.0 {
                     gui::ItemMenuResult::Cancel => new_runstate = RunState::AwaitingInput,
                     gui::ItemMenuResult::NoResponse => {}
@@ -397,11 +717,12 @@ impl GameState for State {
                         }
                     }
                     gui::MainMenuResult::Selected { selected } => match selected {
-                        gui::MainMenuSelection::NewGame => new_runstate = RunState::PreRun,
+                        gui::MainMenuSelection::NewGame => {
+                            self.ecs.write_resource::<saveload_system::LastLoadError>().0 = None;
+                            new_runstate = RunState::PreRun;
+                        }
                         gui::MainMenuSelection::LoadGame => {
-                            saveload_system::load_game(&mut self.ecs);
-                            new_runstate = RunState::AwaitingInput;
-                            saveload_system::delete_save();
+                            new_runstate = RunState::SaveSlotMenu { saving: false };
                         }
                         gui::MainMenuSelection::Quit => {
                             std::process::exit(0);
@@ -410,29 +731,128 @@ impl GameState for State {
                 }
             }
             RunState::GameOver => {
-                let result = gui::game_over(ctx);
+                let result = gui::game_over(self, ctx);
                 match result {
                     gui::GameOverResult::NoSelection => {},
                     gui::GameOverResult::QuitToMenu => {
+                        // a dead run can't be picked up again from its autosave
+                        let deleted = if self.ecs.fetch::<Settings>().permadeath {
+                            saveload_system::delete_save(saveload_system::SaveSlot::Autosave)
+                        } else {
+                            Ok(())
+                        };
                         self.game_over_cleanup();
+                        self.report_file_error(deleted, "remove the autosave");
                         new_runstate = RunState::MainMenu{
                             menu_selection: gui::MainMenuSelection::NewGame
                         }
                     }
                 }
             }
-            RunState::SaveGame => {
-                saveload_system::save_game(&mut self.ecs);
-
-                new_runstate = RunState::MainMenu {
-                    menu_selection: gui::MainMenuSelection::LoadGame,
+            RunState::ConfirmQuit => {
+                // nothing is lost if the last save is as recent as the last turn
+                let saved = self.ecs.fetch::<saveload_system::SavedAtTurn>().0
+                    == Some(self.ecs.fetch::<TurnCount>().0);
+                let result = if saved {
+                    gui::ConfirmResult::Confirmed
+                } else {
+                    gui::confirm_quit(ctx)
                 };
+                match result {
+                    gui::ConfirmResult::NoResponse => {}
+                    gui::ConfirmResult::Cancelled => new_runstate = RunState::AwaitingInput,
+                    gui::ConfirmResult::Confirmed => {
+                        self.game_over_cleanup();
+                        new_runstate = RunState::MainMenu {
+                            menu_selection: if saveload_system::save_exists() {
+                                gui::MainMenuSelection::LoadGame
+                            } else {
+                                gui::MainMenuSelection::NewGame
+                            },
+                        };
+                    }
+                }
+            }
+            RunState::ShowLog { scroll } => {
+                match gui::show_log(&self.ecs, ctx, scroll) {
+                    gui::LogViewResult::NoResponse => {}
+                    gui::LogViewResult::Close => new_runstate = RunState::AwaitingInput,
+                    gui::LogViewResult::Scroll(scroll) => new_runstate = RunState::ShowLog { scroll },
+                }
+            }
+            RunState::SaveSlotMenu { saving } => {
+                let listing = self
+                    .ecs
+                    .write_resource::<saveload_system::SlotListing>()
+                    .0
+                    .get_or_insert_with(saveload_system::SlotListing::read)
+                    .clone();
+                let choice = gui::save_slot_menu(ctx, saving, &listing);
+                // whatever was picked may change what's in the slots, so they're read again next time
+                if choice.0 != gui::ItemMenuResult::NoResponse {
+                    self.ecs.write_resource::<saveload_system::SlotListing>().0 = None;
+                }
+                match choice {
+                    (gui::ItemMenuResult::NoResponse, _) => {}
+                    (gui::ItemMenuResult::Cancel, _) => {
+                        new_runstate = if saving {
+                            RunState::AwaitingInput
+                        } else {
+                            RunState::MainMenu { menu_selection: gui::MainMenuSelection::LoadGame }
+                        };
+                    }
+                    (gui::ItemMenuResult::Selected, Some(slot)) if saving => {
+                        // a failed save leaves the player in the game rather than losing the run
+                        match saveload_system::save_game(&mut self.ecs, slot) {
+                            Ok(()) => {
+                                new_runstate = RunState::MainMenu {
+                                    menu_selection: gui::MainMenuSelection::LoadGame,
+                                };
+                            }
+                            Err(error) => {
+                                self.report_file_error(Err(error), "save the game");
+                                new_runstate = RunState::AwaitingInput;
+                            }
+                        }
+                    }
+                    (gui::ItemMenuResult::Selected, Some(slot)) => new_runstate = self.load_from(slot),
+                    (gui::ItemMenuResult::Selected, None) => {}
+                }
             }
             RunState::NextLevel => {
                 self.go_to_next_level();
+                if self.ecs.fetch::<Settings>().autosave_on_descent {
+                    let saved = saveload_system::save_game(&mut self.ecs, saveload_system::SaveSlot::Autosave);
+                    self.report_file_error(saved, "autosave");
+                }
                 new_runstate = RunState::PreRun;
             }
-            
+            RunState::PreviousLevel => {
+                // the stairs on the first level lead out of the dungeon
+                if self.ecs.fetch::<Map>().depth <= 1 {
+                    new_runstate = RunState::ConfirmLeave;
+                } else {
+                    self.go_to_previous_level();
+                    new_runstate = RunState::PreRun;
+                }
+            }
+            RunState::ConfirmLeave => match gui::confirm_leave(ctx) {
+                gui::ConfirmResult::NoResponse => {}
+                gui::ConfirmResult::Cancelled => new_runstate = RunState::AwaitingInput,
+                gui::ConfirmResult::Confirmed => {
+                    // walking out ends the run just like dying does
+                    let deleted = if self.ecs.fetch::<Settings>().permadeath {
+                        saveload_system::delete_save(saveload_system::SaveSlot::Autosave)
+                    } else {
+                        Ok(())
+                    };
+                    self.game_over_cleanup();
+                    self.report_file_error(deleted, "remove the autosave");
+                    new_runstate = RunState::MainMenu {
+                        menu_selection: gui::MainMenuSelection::NewGame,
+                    };
+                }
+            },
         }
         {
             let mut runwriter = self.ecs.write_resource::<RunState>();
@@ -443,6 +863,97 @@ impl GameState for State {
     }
 }
 
+fn register_components(ecs: &mut World) {
+    ecs.register::<Position>();
+    ecs.register::<Renderable>();
+    ecs.register::<Player>();
+    ecs.register::<Viewshed>();
+    ecs.register::<Monster>();
+    ecs.register::<Name>();
+    ecs.register::<BlocksTile>();
+    ecs.register::<BlocksVision>();
+    ecs.register::<Faction>();
+    ecs.register::<Tags>();
+    ecs.register::<Thorns>();
+    ecs.register::<IgnoresWalls>();
+    ecs.register::<Size>();
+    ecs.register::<Durability>();
+    ecs.register::<AggroRange>();
+    ecs.register::<RangedAttack>();
+    ecs.register::<CanFlee>();
+    ecs.register::<HungerClock>();
+    ecs.register::<ProvidesFood>();
+    ecs.register::<ParticleLifetime>();
+    ecs.register::<EntityMoved>();
+    ecs.register::<EntryTrigger>();
+    ecs.register::<Hidden>();
+    ecs.register::<SingleActivation>();
+    ecs.register::<WantsToSearch>();
+    ecs.register::<Experience>();
+    ecs.register::<DamageOverTime>();
+    ecs.register::<Venomous>();
+    ecs.register::<Regeneration>();
+    ecs.register::<GrantsRegeneration>();
+    ecs.register::<MagicMapper>();
+    ecs.register::<Teleport>();
+    ecs.register::<ObfuscatedName>();
+    ecs.register::<Identify>();
+    ecs.register::<WantsToIdentify>();
+    ecs.register::<LightSource>();
+    ecs.register::<OtherLevelPosition>();
+    ecs.register::<Initiative>();
+    ecs.register::<MyTurn>();
+    ecs.register::<ProvidesHaste>();
+    ecs.register::<Hasted>();
+    ecs.register::<Slowed>();
+    ecs.register::<Wallet>();
+    ecs.register::<GoldDrop>();
+    ecs.register::<Gold>();
+    ecs.register::<Weight>();
+    ecs.register::<TwoHanded>();
+    ecs.register::<Summoner>();
+    ecs.register::<WantsToSummon>();
+    ecs.register::<Summoned>();
+    ecs.register::<Rooted>();
+    ecs.register::<Resistances>();
+    ecs.register::<Vulnerabilities>();
+    ecs.register::<CritChance>();
+    ecs.register::<CombatStats>();
+    ecs.register::<WantsToMelee>();
+    ecs.register::<SufferDamage>();
+    ecs.register::<Item>();
+    ecs.register::<Consumable>();
+    ecs.register::<ProvidesHealing>();
+    ecs.register::<InBackpack>();
+    ecs.register::<WantsToPickUpItem>();
+    ecs.register::<WantsToUseItem>();
+    ecs.register::<WantsToDropItem>();
+    ecs.register::<WantsToRemoveItem>();
+    ecs.register::<Ranged>();
+    ecs.register::<InflictsDamage>();
+    ecs.register::<AreaOfEffect>();
+    ecs.register::<Confusion>();
+    ecs.register::<HarmsCaster>();
+    ecs.register::<ShattersOnThrow>();
+    ecs.register::<SimpleMarker<SerializeMe>>();
+    ecs.register::<SerializationHelper>();
+    ecs.register::<Equippable>();
+    ecs.register::<Equipped>();
+    ecs.register::<MeleePowerBonus>();
+    ecs.register::<DefenseBonus>();
+    ecs.register::<Fountain>();
+    ecs.register::<Cursed>();
+}
+
+// Every run's random numbers come from one seed, which settings.json can fix
+// to play the same dungeon again. Without one it's taken from the clock
+fn run_rng(settings: &Settings) -> rltk::RandomNumberGenerator {
+    let seed = settings.seed.unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64)
+    });
+    rltk::RandomNumberGenerator::seeded(seed)
+}
+
 fn main() -> rltk::BError {
     use rltk::RltkBuilder;
 
@@ -450,55 +961,39 @@ fn main() -> rltk::BError {
         .with_title("Roguelike Tutorial")
         .build()?;
     context.with_post_scanlines(true);
+    // lets tick see the window being closed so the run can be saved first
+    rltk::INPUT.lock().activate_event_queue();
 
     let mut gs = State { ecs: World::new() };
 
-    // Components
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<Renderable>();
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<Viewshed>();
-    gs.ecs.register::<Monster>();
-    gs.ecs.register::<Name>();
-    gs.ecs.register::<BlocksTile>();
-    gs.ecs.register::<CombatStats>();
-    gs.ecs.register::<WantsToMelee>();
-    gs.ecs.register::<SufferDamage>();
-    gs.ecs.register::<Item>();
-    gs.ecs.register::<Consumable>();
-    gs.ecs.register::<ProvidesHealing>();
-    gs.ecs.register::<InBackpack>();
-    gs.ecs.register::<WantsToPickUpItem>();
-    gs.ecs.register::<WantsToUseItem>();
-    gs.ecs.register::<WantsToDropItem>();
-    gs.ecs.register::<WantsToRemoveItem>();
-    gs.ecs.register::<Ranged>();
-    gs.ecs.register::<InflictsDamage>();
-    gs.ecs.register::<AreaOfEffect>();
-    gs.ecs.register::<Confusion>();
-    gs.ecs.register::<SimpleMarker<SerializeMe>>();
-    gs.ecs.register::<SerializationHelper>();
-    gs.ecs.register::<Equippable>();
-    gs.ecs.register::<Equipped>();
-    gs.ecs.register::<MeleePowerBonus>();
-    gs.ecs.register::<DefenseBonus>();
+    register_components(&mut gs.ecs);
 
     // this has to be inserted before map usage
     gs.ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
 
-    let map: Map = Map::new_map_rooms_and_corridors(1);
-    let (player_x, player_y) = map.rooms[0].center(); //make player spawn in center of "first" room
+    // read before the first level is built, it may say how to build it
+    let (settings, settings_problem) = Settings::load();
+    gs.ecs.insert(settings);
+    let (bindings, bindings_problem) = keybindings::KeyBindings::load();
+    gs.ecs.insert(bindings);
+    // so the main menu can offer a save from before there were slots
+    saveload_system::migrate_legacy_save();
+
+    // has to be inserted before the map and rooms are generated
+    let rng = run_rng(&gs.ecs.fetch::<Settings>());
+    gs.ecs.insert(rng);
+    let masks = identification::MagicItemMasks::new(&mut gs.ecs.write_resource::<rltk::RandomNumberGenerator>());
+    gs.ecs.insert(masks);
+    gs.ecs.insert(master_dungeon::MasterDungeon::default());
+    let generator = gs.ecs.fetch::<Settings>().map_generator;
+    let builder = map_builders::build_validated_level(1, generator, &mut gs.ecs.write_resource::<rltk::RandomNumberGenerator>());
+    let map: Map = map_builders::map_with_up_stairs(builder.as_ref());
+    let Position{ x: player_x, y: player_y } = builder.starting_position();
 
     let player_entity = spawner::spawn_player(&mut gs.ecs, player_x, player_y);
 
-    // has to be inserted before rooms are spawned
-    gs.ecs.insert(rltk::RandomNumberGenerator::new());
 
-    // skip the first room to avoid the player
-    // spawning on a mob
-    for room in map.rooms.iter().skip(1) {
-        spawner::spawn_room(&mut gs.ecs, room, map.depth);
-    }
+    builder.spawn_entities(&mut gs.ecs);
 
     
     gs.ecs.insert(map);
@@ -507,9 +1002,37 @@ fn main() -> rltk::BError {
     gs.ecs.insert(RunState::MainMenu {
         menu_selection: MainMenuSelection::NewGame,
     });
-    gs.ecs.insert(gamelog::GameLog {
-        entries: vec!["Welcome to Rusty Roguelike".to_string()],
-    });
+    let mut gamelog = GameLog::new();
+    gamelog.push(LogCategory::System, "Welcome to Rusty Roguelike");
+    // broken config files fall back to the defaults, but the player should know why
+    for problem in settings_problem.into_iter().chain(bindings_problem) {
+        gamelog.push_colored(LogCategory::System, problem, RGB::named(rltk::RED));
+    }
+    gs.ecs.insert(gamelog);
+    gs.ecs.insert(TurnCount(0));
+    gs.ecs.insert(Stats::new_run());
+    gs.ecs.insert(saveload_system::SavedAtTurn(None));
+    gs.ecs.insert(saveload_system::LastLoadError(None));
+    gs.ecs.insert(saveload_system::SlotListing(None));
+    gs.ecs.insert(particle_system::ParticleBuilder::new());
 
     rltk::main_loop(context, gs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{open_map, test_world};
+
+    #[test]
+    fn a_player_turn_moves_the_clock_on_once() {
+        let (ecs, _player) = test_world(open_map(), 10, 10);
+        let mut gs = State { ecs };
+
+        gs.player_turn();
+        assert_eq!(gs.ecs.fetch::<TurnCount>().0, 1);
+        gs.player_turn();
+        assert_eq!(gs.ecs.fetch::<TurnCount>().0, 2);
+        assert_eq!(gs.ecs.fetch::<GameLog>().turn, 2);
+    }
+}
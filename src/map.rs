@@ -1,21 +1,39 @@
-use crate::{Player, Viewshed};
-
-use super::Rect;
+use super::{
+    camera::{screen_to_map, view_origin, VIEW_HEIGHT, VIEW_WIDTH},
+    visibility_system::carried_light,
+    Equipped, InBackpack, LightSource, Rect, Settings,
+};
 use rltk::{Algorithm2D, BaseMap, Point, RandomNumberGenerator, Rltk, RGB};
 use specs::prelude::*;
 use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
 
 pub const MAPWIDTH: usize = 80;
 pub const MAPHEIGHT: usize = 43;
 pub const MAPCOUNT: usize = MAPHEIGHT * MAPWIDTH;
 
+const MAX_SECRET_DOORS: i32 = 3;
+
+// most viewsheds the map remembers before starting over
+const FOV_CACHE_LIMIT: usize = 1024;
+
 
 #[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
 pub enum TileType {
     Wall,
     Floor,
     DownStairs,
+    UpStairs,
+    Door,
+}
+
+// the ways a level can be laid out
+#[derive(PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
+pub enum MapGenerator {
+    RoomsAndCorridors,
+    CellularAutomata,
+    Bsp,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
@@ -29,15 +47,56 @@ pub struct Map {
     pub blocked: Vec<bool>,
     pub depth: i32,
 
+    // glyphs of floor items the player last saw, by tile index,
+    // saved with the map so loading doesn't forget spotted loot
+    pub remembered_items: HashMap<usize, rltk::FontCharType>,
+
+    // walls that are really doors, until the player finds them
+    pub secret_doors: HashSet<usize>,
+
+    // doors start closed, blocking movement and sight, these ones have been opened
+    pub open_doors: HashSet<usize>,
+
+    // tiles something bled on, they stay stained for the rest of the level
+    pub bloodstains: HashSet<usize>,
+
+    // where the builder first put the player, for when stairs can't be found
+    #[serde(default)]
+    pub starting_position: Option<(i32, i32)>,
+
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub tile_content: Vec<Vec<Entity>>,
+
+    // field of view results by (x, y, range), anything that changes
+    // which tiles are opaque after the level is built has to clear this
+    #[serde(skip_serializing)]
+    #[serde(skip_deserializing)]
+    pub fov_cache: HashMap<(i32, i32, i32), Vec<Point>>,
 }
 
 impl Map {
 
-    pub fn is_out_of_bounds(&mut self, x: &i32, y: &i32) -> bool {
-        *x >= 0 && *x <= self.width && *y >= 0 && *y <= self.height
+    // whether (x, y) is off the map or on its outer edge, which nothing may stand on
+    pub fn is_out_of_bounds(&self, x: i32, y: i32) -> bool {
+        x < 1 || x >= self.width - 1 || y < 1 || y >= self.height - 1
+    }
+
+    // field_of_view against the walls only, standing still or coming back
+    // to a tile reuses the earlier result instead of casting again
+    pub fn cached_field_of_view(&mut self, origin: Point, range: i32) -> Vec<Point> {
+        let key = (origin.x, origin.y, range);
+        if let Some(tiles) = self.fov_cache.get(&key) {
+            return tiles.clone();
+        }
+
+        let tiles = rltk::field_of_view(origin, range, &*self);
+        // a long stay on one level shouldn't grow it forever
+        if self.fov_cache.len() >= FOV_CACHE_LIMIT {
+            self.fov_cache.clear();
+        }
+        self.fov_cache.insert(key, tiles.clone());
+        tiles
     }
 
     pub fn clear_content_index(&mut self) {
@@ -46,19 +105,62 @@ impl Map {
         }
     }
 
+    // Where someone coming back to this level by stairs turns up. A level saved before
+    // the start was kept, and with its stairs gone, has only its first room to go on
+    pub fn arrival_point(&self, stairs: TileType) -> (i32, i32) {
+        match self.tiles.iter().position(|tile| *tile == stairs) {
+            Some(idx) => (idx as i32 % self.width, idx as i32 / self.width),
+            None => self.starting_position.unwrap_or_else(|| {
+                self.rooms.first().map_or((self.width / 2, self.height / 2), |room| room.center())
+            }),
+        }
+    }
+
     pub fn populate_blocked(&mut self) {
-        for (i, tile) in self.tiles.iter().enumerate() {
-            self.blocked[i] = *tile == TileType::Wall;
+        for i in 0..self.tiles.len() {
+            self.blocked[i] = self.is_impassable(i);
         }
     }
 
+    // walls and closed doors, what stops anyone moving whether or not a creature stands there
+    pub fn is_impassable(&self, idx: usize) -> bool {
+        self.tiles[idx] == TileType::Wall || self.is_closed_door(idx)
+    }
+
+    // a diagonal step squeezing between two walls or closed doors, which nothing fits through
+    pub fn cuts_corner(&self, x: i32, y: i32, delta_x: i32, delta_y: i32) -> bool {
+        if delta_x == 0 || delta_y == 0 {
+            return false;
+        }
+        self.is_impassable(self.xy_idx(x + delta_x, y)) && self.is_impassable(self.xy_idx(x, y + delta_y))
+    }
+
+    pub fn is_closed_door(&self, idx: usize) -> bool {
+        self.tiles[idx] == TileType::Door && !self.open_doors.contains(&idx)
+    }
+
+    // whether a straight line between the two crosses nothing opaque, the ends themselves don't count
+    pub fn has_line_of_sight(&self, from: Point, to: Point) -> bool {
+        rltk::line2d(rltk::LineAlg::Bresenham, from, to)
+            .iter()
+            .filter(|tile| **tile != from && **tile != to)
+            .all(|tile| !self.is_opaque(self.xy_idx(tile.x, tile.y)))
+    }
+
+    // opening changes what can be seen from all around, so cached sight goes too
+    pub fn open_door(&mut self, idx: usize) {
+        self.open_doors.insert(idx);
+        self.blocked[idx] = false;
+        self.fov_cache.clear();
+    }
+
     fn is_exit_valid(&self, x: i32, y: i32) -> bool {
-        if x < 1 || x > self.width - 1 || y < 1 || y > self.height - 1 {
+        if self.is_out_of_bounds(x, y) {
             return false;
         }
 
         let idx = self.xy_idx(x, y);
-        !self.blocked[idx as usize]
+        !self.blocked[idx]
     }
 
 
@@ -67,7 +169,7 @@ impl Map {
     }
 
     // Apply tiles in the given rectangle
-    fn apply_room_to_map(&mut self, room: &Rect) {
+    pub fn apply_room_to_map(&mut self, room: &Rect) {
         // room.y1 + 1 to prevent player from going off the map
         for y in room.y1 + 1..=room.y2 {
             for x in room.x1 + 1..=room.x2 {
@@ -78,29 +180,28 @@ impl Map {
     }
 
     // Lays tiles in a single-unit wide horizontal row
-    fn apply_horizontal_tunnel(&mut self, x1: i32, x2: i32, y: i32) {
+    pub fn apply_horizontal_tunnel(&mut self, x1: i32, x2: i32, y: i32) {
         for x in min(x1, x2)..=max(x1, x2) {
             let idx = self.xy_idx(x, y);
             if idx > 0 && idx < self.width as usize * self.height as usize {
-                self.tiles[idx as usize] = TileType::Floor;
+                self.tiles[idx] = TileType::Floor;
             }
         }
     }
 
     // Lays tiles in a single-unit wide vertical row
-    fn apply_vertical_tunnel(&mut self, y1: i32, y2: i32, x: i32) {
+    pub fn apply_vertical_tunnel(&mut self, y1: i32, y2: i32, x: i32) {
         for y in min(y1, y2)..=max(y1, y2) {
             let idx = self.xy_idx(x, y);
             if idx > 0 && idx < self.width as usize * self.height as usize {
-                self.tiles[idx as usize] = TileType::Floor;
+                self.tiles[idx] = TileType::Floor;
             }
         }
     }
 
-    // makes a map with random rooms and corridors joining them together
-    // using the three functions above
-    pub fn new_map_rooms_and_corridors(new_depth: i32) -> Map {
-        let mut map = Map {
+    // every tile wall, for the generators to carve out
+    pub fn solid(new_depth: i32) -> Map {
+        Map {
             tiles: vec![TileType::Wall; MAPCOUNT],
             rooms: Vec::new(),
             width: MAPWIDTH as i32,
@@ -110,111 +211,260 @@ impl Map {
             blocked: vec![false; MAPCOUNT],
             tile_content: vec![Vec::new(); MAPCOUNT],
             depth: new_depth,
-        };
-
-        const MAX_ROOMS: i32 = 30;
-        const MIN_SIZE: i32 = 6;
-        const MAX_SIZE: i32 = 10;
-
-        let mut rng = RandomNumberGenerator::new();
-
-        for i in 0..MAX_ROOMS {
-            let w = rng.range(MIN_SIZE, MAX_SIZE);
-            let h = rng.range(MIN_SIZE, MAX_SIZE);
-            // -1 and -1 since width and height are 0-79 and 0-49, and
-            // to ensure a wall at the border
-            let x = rng.roll_dice(1, map.width - w - 1) - 1;
-            let y = rng.roll_dice(1, map.height - h - 1) - 1;
-
-            let new_room = Rect::new(x, y, w, h);
+            remembered_items: HashMap::new(),
+            bloodstains: HashSet::new(),
+            secret_doors: HashSet::new(),
+            open_doors: HashSet::new(),
+            starting_position: None,
+            fov_cache: HashMap::new(),
+        }
+    }
 
-            // check if the new room intersects with any existing ones
-            let mut ok = true;
-            for other_room in map.rooms.iter() {
-                if new_room.intersect(other_room) {
-                    ok = false;
+    // Picks a few walls a single tile thick with floor on both sides to be secret doors.
+    // They stay walls until searched for, so the level never depends on finding them
+    pub fn hide_secret_doors(&mut self, rng: &mut RandomNumberGenerator) {
+        let is_floor = |map: &Map, x: i32, y: i32| map.tiles[map.xy_idx(x, y)] == TileType::Floor;
+        let mut candidates = Vec::new();
+        for y in 1..self.height - 1 {
+            for x in 1..self.width - 1 {
+                if self.tiles[self.xy_idx(x, y)] != TileType::Wall {
+                    continue;
+                }
+                let across = is_floor(self, x - 1, y) && is_floor(self, x + 1, y)
+                    && !is_floor(self, x, y - 1) && !is_floor(self, x, y + 1);
+                let down = is_floor(self, x, y - 1) && is_floor(self, x, y + 1)
+                    && !is_floor(self, x - 1, y) && !is_floor(self, x + 1, y);
+                if across || down {
+                    candidates.push(self.xy_idx(x, y));
                 }
             }
+        }
 
-            //only add room if it does not intersect
-            if ok {
-                map.apply_room_to_map(&new_room);
-
-                // if other rooms exist, connect the new one with a tunnel
-                if !map.rooms.is_empty() {
-                    let (new_x, new_y) = new_room.center();
-                    let (prev_x, prev_y) = map.rooms[map.rooms.len() - 1].center();
-
-                    // 50/50 chance of constructing the tunnel by
-                    // fitting together two pieces
-                    if rng.range(0, 2) == 1 {
-                        map.apply_horizontal_tunnel(prev_x, new_x, prev_y);
-                        map.apply_vertical_tunnel(prev_y, new_y, new_x);
-                    } else {
-                        map.apply_vertical_tunnel(prev_y, new_y, prev_x);
-                        map.apply_horizontal_tunnel(prev_x, new_x, new_y);
-                    }
-                }
+        for _i in 0..MAX_SECRET_DOORS {
+            if candidates.is_empty() {
+                break;
+            }
+            let pick = rng.range(0, candidates.len() as i32) as usize;
+            self.secret_doors.insert(candidates.swap_remove(pick));
+        }
+    }
 
-                map.rooms.push(new_room);
+    // every tile a creature could walk to from start, moving like the player does
+    pub fn reachable_from(&self, start: (i32, i32)) -> Vec<bool> {
+        let mut reachable = vec![false; self.tiles.len()];
+        let start_idx = self.xy_idx(start.0, start.1);
+        reachable[start_idx] = true;
+        let mut open = vec![start_idx];
+
+        while let Some(idx) = open.pop() {
+            let x = idx as i32 % self.width;
+            let y = idx as i32 / self.width;
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= self.width || ny < 0 || ny >= self.height {
+                    continue;
+                }
+                let next = self.xy_idx(nx, ny);
+                if !reachable[next] && self.tiles[next] != TileType::Wall {
+                    reachable[next] = true;
+                    open.push(next);
+                }
             }
         }
 
-        let down_stairs_position = map.rooms[map.rooms.len()-1].center();
-        let down_stairs_idx = map.xy_idx(down_stairs_position.0, down_stairs_position.1);
-        map.tiles[down_stairs_idx] = TileType::DownStairs;
+        reachable
+    }
+}
 
-        map
+// one of the eight tiles around, never around itself
+pub fn random_neighbour(rng: &mut RandomNumberGenerator, around: Point) -> Point {
+    const NEIGHBOURS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+    let (dx, dy) = NEIGHBOURS[rng.range(0, 8) as usize];
+    Point::new(around.x + dx, around.y + dy)
+}
+
+// Rejects degenerate levels: the player has to start on an open floor tile
+// inside the first room, and that whole room and the stairs must be reachable
+pub fn validate_map(map: &Map, start: (i32, i32)) -> bool {
+    let first_room = match map.rooms.first() {
+        Some(room) => room,
+        None => return false,
+    };
+
+    let (x, y) = start;
+    if x < 0 || x >= map.width || y < 0 || y >= map.height {
+        return false;
+    }
+    let start_idx = map.xy_idx(x, y);
+    if map.tiles[start_idx] != TileType::Floor || map.blocked[start_idx] {
+        return false;
     }
+    if x <= first_room.x1 || x > first_room.x2 || y <= first_room.y1 || y > first_room.y2 {
+        return false;
+    }
+
+    let reachable = map.reachable_from(start);
+    let room_reachable = (first_room.y1 + 1..=first_room.y2)
+        .flat_map(|room_y| (first_room.x1 + 1..=first_room.x2).map(move |room_x| (room_x, room_y)))
+        .all(|(room_x, room_y)| reachable[map.xy_idx(room_x, room_y)]);
+    let stairs_reachable = map
+        .tiles
+        .iter()
+        .enumerate()
+        .any(|(idx, tile)| *tile == TileType::DownStairs && reachable[idx]);
+
+    room_reachable && stairs_reachable
 }
 
-pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
-    let map = ecs.fetch::<Map>();
+// Decorative floors, picked per tile so big rooms don't look flat.
+// Most tiles are plain, the rest get cracks, pebbles and the like
+const FLOOR_VARIANTS: &[(char, (u8, u8, u8))] = &[
+    ('.', rltk::GREY),
+    (',', rltk::GREY),
+    ('\'', rltk::DIMGREY),
+    ('`', rltk::SLATEGREY),
+    (':', rltk::DIMGREY),
+];
+const PLAIN_FLOOR_ODDS: u32 = 12;
+
+// the same tile index always hashes to the same variant, so nothing flickers between frames
+fn floor_variant(idx: usize) -> (char, (u8, u8, u8)) {
+    let mut hash = (idx as u32).wrapping_mul(0x9E37_79B9);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+
+    if !hash.is_multiple_of(PLAIN_FLOOR_ODDS) {
+        return FLOOR_VARIANTS[0];
+    }
+    let variant = 1 + (hash / PLAIN_FLOOR_ODDS) as usize % (FLOOR_VARIANTS.len() - 1);
+    FLOOR_VARIANTS[variant]
+}
 
-    let mut x = 0;
-    let mut y = 0;
+fn is_revealed_wall(map: &Map, x: i32, y: i32) -> bool {
+    if x < 0 || x >= map.width || y < 0 || y >= map.height {
+        return false;
+    }
+    let idx = map.xy_idx(x, y);
+    map.tiles[idx] == TileType::Wall && map.revealed_tiles[idx]
+}
 
-    for (idx, tile) in map.tiles.iter().enumerate() {
-        // only render visible tiles
-        if map.revealed_tiles[idx] {
-            let glyph;
-            let mut fg;
+// joins a wall up with the walls next to it, only ones the player has seen
+// count so the glyph doesn't give away what's still unexplored
+pub fn wall_glyph(map: &Map, x: i32, y: i32) -> rltk::FontCharType {
+    let mut mask: u8 = 0;
+    if is_revealed_wall(map, x, y - 1) { mask += 1; }
+    if is_revealed_wall(map, x, y + 1) { mask += 2; }
+    if is_revealed_wall(map, x - 1, y) { mask += 4; }
+    if is_revealed_wall(map, x + 1, y) { mask += 8; }
+
+    let glyph = match mask {
+        0 => '■',
+        1..=3 => '║',
+        4 | 8 | 12 => '═',
+        5 => '╝',
+        6 => '╗',
+        7 => '╣',
+        9 => '╚',
+        10 => '╔',
+        11 => '╠',
+        13 => '╩',
+        14 => '╦',
+        _ => '╬',
+    };
+    rltk::to_cp437(glyph)
+}
+
+// how something the player only remembers is drawn, greyed out and dimmed
+pub fn remembered_color(color: RGB, dim: f32) -> RGB {
+    color.to_greyscale() * dim
+}
 
-            // chose what to render based on the type of tile
-            match tile {
-                TileType::Floor => {
-                    glyph = rltk::to_cp437('.');
-                    fg = RGB::named(rltk::GREY);
+pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
+    let map = ecs.fetch::<Map>();
+    let fog_dim = ecs.fetch::<Settings>().fog_dim;
+    let light = carried_light(
+        *ecs.fetch::<Entity>(),
+        &ecs.entities(),
+        &ecs.read_storage::<LightSource>(),
+        &ecs.read_storage::<Equipped>(),
+        &ecs.read_storage::<InBackpack>(),
+    );
+
+    // only the part of the map in view is drawn, shifted onto the screen
+    let origin = view_origin(&map, *ecs.fetch::<Point>());
+    for screen_y in 0..VIEW_HEIGHT {
+        for screen_x in 0..VIEW_WIDTH {
+            let Point { x, y } = match screen_to_map(origin, Point::new(screen_x, screen_y)) {
+                Some(tile) if tile.x < map.width && tile.y < map.height => tile,
+                _ => continue,
+            };
+            let idx = map.xy_idx(x, y);
+            let tile = &map.tiles[idx];
+
+            // only render visible tiles
+            if map.revealed_tiles[idx] {
+                let glyph;
+                let mut fg;
+
+                // chose what to render based on the type of tile
+                match tile {
+                    TileType::Floor => {
+                        let (floor_glyph, floor_fg) = floor_variant(idx);
+                        glyph = rltk::to_cp437(floor_glyph);
+                        fg = RGB::named(floor_fg);
+                    }
+                    TileType::Wall => {
+                        glyph = wall_glyph(&map, x, y);
+                        fg = RGB::named(rltk::BURLYWOOD);
+                    }
+                    TileType::DownStairs => {
+                        glyph = rltk::to_cp437('↓');
+                        fg = RGB::named(rltk::RED);
+                    }
+                    TileType::UpStairs => {
+                        glyph = rltk::to_cp437('↑');
+                        fg = RGB::named(rltk::RED);
+                    }
+                    TileType::Door => {
+                        glyph = if map.open_doors.contains(&idx) {
+                            rltk::to_cp437('\'')
+                        } else {
+                            rltk::to_cp437('+')
+                        };
+                        fg = RGB::named(rltk::CHOCOLATE);
+                    }
                 }
-                TileType::Wall => {
-                    glyph = rltk::to_cp437('#');
-                    fg = RGB::named(rltk::BURLYWOOD);
+
+                let mut bg = RGB::named(rltk::BLACK);
+                if map.bloodstains.contains(&idx) {
+                    bg = RGB::from_f32(0.5, 0.0, 0.0);
                 }
-                TileType::DownStairs => {
-                    glyph = rltk::to_cp437('↓');
-                    fg = RGB::named(rltk::RED);
+
+                //Draw revealed but non-visible tiles differently
+                if !map.visible_tiles[idx] {
+                    fg = remembered_color(fg, fog_dim);
+                    bg = remembered_color(bg, fog_dim);
+                } else if let Some(light) = &light {
+                    // what the player's light falls on takes on a little of its colour
+                    fg = fg.lerp(light.color, 0.25);
                 }
-            }
+                ctx.set(screen_x, screen_y, fg, bg, glyph);
 
-            //Draw revealed but non-visible tiles differently
-            if !map.visible_tiles[idx] {
-                fg = fg.to_greyscale();
+                // items the player remembers seeing here, entities cover these on visible tiles
+                if !map.visible_tiles[idx] {
+                    if let Some(item_glyph) = map.remembered_items.get(&idx) {
+                        ctx.set(screen_x, screen_y, remembered_color(RGB::named(rltk::WHITE), fog_dim), bg, *item_glyph);
+                    }
+                }
             }
-            ctx.set(x, y, fg, RGB::named(rltk::BLACK), glyph);
-        }
-
-        // move to the next coordinates
-        x += 1;
-        if x > 79 {
-            x = 0;
-            y += 1;
         }
     }
 }
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.tiles[idx as usize] == TileType::Wall
+        self.tiles[idx] == TileType::Wall || self.is_closed_door(idx)
     }
 
 
@@ -241,19 +491,12 @@ impl BaseMap for Map {
         };
 
         
-        // Diagonals
-        if self.is_exit_valid(x - 1, y-1) {
-            exits.push(((idx -w) - 1, 1.45))
-        };
-        if self.is_exit_valid(x - 1, y) {
-            exits.push(((idx - w) - 1, 1.45))
-        };
-        if self.is_exit_valid(x - 1, y) {
-            exits.push(((idx + w) - 1, 1.45))
-        };
-        if self.is_exit_valid(x - 1, y) {
-            exits.push(((idx + w)- 1, 1.45))
-        };
+        // Diagonals, as long as they don't cut between two walls
+        for (delta_x, delta_y) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter() {
+            if self.is_exit_valid(x + delta_x, y + delta_y) && !self.cuts_corner(x, y, *delta_x, *delta_y) {
+                exits.push((self.xy_idx(x + delta_x, y + delta_y), 1.45))
+            }
+        }
 
         exits
     }
@@ -272,3 +515,107 @@ impl Algorithm2D for Map {
         Point::new(self.width, self.height)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one room with the stairs in it, which validate_map is happy with
+    fn one_room_map() -> Map {
+        let mut map = Map::solid(1);
+        let room = Rect::new(2, 2, 10, 10);
+        map.apply_room_to_map(&room);
+        map.rooms.push(room);
+        let stairs_idx = map.xy_idx(10, 10);
+        map.tiles[stairs_idx] = TileType::DownStairs;
+        map.populate_blocked();
+        map
+    }
+
+    #[test]
+    fn validation_rejects_broken_levels() {
+        assert!(validate_map(&one_room_map(), (5, 5)));
+
+        // no rooms at all
+        assert!(!validate_map(&Map::solid(1), (5, 5)));
+
+        // starting in the rock outside the room
+        assert!(!validate_map(&one_room_map(), (20, 20)));
+
+        // stairs walled off from the start
+        let mut map = one_room_map();
+        for y in 3..=12 {
+            let idx = map.xy_idx(8, y);
+            map.tiles[idx] = TileType::Wall;
+        }
+        map.populate_blocked();
+        assert!(!validate_map(&map, (5, 5)));
+    }
+
+    #[test]
+    fn sight_from_the_same_tile_comes_from_the_cache() {
+        let mut map = one_room_map();
+        let door = map.xy_idx(13, 5);
+        map.tiles[door] = TileType::Door;
+        let first = map.cached_field_of_view(Point::new(5, 5), 8);
+        assert_eq!(map.fov_cache.len(), 1);
+
+        // a cached answer that differed from a fresh cast would show up here
+        map.fov_cache.insert((5, 5, 8), vec![Point::new(5, 5)]);
+        assert_eq!(map.cached_field_of_view(Point::new(5, 5), 8), vec![Point::new(5, 5)]);
+        assert_eq!(map.fov_cache.len(), 1);
+
+        // opening a door changes what can be seen, so the cache goes
+        map.open_door(door);
+        assert!(map.fov_cache.is_empty());
+        let fresh = map.cached_field_of_view(Point::new(5, 5), 8);
+        assert_eq!(fresh.len(), first.len());
+        assert!(fresh.iter().all(|tile| first.contains(tile)));
+    }
+
+    #[test]
+    fn walls_block_a_clear_shot() {
+        let mut map = one_room_map();
+        let pillar = map.xy_idx(7, 5);
+        map.tiles[pillar] = TileType::Wall;
+        map.populate_blocked();
+        let from = Point::new(5, 5);
+
+        assert!(!map.has_line_of_sight(from, Point::new(9, 5)));
+        // the wall itself can still be aimed at, and so can anything off to the side of it
+        assert!(map.has_line_of_sight(from, Point::new(7, 5)));
+        assert!(map.has_line_of_sight(from, Point::new(9, 8)));
+    }
+
+    // the glyph for the wall at (10, 10) with only the given walls seen
+    fn glyph_with_seen(walls: &[(i32, i32)]) -> rltk::FontCharType {
+        let mut map = Map::solid(1);
+        for (x, y) in walls.iter() {
+            let idx = map.xy_idx(*x, *y);
+            map.revealed_tiles[idx] = true;
+        }
+        wall_glyph(&map, 10, 10)
+    }
+
+    #[test]
+    fn walls_join_up_with_the_seen_walls_beside_them() {
+        // the walls all round are there, just not seen yet
+        assert_eq!(glyph_with_seen(&[(10, 10)]), rltk::to_cp437('■'));
+        assert_eq!(glyph_with_seen(&[(9, 10), (10, 10), (11, 10)]), rltk::to_cp437('═'));
+        assert_eq!(glyph_with_seen(&[(10, 9), (10, 10), (10, 11)]), rltk::to_cp437('║'));
+        assert_eq!(glyph_with_seen(&[(9, 10), (10, 10), (11, 10), (10, 11)]), rltk::to_cp437('╦'));
+    }
+
+    #[test]
+    fn remembered_tiles_are_grey_and_dimmed() {
+        let color = remembered_color(RGB::from_f32(1.0, 0.5, 0.0), 0.35);
+        // the usual luminance weights, 0.2126 red and 0.7152 green, scaled down by the dim
+        let expected = (0.2126 + 0.5 * 0.7152) * 0.35;
+        for channel in [color.r, color.g, color.b] {
+            assert!((channel - expected).abs() < 0.0001, "{} isn't {}", channel, expected);
+        }
+
+        let white = remembered_color(RGB::from_f32(1.0, 1.0, 1.0), 1.0);
+        assert!((white.r - 1.0).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,621 @@
+use std::cmp::{max, min};
+
+use rltk::{Point, RandomNumberGenerator};
+use specs::prelude::*;
+
+use super::{spawner, validate_map, Map, MapGenerator, Position, Rect, TileType};
+
+// Lays out a level, then fills it. Anything that builds levels goes behind
+// this, so the game can pick one per depth without caring which it got
+pub trait MapBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator);
+    fn spawn_entities(&self, ecs: &mut World);
+    fn get_map(&self) -> Map;
+
+    // where the player goes, always open floor inside the first room
+    fn starting_position(&self) -> Position;
+}
+
+// Without a generator asked for, deeper levels are sometimes caves and the rest
+// are split between rooms and corridors and BSP
+pub fn random_builder(new_depth: i32, generator: Option<MapGenerator>, rng: &mut RandomNumberGenerator) -> Box<dyn MapBuilder> {
+    let generator = generator.unwrap_or_else(|| match rng.roll_dice(1, 3) {
+        1 if new_depth > 1 => MapGenerator::CellularAutomata,
+        2 => MapGenerator::Bsp,
+        _ => MapGenerator::RoomsAndCorridors,
+    });
+    match generator {
+        MapGenerator::RoomsAndCorridors => Box::new(RoomsAndCorridorsBuilder::new(new_depth)),
+        MapGenerator::CellularAutomata => Box::new(CellularAutomataBuilder::new(new_depth)),
+        MapGenerator::Bsp => Box::new(BspBuilder::new(new_depth)),
+    }
+}
+
+// the finished level, with the stairs up under where the player starts
+pub fn map_with_up_stairs(builder: &dyn MapBuilder) -> Map {
+    let mut map = builder.get_map();
+    let start = builder.starting_position();
+    let start_idx = map.xy_idx(start.x, start.y);
+    map.tiles[start_idx] = TileType::UpStairs;
+    map.starting_position = Some((start.x, start.y));
+    map
+}
+
+// how many levels get thrown away before settling for the fallback layout
+const MAX_LEVEL_ATTEMPTS: i32 = 50;
+
+// builds levels until one passes validate_map, or gives up and uses FallbackBuilder
+pub fn build_validated_level(new_depth: i32, generator: Option<MapGenerator>, rng: &mut RandomNumberGenerator) -> Box<dyn MapBuilder> {
+    for _attempt in 0..MAX_LEVEL_ATTEMPTS {
+        let mut builder = random_builder(new_depth, generator, rng);
+        builder.build_map(rng);
+        let start = builder.starting_position();
+        if validate_map(&builder.get_map(), (start.x, start.y)) {
+            return builder;
+        }
+    }
+    let mut fallback = FallbackBuilder::new(new_depth);
+    fallback.build_map(rng);
+    Box::new(fallback)
+}
+
+// Two fixed rooms in opposite corners joined by a corridor, plain but always valid,
+// for when the real builders keep coming up with broken levels
+pub struct FallbackBuilder {
+    map: Map,
+    depth: i32,
+    spawn_list: Vec<(usize, String)>,
+    starting_position: Position,
+}
+
+impl FallbackBuilder {
+    pub fn new(new_depth: i32) -> FallbackBuilder {
+        FallbackBuilder {
+            map: Map::default(),
+            depth: new_depth,
+            spawn_list: Vec::new(),
+            starting_position: Position{ x: 0, y: 0 },
+        }
+    }
+}
+
+impl MapBuilder for FallbackBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        let mut map = Map::solid(self.depth);
+        let start_room = Rect::new(2, 2, 10, 10);
+        let stairs_room = Rect::new(map.width - 14, map.height - 14, 10, 10);
+        map.apply_room_to_map(&start_room);
+        map.apply_room_to_map(&stairs_room);
+        let (start_x, start_y) = start_room.center();
+        let (stairs_x, stairs_y) = stairs_room.center();
+        map.apply_horizontal_tunnel(start_x, stairs_x, start_y);
+        map.apply_vertical_tunnel(start_y, stairs_y, stairs_x);
+        map.rooms = vec![start_room, stairs_room];
+
+        let stairs_idx = map.xy_idx(stairs_x, stairs_y);
+        map.tiles[stairs_idx] = TileType::DownStairs;
+
+        self.starting_position = first_room_center(&map);
+        self.spawn_list.clear();
+        spawner::fill_rooms(&map, &self.starting_position, rng, &mut self.spawn_list);
+        self.map = map;
+    }
+
+    fn spawn_entities(&self, ecs: &mut World) {
+        spawner::spawn_from_list(ecs, &self.spawn_list);
+    }
+
+    fn get_map(&self) -> Map {
+        self.map.clone()
+    }
+
+    fn starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+}
+
+// makes a map with random rooms and corridors joining them together
+pub struct RoomsAndCorridorsBuilder {
+    map: Map,
+    depth: i32,
+    spawn_list: Vec<(usize, String)>,
+    starting_position: Position,
+}
+
+impl RoomsAndCorridorsBuilder {
+    pub fn new(new_depth: i32) -> RoomsAndCorridorsBuilder {
+        RoomsAndCorridorsBuilder {
+            map: Map::default(),
+            depth: new_depth,
+            spawn_list: Vec::new(),
+            starting_position: Position{ x: 0, y: 0 },
+        }
+    }
+}
+
+impl MapBuilder for RoomsAndCorridorsBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        let mut map = Map::solid(self.depth);
+
+        const MAX_ROOMS: i32 = 30;
+        const MIN_SIZE: i32 = 6;
+        const MAX_SIZE: i32 = 10;
+
+        for _i in 0..MAX_ROOMS {
+            let w = rng.range(MIN_SIZE, MAX_SIZE);
+            let h = rng.range(MIN_SIZE, MAX_SIZE);
+            // -1 and -1 since width and height are 0-79 and 0-49, and
+            // to ensure a wall at the border
+            let x = rng.roll_dice(1, map.width - w - 1) - 1;
+            let y = rng.roll_dice(1, map.height - h - 1) - 1;
+
+            let new_room = Rect::new(x, y, w, h);
+
+            // check if the new room intersects with any existing ones
+            let mut ok = true;
+            for other_room in map.rooms.iter() {
+                if new_room.intersect(other_room) {
+                    ok = false;
+                }
+            }
+
+            //only add room if it does not intersect
+            if ok {
+                map.apply_room_to_map(&new_room);
+
+                // if other rooms exist, connect the new one with a tunnel
+                if !map.rooms.is_empty() {
+                    let (new_x, new_y) = new_room.center();
+                    let (prev_x, prev_y) = map.rooms[map.rooms.len() - 1].center();
+
+                    // 50/50 chance of constructing the tunnel by
+                    // fitting together two pieces
+                    if rng.range(0, 2) == 1 {
+                        map.apply_horizontal_tunnel(prev_x, new_x, prev_y);
+                        map.apply_vertical_tunnel(prev_y, new_y, new_x);
+                    } else {
+                        map.apply_vertical_tunnel(prev_y, new_y, prev_x);
+                        map.apply_horizontal_tunnel(prev_x, new_x, new_y);
+                    }
+                }
+
+                map.rooms.push(new_room);
+            }
+        }
+
+        if let Some(last_room) = map.rooms.last() {
+            let down_stairs_position = last_room.center();
+            let down_stairs_idx = map.xy_idx(down_stairs_position.0, down_stairs_position.1);
+            map.tiles[down_stairs_idx] = TileType::DownStairs;
+        }
+
+        self.starting_position = first_room_center(&map);
+        connect_diagonal_pinches(&mut map, &self.starting_position);
+        map.hide_secret_doors(rng);
+        self.spawn_list.clear();
+        spawner::fill_rooms(&map, &self.starting_position, rng, &mut self.spawn_list);
+        self.map = map;
+    }
+
+    fn spawn_entities(&self, ecs: &mut World) {
+        spawner::spawn_from_list(ecs, &self.spawn_list);
+    }
+
+    fn get_map(&self) -> Map {
+        self.map.clone()
+    }
+
+    fn starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+}
+
+// Organic caves: random noise smoothed into blobs, with everything the
+// middle of the map can't reach filled back in. The rooms are synthetic,
+// a small one around the start, a few scattered for spawning and one
+// around the stairs, which go on the reachable tile furthest from the start
+pub struct CellularAutomataBuilder {
+    map: Map,
+    depth: i32,
+    spawn_list: Vec<(usize, String)>,
+    starting_position: Position,
+}
+
+impl CellularAutomataBuilder {
+    pub fn new(new_depth: i32) -> CellularAutomataBuilder {
+        CellularAutomataBuilder {
+            map: Map::default(),
+            depth: new_depth,
+            spawn_list: Vec::new(),
+            starting_position: Position{ x: 0, y: 0 },
+        }
+    }
+}
+
+impl MapBuilder for CellularAutomataBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        let mut map = Map::solid(self.depth);
+
+        const WALL_CHANCE: i32 = 45;
+        const SMOOTHING_PASSES: i32 = 5;
+        const SPAWN_AREAS: i32 = 12;
+
+        // seed the noise, the border always stays wall
+        for y in 1..map.height - 1 {
+            for x in 1..map.width - 1 {
+                let idx = map.xy_idx(x, y);
+                if rng.roll_dice(1, 100) > WALL_CHANCE {
+                    map.tiles[idx] = TileType::Floor;
+                }
+            }
+        }
+
+        // a tile with 5 or more wall neighbours becomes wall, the rest open up
+        for _pass in 0..SMOOTHING_PASSES {
+            let mut smoothed = map.tiles.clone();
+            for y in 1..map.height - 1 {
+                for x in 1..map.width - 1 {
+                    let mut walls = 0;
+                    for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                        if map.tiles[map.xy_idx(x + dx, y + dy)] == TileType::Wall {
+                            walls += 1;
+                        }
+                    }
+                    smoothed[map.xy_idx(x, y)] = if walls >= 5 { TileType::Wall } else { TileType::Floor };
+                }
+            }
+            map.tiles = smoothed;
+        }
+
+        // start on the open tile closest to the middle, with a bit of room around it
+        let (mid_x, mid_y) = (map.width / 2, map.height / 2);
+        let mut start = (mid_x, mid_y);
+        let mut best = f32::MAX;
+        for y in 2..map.height - 2 {
+            for x in 2..map.width - 2 {
+                if map.tiles[map.xy_idx(x, y)] == TileType::Floor {
+                    let distance = rltk::DistanceAlg::Pythagoras.distance2d(Point::new(x, y), Point::new(mid_x, mid_y));
+                    if distance < best {
+                        best = distance;
+                        start = (x, y);
+                    }
+                }
+            }
+        }
+        let start_room = Rect::new(start.0 - 1, start.1 - 1, 2, 2);
+        map.apply_room_to_map(&start_room);
+        map.rooms.push(start_room);
+
+        // anything the start can't reach is closed off
+        let reachable = map.reachable_from(start);
+        for (idx, tile) in map.tiles.iter_mut().enumerate() {
+            if !reachable[idx] {
+                *tile = TileType::Wall;
+            }
+        }
+
+        // spawn areas are clamped to the map, the spawner skips whatever wall they cover
+        let (width, height) = (map.width, map.height);
+        let area_around = |x: i32, y: i32| Rect {
+            x1: max(x - 3, 0),
+            x2: min(x + 3, width - 1),
+            y1: max(y - 3, 0),
+            y2: min(y + 3, height - 1),
+        };
+
+        let open: Vec<usize> = (0..map.tiles.len()).filter(|idx| reachable[*idx]).collect();
+        for _i in 0..SPAWN_AREAS {
+            let idx = open[rng.range(0, open.len() as i32) as usize];
+            let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+            map.rooms.push(area_around(x, y));
+        }
+
+        let stairs_idx = open
+            .iter()
+            .copied()
+            .max_by_key(|idx| {
+                let (x, y) = (*idx as i32 % map.width, *idx as i32 / map.width);
+                (x - start.0).abs() + (y - start.1).abs()
+            })
+            .unwrap_or_else(|| map.xy_idx(start.0, start.1));
+        map.tiles[stairs_idx] = TileType::DownStairs;
+        let (stairs_x, stairs_y) = (stairs_idx as i32 % map.width, stairs_idx as i32 / map.width);
+        map.rooms.push(area_around(stairs_x, stairs_y));
+
+        // the flood fill started here, so everything that's left is reachable from it
+        self.starting_position = Position{ x: start.0, y: start.1 };
+        connect_diagonal_pinches(&mut map, &self.starting_position);
+        map.hide_secret_doors(rng);
+        self.spawn_list.clear();
+        spawner::fill_rooms(&map, &self.starting_position, rng, &mut self.spawn_list);
+        self.map = map;
+    }
+
+    fn spawn_entities(&self, ecs: &mut World) {
+        spawner::spawn_from_list(ecs, &self.spawn_list);
+    }
+
+    fn get_map(&self) -> Map {
+        self.map.clone()
+    }
+
+    fn starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+}
+
+// Binary space partitioning: the map is cut in two again and again until the pieces
+// are small, each piece gets a room and the two halves of every cut are joined by a
+// corridor, so rooms fill the map evenly and every one is connected
+pub struct BspBuilder {
+    map: Map,
+    depth: i32,
+    spawn_list: Vec<(usize, String)>,
+    starting_position: Position,
+}
+
+impl BspBuilder {
+    pub fn new(new_depth: i32) -> BspBuilder {
+        BspBuilder {
+            map: Map::default(),
+            depth: new_depth,
+            spawn_list: Vec::new(),
+            starting_position: Position{ x: 0, y: 0 },
+        }
+    }
+}
+
+impl MapBuilder for BspBuilder {
+    fn build_map(&mut self, rng: &mut RandomNumberGenerator) {
+        let mut map = Map::solid(self.depth);
+        // the room floors go inside x1 + 1..=x2, so this keeps a wall round the edge
+        let whole = Rect { x1: 0, y1: 0, x2: map.width - 2, y2: map.height - 2 };
+        bsp_split(&mut map, whole, rng);
+
+        if let Some(last_room) = map.rooms.last() {
+            let (stairs_x, stairs_y) = last_room.center();
+            let stairs_idx = map.xy_idx(stairs_x, stairs_y);
+            map.tiles[stairs_idx] = TileType::DownStairs;
+        }
+
+        self.starting_position = first_room_center(&map);
+        connect_diagonal_pinches(&mut map, &self.starting_position);
+        map.hide_secret_doors(rng);
+        self.spawn_list.clear();
+        spawner::fill_rooms(&map, &self.starting_position, rng, &mut self.spawn_list);
+        self.map = map;
+    }
+
+    fn spawn_entities(&self, ecs: &mut World) {
+        spawner::spawn_from_list(ecs, &self.spawn_list);
+    }
+
+    fn get_map(&self) -> Map {
+        self.map.clone()
+    }
+
+    fn starting_position(&self) -> Position {
+        self.starting_position.clone()
+    }
+}
+
+// Squeezing between two walls that only meet at a corner isn't something to count on,
+// so all floor has to be reachable from start in straight steps. Floor that only
+// touches the rest corner to corner gets a wall beside the gap knocked out, floor that
+// doesn't touch it at all is filled in
+fn connect_diagonal_pinches(map: &mut Map, start: &Position) {
+    loop {
+        let connected = orthogonally_reachable(map, start);
+        let mut carved = false;
+        for y in 1..map.height - 1 {
+            for x in 1..map.width - 1 {
+                let idx = map.xy_idx(x, y);
+                if map.tiles[idx] == TileType::Wall || connected[idx] {
+                    continue;
+                }
+                for (dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                    if !connected[map.xy_idx(x + dx, y + dy)] {
+                        continue;
+                    }
+                    let gap = if map.is_out_of_bounds(x + dx, y) { (x, y + dy) } else { (x + dx, y) };
+                    let gap_idx = map.xy_idx(gap.0, gap.1);
+                    map.tiles[gap_idx] = TileType::Floor;
+                    carved = true;
+                    break;
+                }
+            }
+        }
+
+        if !carved {
+            for (idx, tile) in map.tiles.iter_mut().enumerate() {
+                if !connected[idx] {
+                    *tile = TileType::Wall;
+                }
+            }
+            return;
+        }
+    }
+}
+
+// every tile reachable from start without moving diagonally
+fn orthogonally_reachable(map: &Map, start: &Position) -> Vec<bool> {
+    let mut reachable = vec![false; map.tiles.len()];
+    if map.is_out_of_bounds(start.x, start.y) {
+        return reachable;
+    }
+    let start_idx = map.xy_idx(start.x, start.y);
+    reachable[start_idx] = map.tiles[start_idx] != TileType::Wall;
+    let mut open = if reachable[start_idx] { vec![start_idx] } else { Vec::new() };
+
+    while let Some(idx) = open.pop() {
+        let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+        for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || nx >= map.width || ny < 0 || ny >= map.height {
+                continue;
+            }
+            let next = map.xy_idx(nx, ny);
+            if !reachable[next] && map.tiles[next] != TileType::Wall {
+                reachable[next] = true;
+                open.push(next);
+            }
+        }
+    }
+
+    reachable
+}
+
+// Rooms based layouts start the player in the middle of the first room. Without
+// one it's (0, 0), which is always wall, so the map can't pass validation
+fn first_room_center(map: &Map) -> Position {
+    let (x, y) = map.rooms.first().map_or((0, 0), |room| room.center());
+    Position{ x, y }
+}
+
+// carves a room in every leaf below area and returns one of them to connect to
+fn bsp_split(map: &mut Map, area: Rect, rng: &mut RandomNumberGenerator) -> Rect {
+    const MIN_PARTITION: i32 = 8;
+    const MIN_ROOM: i32 = 4;
+
+    let (w, h) = (area.x2 - area.x1, area.y2 - area.y1);
+    let across = w >= MIN_PARTITION * 2;
+    let down = h >= MIN_PARTITION * 2;
+
+    if !across && !down {
+        // a wall's width is kept free on the far sides, so neighbouring rooms never touch
+        let room_w = rng.range(MIN_ROOM, w - 1);
+        let room_h = rng.range(MIN_ROOM, h - 1);
+        let x = area.x1 + rng.range(0, w - 1 - room_w);
+        let y = area.y1 + rng.range(0, h - 1 - room_h);
+        let room = Rect::new(x, y, room_w, room_h);
+        map.apply_room_to_map(&room);
+        map.rooms.push(room);
+        return room;
+    }
+
+    // cut across the longer side, so pieces stay roughly square
+    let (first, second) = if across && (!down || w >= h) {
+        let cut = area.x1 + rng.range(MIN_PARTITION, w - MIN_PARTITION + 1);
+        (Rect { x2: cut, ..area }, Rect { x1: cut, ..area })
+    } else {
+        let cut = area.y1 + rng.range(MIN_PARTITION, h - MIN_PARTITION + 1);
+        (Rect { y2: cut, ..area }, Rect { y1: cut, ..area })
+    };
+
+    let first_room = bsp_split(map, first, rng);
+    let second_room = bsp_split(map, second, rng);
+
+    let (first_x, first_y) = first_room.center();
+    let (second_x, second_y) = second_room.center();
+    if rng.range(0, 2) == 1 {
+        map.apply_horizontal_tunnel(first_x, second_x, first_y);
+        map.apply_vertical_tunnel(first_y, second_y, second_x);
+    } else {
+        map.apply_vertical_tunnel(first_y, second_y, first_x);
+        map.apply_horizontal_tunnel(first_x, second_x, second_y);
+    }
+
+    if rng.range(0, 2) == 1 { first_room } else { second_room }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{refresh_map, test_world, TEST_SEED};
+
+    #[test]
+    fn fallback_level_is_valid() {
+        let mut rng = RandomNumberGenerator::seeded(TEST_SEED);
+        let mut builder = FallbackBuilder::new(1);
+        builder.build_map(&mut rng);
+        let start = builder.starting_position();
+        assert!(validate_map(&builder.get_map(), (start.x, start.y)));
+    }
+
+    #[test]
+    fn every_bsp_room_is_reachable_from_the_first() {
+        for seed in 0..20 {
+            let mut rng = RandomNumberGenerator::seeded(TEST_SEED + seed);
+            let mut builder = BspBuilder::new(1);
+            builder.build_map(&mut rng);
+            let map = builder.get_map();
+            let reachable = orthogonally_reachable(&map, &first_room_center(&map));
+            for room in map.rooms.iter() {
+                let (x, y) = room.center();
+                assert!(reachable[map.xy_idx(x, y)], "seed {} cut off the room at ({}, {})", TEST_SEED + seed, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn every_builder_starts_the_player_on_open_floor() {
+        let generators = [MapGenerator::RoomsAndCorridors, MapGenerator::CellularAutomata, MapGenerator::Bsp];
+        for seed in 0..10 {
+            let mut rng = RandomNumberGenerator::seeded(TEST_SEED + seed);
+            let mut builders: Vec<Box<dyn MapBuilder>> = generators
+                .iter()
+                .map(|generator| random_builder(2, Some(*generator), &mut rng))
+                .collect();
+            builders.push(Box::new(FallbackBuilder::new(2)));
+            for builder in builders.iter_mut() {
+                builder.build_map(&mut rng);
+                let map = builder.get_map();
+                let start = builder.starting_position();
+                assert!(!map.is_out_of_bounds(start.x, start.y));
+                assert!(map.tiles[map.xy_idx(start.x, start.y)] == TileType::Floor);
+            }
+        }
+    }
+
+    #[test]
+    fn player_starts_on_free_floor_once_the_level_is_filled() {
+        for seed in 0..20 {
+            let mut rng = RandomNumberGenerator::seeded(TEST_SEED + seed);
+            let builder = build_validated_level(4, None, &mut rng);
+            let start = builder.starting_position();
+            let (mut ecs, _player) = test_world(builder.get_map(), start.x, start.y);
+            builder.spawn_entities(&mut ecs);
+            refresh_map(&mut ecs);
+
+            let map = ecs.fetch::<Map>();
+            let idx = map.xy_idx(start.x, start.y);
+            assert_eq!(map.tiles[idx], TileType::Floor);
+            assert!(!map.blocked[idx], "seed {} spawned something on the start", TEST_SEED + seed);
+        }
+    }
+
+    #[test]
+    fn all_the_floor_is_one_orthogonally_connected_area() {
+        let generators = [MapGenerator::RoomsAndCorridors, MapGenerator::CellularAutomata, MapGenerator::Bsp];
+        for seed in 0..10 {
+            let mut rng = RandomNumberGenerator::seeded(TEST_SEED + seed);
+            for generator in generators.iter() {
+                let mut builder = random_builder(3, Some(*generator), &mut rng);
+                builder.build_map(&mut rng);
+                let map = builder.get_map();
+                let reachable = orthogonally_reachable(&map, &builder.starting_position());
+                for (idx, tile) in map.tiles.iter().enumerate() {
+                    assert!(
+                        *tile == TileType::Wall || reachable[idx],
+                        "{:?} with seed {} left ({}, {}) cut off",
+                        generator, TEST_SEED + seed, idx as i32 % map.width, idx as i32 / map.width
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_meeting_at_a_corner_get_a_way_through() {
+        let mut map = Map::solid(1);
+        map.apply_room_to_map(&Rect::new(2, 2, 3, 3));
+        map.apply_room_to_map(&Rect::new(5, 5, 3, 3));
+        let start = Position{ x: 4, y: 4 };
+        assert!(!orthogonally_reachable(&map, &start)[map.xy_idx(7, 7)]);
+
+        connect_diagonal_pinches(&mut map, &start);
+
+        assert_eq!(map.tiles[map.xy_idx(7, 7)], TileType::Floor);
+        assert!(orthogonally_reachable(&map, &start)[map.xy_idx(7, 7)]);
+    }
+}
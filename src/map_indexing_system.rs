@@ -1,5 +1,5 @@
 use specs::prelude::*;
-use super::{Map, Position, BlocksTile};
+use super::{footprint, Map, Position, BlocksTile, Size};
 
 pub struct MapIndexingSystem {}
 
@@ -8,29 +8,33 @@ impl<'a> System<'a> for MapIndexingSystem {
         WriteExpect<'a, Map>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, BlocksTile>,
+        ReadStorage<'a, Size>,
         Entities<'a>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, position, blockers, entities) = data;
+        let (mut map, position, blockers, sizes, entities) = data;
 
         map.populate_blocked(); // adds blocking from walls and other blocking tiles
         map.clear_content_index();
 
         
         for (entity, position) in (&entities, &position).join() {
-            let idx = map.xy_idx(position.x, position.y);
-            
-
-            // If the entity is blocking, update the blocking list
-            let _p: Option<&BlocksTile> = blockers.get(entity);
-
-            if let Some(_p) = _p {
-                map.blocked[idx] = true;
+            // large creatures are indexed on every tile they cover
+            for tile in footprint(sizes.get(entity), position.x, position.y) {
+                if tile.x < 0 || tile.x >= map.width || tile.y < 0 || tile.y >= map.height {
+                    continue;
+                }
+                let idx = map.xy_idx(tile.x, tile.y);
+
+                // If the entity is blocking, update the blocking list
+                if blockers.get(entity).is_some() {
+                    map.blocked[idx] = true;
+                }
+
+                // push the entity to the appropriate index slot
+                map.tile_content[idx].push(entity);
             }
-
-            // push the entity to the appropriate index slot
-            map.tile_content[idx].push(entity);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use super::{
+    spawner, Initiative, Map, MyTurn, Name, OtherLevelPosition, ParticleLifetime, Player,
+    Position, MAPCOUNT,
+};
+
+// Every level the player has left, by depth, so going back finds it as it was.
+// The level being played is the Map resource and isn't kept in here
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+pub struct MasterDungeon {
+    maps: HashMap<i32, Map>,
+}
+
+impl MasterDungeon {
+    pub fn store_map(&mut self, map: &Map) {
+        self.maps.insert(map.depth, map.clone());
+    }
+
+    // takes a level back out to be played again, nothing on it is in view yet
+    pub fn take_map(&mut self, depth: i32) -> Option<Map> {
+        self.maps.remove(&depth).map(|mut map| {
+            map.tile_content = vec![Vec::new(); MAPCOUNT];
+            map.visible_tiles = vec![false; MAPCOUNT];
+            map.fov_cache.clear();
+            map
+        })
+    }
+}
+
+// Takes everything standing on the current level off the map until the player comes
+// back. Particles are just deleted, they'd be long gone by then anyway. Turns are taken
+// away and handed out fresh on thawing, poison, regeneration and hunger are kept but
+// their systems leave anything with an OtherLevelPosition alone, so they pick up where they were
+pub fn freeze_level_entities(ecs: &mut World) {
+    let depth = ecs.fetch::<Map>().depth;
+    let entities = ecs.entities();
+    let mut positions = ecs.write_storage::<Position>();
+    let mut other_level_positions = ecs.write_storage::<OtherLevelPosition>();
+    let players = ecs.read_storage::<Player>();
+    let particles = ecs.read_storage::<ParticleLifetime>();
+
+    let mut frozen: Vec<Entity> = Vec::new();
+    for (entity, pos, _player) in (&entities, &positions, !&players).join() {
+        if particles.get(entity).is_some() {
+            entities.delete(entity).expect("Unable to delete particle");
+            continue;
+        }
+        other_level_positions
+            .insert(entity, OtherLevelPosition{ x: pos.x, y: pos.y, depth })
+            .expect("Unable to insert other level position");
+        frozen.push(entity);
+    }
+
+    let mut initiatives = ecs.write_storage::<Initiative>();
+    let mut turns = ecs.write_storage::<MyTurn>();
+    for entity in frozen.iter() {
+        positions.remove(*entity);
+        initiatives.remove(*entity);
+        turns.remove(*entity);
+    }
+}
+
+// puts back everything that was left on depth, monsters waiting a fresh while for their turn
+pub fn thaw_level_entities(ecs: &mut World, depth: i32) {
+    let entities = ecs.entities();
+    let mut positions = ecs.write_storage::<Position>();
+    let mut other_level_positions = ecs.write_storage::<OtherLevelPosition>();
+    let mut initiatives = ecs.write_storage::<Initiative>();
+    let names = ecs.read_storage::<Name>();
+    let mut rng = ecs.write_resource::<rltk::RandomNumberGenerator>();
+
+    let mut thawed: Vec<Entity> = Vec::new();
+    for (entity, other) in (&entities, &other_level_positions).join() {
+        if other.depth == depth {
+            positions
+                .insert(entity, Position{ x: other.x, y: other.y })
+                .expect("Unable to insert position");
+            thawed.push(entity);
+        }
+    }
+
+    for entity in thawed.iter() {
+        other_level_positions.remove(*entity);
+        let initiative = names.get(*entity).and_then(|name| spawner::monster_initiative(&name.name, &mut rng));
+        if let Some(initiative) = initiative {
+            initiatives.insert(*entity, initiative).expect("Unable to insert initiative");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builders::{build_validated_level, map_with_up_stairs};
+    use crate::test_support::{test_world, TEST_SEED};
+    use crate::damage_system::DamageSystem;
+    use crate::status_effect_system::StatusEffectSystem;
+    use crate::{CombatStats, DamageOverTime, Monster, RunState, State, TileType};
+
+    #[test]
+    fn going_down_and_back_up_finds_the_level_as_it_was() {
+        let mut rng = rltk::RandomNumberGenerator::seeded(TEST_SEED);
+        let builder = build_validated_level(1, None, &mut rng);
+        let level_one = map_with_up_stairs(builder.as_ref());
+        let start = builder.starting_position();
+        let (mut ecs, player) = test_world(level_one.clone(), start.x, start.y);
+        builder.spawn_entities(&mut ecs);
+        let (monster, left_at) = {
+            let monsters = ecs.read_storage::<Monster>();
+            let positions = ecs.read_storage::<Position>();
+            let (monster, _monster, pos) = (&ecs.entities(), &monsters, &positions).join().next().expect("No monsters");
+            (monster, (pos.x, pos.y))
+        };
+        let mut gs = State { ecs };
+
+        gs.ecs.write_storage::<DamageOverTime>()
+            .insert(monster, DamageOverTime{ damage: 1, turns: 3, announced: false, from_player: true })
+            .unwrap();
+        let hp = gs.ecs.read_storage::<CombatStats>().get(monster).unwrap().hp;
+
+        gs.change_level(1);
+        assert_eq!(gs.ecs.fetch::<Map>().depth, 2);
+        assert!(gs.ecs.read_storage::<Position>().get(monster).is_none());
+        assert!(gs.ecs.read_storage::<Initiative>().get(monster).is_none());
+        // the poison waits for the level to come back rather than wearing off
+        *gs.ecs.write_resource::<RunState>() = RunState::PlayerTurn;
+        for _turn in 0..5 {
+            StatusEffectSystem {}.run_now(&gs.ecs);
+            DamageSystem {}.run_now(&gs.ecs);
+        }
+        gs.ecs.maintain();
+        assert_eq!(gs.ecs.read_storage::<DamageOverTime>().get(monster).map(|dot| dot.turns), Some(3));
+        assert_eq!(gs.ecs.read_storage::<CombatStats>().get(monster).unwrap().hp, hp);
+        gs.change_level(-1);
+
+        let map = gs.ecs.fetch::<Map>();
+        assert_eq!(map.tiles, level_one.tiles);
+        let arrived = gs.ecs.read_storage::<Position>().get(player).map(|pos| (pos.x, pos.y));
+        assert_eq!(arrived, Some(level_one.arrival_point(TileType::DownStairs)));
+        let back = gs.ecs.read_storage::<Position>().get(monster).map(|pos| (pos.x, pos.y));
+        assert_eq!(back, Some(left_at));
+        assert!(gs.ecs.read_storage::<Initiative>().get(monster).is_some());
+        assert_eq!(gs.ecs.read_storage::<DamageOverTime>().get(monster).map(|dot| dot.turns), Some(3));
+    }
+
+    #[test]
+    fn a_level_without_stairs_back_puts_the_player_at_its_start() {
+        let mut rng = rltk::RandomNumberGenerator::seeded(TEST_SEED);
+        let builder = build_validated_level(1, None, &mut rng);
+        let mut map = map_with_up_stairs(builder.as_ref());
+        for tile in map.tiles.iter_mut().filter(|tile| **tile == TileType::DownStairs) {
+            *tile = TileType::Floor;
+        }
+        let start = builder.starting_position();
+        assert_eq!(map.arrival_point(TileType::DownStairs), (start.x, start.y));
+    }
+}
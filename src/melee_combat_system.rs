@@ -1,13 +1,42 @@
-use crate::{DefenseBonus, Equipped, MeleePowerBonus};
-use rltk::console;
+use crate::{CritChance, Map, particle_system::{ParticleBuilder, PARTICLE_LIFETIME_MS}, Position, DefenseBonus, Durability, EquipmentSlot, Equipped, MeleePowerBonus, Player, Thorns, DamageOverTime, Venomous};
+use rltk::{RandomNumberGenerator, RGB};
 use specs::prelude::*;
 
-use super::{gamelog::GameLog, CombatStats, Name, SufferDamage, WantsToMelee};
+use super::{gamelog::{GameLog, LogCategory}, CombatStats, Name, SufferDamage, WantsToMelee};
 use specs::{Entities, ReadStorage, System, WriteStorage};
 
+// Takes a point of durability off an item, breaking it at zero.
+// Items without Durability never wear out
+fn wear_item(
+    item: Entity,
+    owned_by_player: bool,
+    entities: &Entities,
+    durability: &mut WriteStorage<Durability>,
+    equipped: &mut WriteStorage<Equipped>,
+    names: &ReadStorage<Name>,
+    log: &mut GameLog,
+) {
+    if let Some(wear) = durability.get_mut(item) {
+        wear.current -= 1;
+        if wear.current < 1 {
+            equipped.remove(item);
+            entities.delete(item).expect("Unable to delete broken item");
+            if owned_by_player {
+                if let Some(name) = names.get(item) {
+                    log.push(LogCategory::Combat, format!("Your {} shatters!", name.name));
+                }
+            }
+        }
+    }
+}
+
 pub struct MeleeCombatSystem {}
 
+// chance in a hundred that any hit is a critical one, before fighter and gear bonuses
+const BASE_CRIT_CHANCE: i32 = 10;
+
 impl<'a> System<'a> for MeleeCombatSystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         Entities<'a>,
         WriteExpect<'a, GameLog>,
@@ -17,7 +46,17 @@ impl<'a> System<'a> for MeleeCombatSystem {
         WriteStorage<'a, SufferDamage>,
         ReadStorage<'a, MeleePowerBonus>,
         ReadStorage<'a, DefenseBonus>,
-        ReadStorage<'a, Equipped>,
+        WriteStorage<'a, Equipped>,
+        ReadStorage<'a, Thorns>,
+        ReadStorage<'a, Player>,
+        WriteStorage<'a, Durability>,
+        ReadStorage<'a, Position>,
+        WriteExpect<'a, ParticleBuilder>,
+        ReadStorage<'a, Venomous>,
+        WriteStorage<'a, DamageOverTime>,
+        ReadStorage<'a, CritChance>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        ReadExpect<'a, Map>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
@@ -30,9 +69,21 @@ impl<'a> System<'a> for MeleeCombatSystem {
             mut inflict_damage,
             melee_power_bonuses,
             defense_bonuses,
-            equipped,
+            mut equipped,
+            thorns,
+            players,
+            mut durability,
+            positions,
+            mut particle_builder,
+            venomous,
+            mut damage_over_time,
+            crit_chances,
+            mut rng,
+            map,
         ) = data;
 
+        let mut worn_down: Vec<(Entity, Entity)> = Vec::new();
+
         for (entity, wants_melee, name, stats) in
             (&entities, &wants_melee, &names, &combat_stats).join()
         {
@@ -49,6 +100,13 @@ impl<'a> System<'a> for MeleeCombatSystem {
                 if target_stats.hp > 0 {
                     let target_name = names.get(wants_melee.target).unwrap();
 
+                    // every swing wears the weapon down, hit or not
+                    for (weapon, equipped_by) in (&entities, &equipped).join() {
+                        if equipped_by.owner == entity && equipped_by.slot == EquipmentSlot::Melee {
+                            worn_down.push((weapon, entity));
+                        }
+                    }
+
                     let mut defensive_bonus = 0;
                     for (_item_entity, defense_bonus, equipped_by) in (&entities, &defense_bonuses, &equipped).join() {
                         if equipped_by.owner == wants_melee.target {
@@ -56,23 +114,171 @@ impl<'a> System<'a> for MeleeCombatSystem {
                         }
                     }
 
-                    let damage = i32::max(0, (stats.power + offensive_bonus) - (target_stats.defense + defensive_bonus));
+                    let mut damage = i32::max(0, (stats.power + offensive_bonus) - (target_stats.defense + defensive_bonus));
+
+                    // a critical hit lands twice as hard
+                    if damage > 0 {
+                        let mut crit_chance = BASE_CRIT_CHANCE + crit_chances.get(entity).map_or(0, |c| c.percent);
+                        for (_item_entity, item_crit, equipped_by) in (&entities, &crit_chances, &equipped).join() {
+                            if equipped_by.owner == entity {
+                                crit_chance += item_crit.percent;
+                            }
+                        }
+                        if rng.roll_dice(1, 100) <= crit_chance {
+                            damage *= 2;
+                            let seen = positions
+                                .get(wants_melee.target)
+                                .is_some_and(|pos| map.visible_tiles[map.xy_idx(pos.x, pos.y)]);
+                            if seen {
+                                log.push(LogCategory::Combat, "A critical hit!".to_string());
+                            }
+                        }
+                    }
 
                     if damage == 0 {
-                        log.entries.push(format!(
+                        log.push(LogCategory::Combat, format!(
                             "{} is unable to hurt {}",
                             &name.name, &target_name.name
                         ));
                     } else {
-                        log.entries.push(format!(
+                        log.push(LogCategory::Combat, format!(
                             "{} hits {} for {} hp.",
                             &name.name, &target_name.name, damage
                         ));
-                        SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage);
+                        SufferDamage::new_damage(&mut inflict_damage, wants_melee.target, damage, players.get(entity).is_some());
+
+                        // only blows that land wear down the armour they land on
+                        for (armour, equipped_by) in (&entities, &equipped).join() {
+                            if equipped_by.owner == wants_melee.target && equipped_by.slot != EquipmentSlot::Melee {
+                                worn_down.push((armour, wants_melee.target));
+                            }
+                        }
+                        if let Some(pos) = positions.get(wants_melee.target) {
+                            particle_builder.request(pos.x, pos.y, RGB::named(rltk::ORANGE), rltk::to_cp437('‼'), PARTICLE_LIFETIME_MS);
+                        }
+
+                        // venom from a bite or a coated blade, a fresh dose only tops up how long it lasts
+                        let mut venom = venomous.get(entity).cloned();
+                        for (_item_entity, item_venom, equipped_by) in (&entities, &venomous, &equipped).join() {
+                            if equipped_by.owner == entity && equipped_by.slot == EquipmentSlot::Melee {
+                                venom = Some(item_venom.clone());
+                            }
+                        }
+                        if let Some(venom) = venom {
+                            let from_player = players.get(entity).is_some();
+                            match damage_over_time.get_mut(wants_melee.target) {
+                                Some(dot) => {
+                                    dot.damage = i32::max(dot.damage, venom.damage);
+                                    dot.turns = i32::max(dot.turns, venom.turns);
+                                    dot.from_player |= from_player;
+                                }
+                                None => {
+                                    damage_over_time
+                                        .insert(wants_melee.target, DamageOverTime{ damage: venom.damage, turns: venom.turns, announced: false, from_player })
+                                        .expect("Unable to insert poison");
+                                }
+                            }
+                        }
+
+                        // spikes on the defender or its gear hit back, this is plain damage
+                        // rather than a melee attack so it can't set off the attacker's thorns
+                        let mut reflected = thorns.get(wants_melee.target).map_or(0, |t| t.amount);
+                        for (_item_entity, item_thorns, equipped_by) in (&entities, &thorns, &equipped).join() {
+                            if equipped_by.owner == wants_melee.target {
+                                reflected += item_thorns.amount;
+                            }
+                        }
+                        if reflected > 0 {
+                            SufferDamage::new_damage(&mut inflict_damage, entity, reflected, players.get(wants_melee.target).is_some());
+                            if players.get(wants_melee.target).is_some() {
+                                log.push(LogCategory::Combat, format!(
+                                    "The {} is hurt by your spikes.",
+                                    &name.name
+                                ));
+                            } else {
+                                log.push(LogCategory::Combat, format!(
+                                    "{} is hurt by {}'s spikes.",
+                                    &name.name, &target_name.name
+                                ));
+                            }
+                        }
                     }
                 }
             }
         }
         wants_melee.clear();
+
+        for (item, owner) in worn_down {
+            wear_item(item, players.get(owner).is_some(), &entities, &mut durability, &mut equipped, &names, &mut log);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::damage_system::DamageSystem;
+    use crate::test_support::{damage_queued, equip, logged, open_map, refresh_map, spawn_at, test_world};
+
+    fn armour_left(ecs: &World, armour: Entity) -> i32 {
+        ecs.read_storage::<Durability>().get(armour).unwrap().current
+    }
+
+    #[test]
+    fn only_melee_hits_wear_armour_down() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let armour = equip(&mut ecs, player, "Leather Armor");
+        let orc = spawn_at(&mut ecs, 11, 10, "Orc");
+
+        // poison and the like go straight to the damage system
+        SufferDamage::new_damage(&mut ecs.write_storage::<SufferDamage>(), player, 1, false);
+        DamageSystem{}.run_now(&ecs);
+        assert_eq!(armour_left(&ecs, armour), 40);
+
+        ecs.write_storage::<WantsToMelee>().insert(orc, WantsToMelee{ target: player }).unwrap();
+        MeleeCombatSystem{}.run_now(&ecs);
+        assert_eq!(armour_left(&ecs, armour), 39);
+    }
+
+    // the melee damage attacker queues up on target in one swing
+    fn swing(ecs: &mut World, attacker: Entity, target: Entity) -> i32 {
+        ecs.write_storage::<WantsToMelee>().insert(attacker, WantsToMelee{ target }).unwrap();
+        MeleeCombatSystem{}.run_now(ecs);
+        let damage = damage_queued(ecs, target);
+        ecs.write_storage::<SufferDamage>().clear();
+        damage
+    }
+
+    #[test]
+    fn dagger_adds_its_bonus_to_the_hit() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        // no crits, so every hit is exactly power less defense
+        ecs.write_storage::<CritChance>().insert(player, CritChance{ percent: -100 }).unwrap();
+        let goblin = spawn_at(&mut ecs, 11, 10, "Goblin");
+        assert_eq!(swing(&mut ecs, player, goblin), 4);
+
+        equip(&mut ecs, player, "Dagger");
+        assert_eq!(swing(&mut ecs, player, goblin), 6);
+    }
+
+    #[test]
+    fn a_sure_crit_doubles_the_hit() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        ecs.write_storage::<CritChance>().insert(player, CritChance{ percent: 100 }).unwrap();
+        let goblin = spawn_at(&mut ecs, 11, 10, "Goblin");
+        refresh_map(&mut ecs);
+        assert_eq!(swing(&mut ecs, player, goblin), 8);
+        assert!(logged(&ecs, "A critical hit!"));
+    }
+
+    #[test]
+    fn crits_out_of_sight_go_unmentioned() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let orc = spawn_at(&mut ecs, 40, 30, "Orc");
+        let goblin = spawn_at(&mut ecs, 41, 30, "Goblin");
+        ecs.write_storage::<CritChance>().insert(orc, CritChance{ percent: 100 }).unwrap();
+        refresh_map(&mut ecs);
+        assert!(swing(&mut ecs, orc, goblin) > 0);
+        assert!(!logged(&ecs, "A critical hit!"));
     }
 }
@@ -1,7 +1,9 @@
-use crate::{RunState, WantsToMelee, Confusion};
+use crate::{footprint, inventory_system::name_of, random_neighbour, gamelog::{GameLog, LogCategory}, AggroRange, CanFlee, CombatStats, Confusion, EntityMoved, Faction, MyTurn, Name, RangedAttack, Rooted, RunState, Size, SufferDamage, Summoner, TileType, WantsToMelee, WantsToSummon};
 
-use super::{Map, Monster, Name, Position, Viewshed};
-use rltk::{console, Point};
+use super::{Map, Monster, Position, Viewshed};
+use crate::pathfinding::DijkstraMap;
+use rltk::{Point, RandomNumberGenerator};
+use std::collections::HashMap;
 use specs::prelude::*;
 
 pub struct MonsterAI {}
@@ -10,8 +12,6 @@ impl<'a> System<'a> for MonsterAI {
     #[allow(clippy::type_complexity)]
     type SystemData = (
         WriteExpect<'a, Map>,
-        ReadExpect<'a, Point>,
-        ReadExpect<'a, Entity>,
         ReadExpect<'a, RunState>,
         Entities<'a>,
         WriteStorage<'a, Viewshed>,
@@ -19,55 +19,410 @@ impl<'a> System<'a> for MonsterAI {
         WriteStorage<'a, Position>,
         WriteStorage<'a, WantsToMelee>,
         WriteStorage<'a, Confusion>,
+        ReadStorage<'a, Faction>,
+        ReadStorage<'a, CombatStats>,
+        ReadStorage<'a, Size>,
+        ReadStorage<'a, AggroRange>,
+        ReadStorage<'a, RangedAttack>,
+        ReadStorage<'a, Name>,
+        WriteStorage<'a, SufferDamage>,
+        WriteExpect<'a, GameLog>,
+        WriteStorage<'a, CanFlee>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        WriteStorage<'a, EntityMoved>,
+        ReadStorage<'a, MyTurn>,
+        WriteStorage<'a, Summoner>,
+        WriteStorage<'a, WantsToSummon>,
+        WriteStorage<'a, Rooted>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, player_pos, player_entity, runstate, entities, mut viewshed, monster, mut position, mut wants_to_melee, mut confusion) = data;
+        let (mut map, runstate, entities, mut viewshed, monster, mut position, mut wants_to_melee, mut confusion, factions, combat_stats, sizes, aggro_ranges, ranged_attacks, names, mut inflict_damage, mut log, mut can_flee, mut rng, mut moved, my_turn, mut summoners, mut wants_summon, mut rooted) = data;
 
         if *runstate != RunState::MonsterTurn {
             return;
         }
 
-        for (entity, mut viewshed, _monster, mut pos) in
-            (&entities, &mut viewshed, &monster, &mut position).join()
-        {
-            let mut can_act = true;
+        // Monsters act in ascending entity id order, which is the order joins walk the
+        // storages in, so with the run's seeded rng the same game state always plays out
+        // the same way. Only those the initiative system says are ready get to act at all
+        let acting: Vec<Entity> = (&entities, &monster, &viewshed, &position, &my_turn)
+            .join()
+            .map(|(entity, _monster, _viewshed, _pos, _turn)| entity)
+            .collect();
 
-            let is_confused = confusion.get_mut(entity);
-            if let Some(is_confused) = is_confused {
+        // walls don't change during the monsters' turn, so fields stay valid for every monster
+        let mut fields: HashMap<(Entity, i32, i32), DijkstraMap> = HashMap::new();
+
+        for entity in acting {
+            if let Some(summoner) = summoners.get_mut(entity) {
+                summoner.cooldown = i32::max(0, summoner.cooldown - 1);
+            }
+
+            // a rooted monster can still fight whatever is in reach, it only loses a turn of the root
+            // when it tries to move, like the player does
+            let is_rooted = rooted.get(entity).is_some();
+
+            // confused monsters stagger about at random instead of acting
+            if let Some(is_confused) = confusion.get_mut(entity) {
                 is_confused.turns -= 1;
                 if is_confused.turns < 1 {
                     confusion.remove(entity);
                 }
-                can_act = false;
+                let next = {
+                    let pos = position.get(entity).unwrap();
+                    random_neighbour(&mut rng, Point::new(pos.x, pos.y))
+                };
+                if !struggle(&mut rooted, entity) {
+                    step(&mut map, entity, sizes.get(entity), position.get_mut(entity).unwrap(), viewshed.get_mut(entity).unwrap(), &mut moved, next);
+                }
+                continue;
             }
 
+            let aggro_range = aggro_ranges.get(entity).map(|aggro| aggro.tiles);
+            let target = nearest_hostile(entity, aggro_range, &entities, &factions, &combat_stats, &position, &viewshed);
+            let viewshed = viewshed.get_mut(entity).unwrap();
+            let pos = position.get_mut(entity).unwrap();
+
+            if let Some((target, target_pos)) = target {
+                let own_tiles = footprint(sizes.get(entity), pos.x, pos.y);
+                let target_tiles = footprint(sizes.get(target), target_pos.x, target_pos.y);
+                let adjacent = own_tiles.iter().any(|own| {
+                    target_tiles
+                        .iter()
+                        .any(|tile| {
+                            rltk::DistanceAlg::Pythagoras.distance2d(*own, *tile) < 1.5
+                                && !map.cuts_corner(own.x, own.y, tile.x - own.x, tile.y - own.y)
+                        })
+                });
+                // archers shoot at anything they can see in range rather than walking up to it
+                let shot = ranged_attacks.get(entity).filter(|ranged| {
+                    target_tiles.iter().any(|tile| {
+                        viewshed.visible_tiles.contains(tile)
+                            && rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), *tile) <= ranged.range as f32
+                    })
+                });
+
+                // badly hurt monsters run for it, and only fight back once cornered
+                let wounded = match (can_flee.get_mut(entity), combat_stats.get(entity)) {
+                    (Some(flee), Some(stats)) => {
+                        let below = stats.hp * 100 < stats.max_hp * flee.threshold_pct;
+                        if below && !flee.fleeing {
+                            log.push(LogCategory::Combat, format!("The {} flees in terror!", name_of(&names, entity)));
+                        }
+                        flee.fleeing = below;
+                        below
+                    }
+                    _ => false,
+                };
+                if wounded && !is_rooted {
+                    if let Some(next) = flee_step(&map, sizes.get(entity), pos, target_pos) {
+                        step(&mut map, entity, sizes.get(entity), pos, viewshed, &mut moved, next);
+                        continue;
+                    }
+                }
 
-            // Only act if not confused
-            if can_act {
-                let distance =
-                    rltk::DistanceAlg::Pythagoras.distance2d(Point::new(pos.x, pos.y), *player_pos);
-                if distance < 1.5 {
-                    wants_to_melee.insert(entity, WantsToMelee{target: *player_entity}).expect("Could not insert want_to_melee");
-                } else if viewshed.visible_tiles.contains(&*player_pos) {
+                // summoners call for help as soon as they're able, as long as they can see who it's against
+                let sees_target = target_tiles.iter().any(|tile| viewshed.visible_tiles.contains(tile));
+                if let Some(summoner) = summoners.get_mut(entity).filter(|summoner| summoner.cooldown <= 0 && sees_target) {
+                    if let Some(spot) = summon_spot(&map, pos, &mut rng) {
+                        let idx = map.xy_idx(spot.x, spot.y);
+                        map.blocked[idx] = true;
+                        summoner.cooldown = summoner.every;
+                        wants_summon
+                            .insert(entity, WantsToSummon{spawn_name: summoner.spawn_name.clone(), x: spot.x, y: spot.y})
+                            .expect("Unable to insert summon");
+                        if map.visible_tiles[map.xy_idx(pos.x, pos.y)] {
+                            log.push(LogCategory::Combat, format!(
+                                "The {} summons a {}!",
+                                name_of(&names, entity), summoner.spawn_name.to_lowercase()
+                            ));
+                        }
+                        continue;
+                    }
+                }
+
+                if adjacent {
+                    wants_to_melee.insert(entity, WantsToMelee{target}).expect("Could not insert want_to_melee");
+                } else if let Some(ranged) = shot {
+                    SufferDamage::new_damage(&mut inflict_damage, target, ranged.damage, false);
+                    log.push(LogCategory::Combat, format!(
+                        "{} shoots {} for {} hp.",
+                        name_of(&names, entity), name_of(&names, target), ranged.damage
+                    ));
+                } else if !struggle(&mut rooted, entity) {
+                    // one shared distance field per target covers the usual case of everyone chasing
+                    // the same creature, A* is only needed when that way is blocked or for big bodies
+                    if sizes.get(entity).is_none() {
+                        let field = fields.entry((target, target_pos.x, target_pos.y)).or_insert_with(|| {
+                            let goals: Vec<usize> = target_tiles.iter().map(|tile| map.xy_idx(tile.x, tile.y)).collect();
+                            DijkstraMap::build(&map, &goals)
+                        });
+                        if let Some(exit) = field.find_lowest_exit(map.xy_idx(pos.x, pos.y)) {
+                            let next = Point::new(exit as i32 % map.width, exit as i32 / map.width);
+                            if fits(&map, None, pos, next) {
+                                step(&mut map, entity, None, pos, viewshed, &mut moved, next);
+                                continue;
+                            }
+                        }
+                    }
+
+                    // creatures block the tiles they stand on, so open up both ends for the search
+                    let mut opened = Vec::new();
+                    for tile in own_tiles.iter().chain(target_tiles.iter()) {
+                        let idx = map.xy_idx(tile.x, tile.y);
+                        opened.push((idx, map.blocked[idx]));
+                        map.blocked[idx] = false;
+                    }
                     let path = rltk::a_star_search(
                         map.xy_idx(pos.x, pos.y) as i32,
-                        map.xy_idx(player_pos.x, player_pos.y) as i32,
-                        &mut *map,
+                        map.xy_idx(target_pos.x, target_pos.y) as i32,
+                        &*map,
                     );
+                    for (idx, was_blocked) in opened.iter().rev() {
+                        map.blocked[*idx] = *was_blocked;
+                    }
 
                     // steps[0] is always the current location
                     if path.success && path.steps.len() > 1 {
-                        let mut idx = map.xy_idx(pos.x, pos.y);
-                        map.blocked[idx] = false;
-                        pos.x = path.steps[1] as i32 % map.width;
-                        pos.y = path.steps[1] as i32 / map.width;
-                        idx = map.xy_idx(pos.x, pos.y);
-                        map.blocked[idx] = true;
-                        viewshed.dirty = true;
+                        let next = Point::new(path.steps[1] as i32 % map.width, path.steps[1] as i32 / map.width);
+                        step(&mut map, entity, sizes.get(entity), pos, viewshed, &mut moved, next);
                     }
                 }
             }
         }
     }
 }
+
+// a rooted creature trying to move only wears its root down a turn instead, true if it was held
+fn struggle(rooted: &mut WriteStorage<Rooted>, entity: Entity) -> bool {
+    match rooted.get_mut(entity) {
+        Some(root) => {
+            root.turns -= 1;
+            if root.turns < 1 {
+                rooted.remove(entity);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+// whether a creature at pos could move its whole body over to next,
+// paths are only found for the corner so every tile has to be checked
+fn fits(map: &Map, size: Option<&Size>, pos: &Position, next: Point) -> bool {
+    let own_tiles = footprint(size, pos.x, pos.y);
+    footprint(size, next.x, next.y).iter().all(|tile| {
+        !map.is_out_of_bounds(tile.x, tile.y)
+            && (own_tiles.contains(tile) || !map.blocked[map.xy_idx(tile.x, tile.y)])
+    })
+}
+
+// moves a creature to next if it fits there, keeping the blocked tiles up to date
+// and marking it as moved so traps can go off
+fn step(
+    map: &mut Map,
+    entity: Entity,
+    size: Option<&Size>,
+    pos: &mut Position,
+    viewshed: &mut Viewshed,
+    moved: &mut WriteStorage<EntityMoved>,
+    next: Point,
+) {
+    if !fits(map, size, pos, next) {
+        return;
+    }
+    for tile in footprint(size, pos.x, pos.y) {
+        let idx = map.xy_idx(tile.x, tile.y);
+        map.blocked[idx] = false;
+    }
+    pos.x = next.x;
+    pos.y = next.y;
+    for tile in footprint(size, pos.x, pos.y) {
+        let idx = map.xy_idx(tile.x, tile.y);
+        map.blocked[idx] = true;
+    }
+    viewshed.dirty = true;
+    moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
+}
+
+// a random free floor tile next to pos for a summoned monster, nothing standing or lying on it
+fn summon_spot(map: &Map, pos: &Position, rng: &mut RandomNumberGenerator) -> Option<Point> {
+    let mut free: Vec<Point> = Vec::new();
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let spot = Point::new(pos.x + dx, pos.y + dy);
+            if (dx, dy) == (0, 0) || map.is_out_of_bounds(spot.x, spot.y) || map.cuts_corner(pos.x, pos.y, dx, dy) {
+                continue;
+            }
+            let idx = map.xy_idx(spot.x, spot.y);
+            if map.tiles[idx] == TileType::Floor && !map.blocked[idx] && map.tile_content[idx].is_empty() {
+                free.push(spot);
+            }
+        }
+    }
+    if free.is_empty() {
+        None
+    } else {
+        Some(free[rng.range(0, free.len())])
+    }
+}
+
+// the neighbouring spot that gets furthest from the threat, if any is further than staying put
+fn flee_step(map: &Map, size: Option<&Size>, pos: &Position, threat: Point) -> Option<Point> {
+    let distance = |point: Point| rltk::DistanceAlg::Pythagoras.distance2d(point, threat);
+    let mut best = (Point::new(pos.x, pos.y), distance(Point::new(pos.x, pos.y)));
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let next = Point::new(pos.x + dx, pos.y + dy);
+            if (dx, dy) != (0, 0) && distance(next) > best.1 && fits(map, size, pos, next)
+                && !map.cuts_corner(pos.x, pos.y, dx, dy)
+            {
+                best = (next, distance(next));
+            }
+        }
+    }
+    if best.0 == Point::new(pos.x, pos.y) {
+        None
+    } else {
+        Some(best.0)
+    }
+}
+
+// the closest living creature of another faction this monster can see or sense
+// within its aggro range, creatures without a faction are treated as hostile to everyone
+fn nearest_hostile(
+    entity: Entity,
+    aggro_range: Option<i32>,
+    entities: &Entities,
+    factions: &ReadStorage<Faction>,
+    combat_stats: &ReadStorage<CombatStats>,
+    positions: &WriteStorage<Position>,
+    viewsheds: &WriteStorage<Viewshed>,
+) -> Option<(Entity, Point)> {
+    let viewshed = viewsheds.get(entity)?;
+    let pos = positions.get(entity)?;
+    let here = Point::new(pos.x, pos.y);
+    let own_faction = factions.get(entity);
+
+    (entities, combat_stats, positions)
+        .join()
+        .filter(|(other, stats, _pos)| *other != entity && stats.hp > 0)
+        .filter(|(other, _stats, _pos)| match (own_faction, factions.get(*other)) {
+            (Some(own), Some(theirs)) => own.name != theirs.name,
+            _ => true,
+        })
+        .map(|(other, _stats, pos)| (other, Point::new(pos.x, pos.y)))
+        .filter(|(_other, point)| {
+            viewshed.visible_tiles.contains(point)
+                || aggro_range.is_some_and(|tiles| {
+                    rltk::DistanceAlg::Pythagoras.distance2d(here, *point) <= tiles as f32
+                })
+        })
+        .min_by(|(a, a_pos), (b, b_pos)| {
+            let a_dist = rltk::DistanceAlg::Pythagoras.distance2d(here, *a_pos);
+            let b_dist = rltk::DistanceAlg::Pythagoras.distance2d(here, *b_pos);
+            a_dist
+                .partial_cmp(&b_dist)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.id().cmp(&b.id()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{open_map, refresh_map, spawn_at, test_world};
+
+    fn spot(ecs: &World, entity: Entity) -> (i32, i32) {
+        let pos = ecs.read_storage::<Position>().get(entity).cloned().unwrap();
+        (pos.x, pos.y)
+    }
+
+    // where everything stands after a few monster turns of a fixed fight
+    fn play_out() -> Vec<(i32, i32)> {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let monsters = [
+            spawn_at(&mut ecs, 16, 10, "Goblin"),
+            spawn_at(&mut ecs, 16, 12, "Orc"),
+            spawn_at(&mut ecs, 12, 14, "Goblin"),
+        ];
+        ecs.write_storage::<Confusion>().insert(monsters[2], Confusion{ turns: 4 }).unwrap();
+        *ecs.write_resource::<RunState>() = RunState::MonsterTurn;
+
+        for _turn in 0..6 {
+            refresh_map(&mut ecs);
+            for monster in monsters.iter() {
+                ecs.write_storage::<MyTurn>().insert(*monster, MyTurn{}).unwrap();
+            }
+            MonsterAI{}.run_now(&ecs);
+            ecs.write_storage::<WantsToMelee>().clear();
+            ecs.maintain();
+        }
+
+        monsters.iter().map(|monster| spot(&ecs, *monster)).collect()
+    }
+
+    #[test]
+    fn same_seed_plays_out_the_same() {
+        let first = play_out();
+        assert_eq!(first, play_out());
+        assert_ne!(first, vec![(16, 10), (16, 12), (12, 14)], "nobody moved");
+    }
+
+    #[test]
+    fn confused_monsters_stagger_instead_of_fighting() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let beside = spawn_at(&mut ecs, 11, 10, "Goblin");
+        let away = spawn_at(&mut ecs, 30, 30, "Goblin");
+        for goblin in [beside, away].iter() {
+            ecs.write_storage::<Confusion>().insert(*goblin, Confusion{ turns: 3 }).unwrap();
+        }
+        *ecs.write_resource::<RunState>() = RunState::MonsterTurn;
+
+        for turns_left in (1..3).rev() {
+            refresh_map(&mut ecs);
+            let was_at = spot(&ecs, away);
+            for goblin in [beside, away].iter() {
+                ecs.write_storage::<MyTurn>().insert(*goblin, MyTurn{}).unwrap();
+            }
+            MonsterAI{}.run_now(&ecs);
+
+            let confusion = ecs.read_storage::<Confusion>();
+            assert_eq!(confusion.get(beside).map(|c| c.turns), Some(turns_left));
+            assert!(ecs.read_storage::<WantsToMelee>().get(beside).is_none());
+            // nothing is in its way, so it always ends up somewhere else
+            assert_ne!(spot(&ecs, away), was_at);
+        }
+    }
+
+    #[test]
+    fn rooted_monsters_stay_put_until_they_tear_free() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let chaser = spawn_at(&mut ecs, 14, 10, "Goblin");
+        let fighter = spawn_at(&mut ecs, 11, 10, "Goblin");
+        for goblin in [chaser, fighter].iter() {
+            ecs.write_storage::<Rooted>().insert(*goblin, Rooted{ turns: 2 }).unwrap();
+        }
+        *ecs.write_resource::<RunState>() = RunState::MonsterTurn;
+
+        let mut chaser_spots = Vec::new();
+        for _turn in 0..3 {
+            refresh_map(&mut ecs);
+            for goblin in [chaser, fighter].iter() {
+                ecs.write_storage::<MyTurn>().insert(*goblin, MyTurn{}).unwrap();
+            }
+            MonsterAI{}.run_now(&ecs);
+            ecs.write_storage::<WantsToMelee>().clear();
+            ecs.maintain();
+            chaser_spots.push(spot(&ecs, chaser));
+        }
+
+        // two turns spent pulling free, then it's off after the player
+        assert_eq!(&chaser_spots[..2], &[(14, 10), (14, 10)]);
+        assert_ne!(chaser_spots[2], (14, 10));
+        assert!(ecs.read_storage::<Rooted>().get(chaser).is_none());
+        // fighting doesn't loosen the web
+        assert_eq!(ecs.read_storage::<Rooted>().get(fighter).map(|root| root.turns), Some(2));
+    }
+}
@@ -0,0 +1,87 @@
+use rltk::{Rltk, RGB};
+use specs::prelude::*;
+
+use super::{ParticleLifetime, Position, Renderable};
+
+// drawn over everything else on the tile
+const RENDER_ORDER_PARTICLE: i32 = -1;
+
+// how long hit and heal markers stay up
+pub const PARTICLE_LIFETIME_MS: f32 = 200.0;
+
+struct ParticleRequest {
+    x: i32,
+    y: i32,
+    fg: RGB,
+    glyph: rltk::FontCharType,
+    lifetime: f32,
+}
+
+// Systems can't create entities with components of their own choosing mid-run,
+// so they queue particles here and ParticleSpawnSystem makes them afterwards
+pub struct ParticleBuilder {
+    requests: Vec<ParticleRequest>,
+}
+
+impl ParticleBuilder {
+    pub fn new() -> ParticleBuilder {
+        ParticleBuilder { requests: Vec::new() }
+    }
+
+    pub fn request(&mut self, x: i32, y: i32, fg: RGB, glyph: rltk::FontCharType, lifetime: f32) {
+        self.requests.push(ParticleRequest { x, y, fg, glyph, lifetime });
+    }
+}
+
+pub struct ParticleSpawnSystem {}
+
+impl<'a> System<'a> for ParticleSpawnSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Position>,
+        WriteStorage<'a, Renderable>,
+        WriteStorage<'a, ParticleLifetime>,
+        WriteExpect<'a, ParticleBuilder>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut positions, mut renderables, mut particles, mut particle_builder) = data;
+
+        for request in particle_builder.requests.drain(..) {
+            let particle = entities.create();
+            positions
+                .insert(particle, Position { x: request.x, y: request.y })
+                .expect("Unable to insert position");
+            renderables
+                .insert(particle, Renderable {
+                    glyph: request.glyph,
+                    fg: request.fg,
+                    bg: RGB::named(rltk::BLACK),
+                    render_order: RENDER_ORDER_PARTICLE,
+                })
+                .expect("Unable to insert renderable");
+            particles
+                .insert(particle, ParticleLifetime { lifetime_ms: request.lifetime })
+                .expect("Unable to insert lifetime");
+        }
+    }
+}
+
+// ages every particle by the time the last frame took and removes the ones that ran out
+pub fn cull_dead_particles(ecs: &mut World, ctx: &Rltk) {
+    let mut dead_particles: Vec<Entity> = Vec::new();
+    {
+        let entities = ecs.entities();
+        let mut particles = ecs.write_storage::<ParticleLifetime>();
+        for (entity, particle) in (&entities, &mut particles).join() {
+            particle.lifetime_ms -= ctx.frame_time_ms;
+            if particle.lifetime_ms < 0.0 {
+                dead_particles.push(entity);
+            }
+        }
+    }
+    for dead in dead_particles.iter() {
+        ecs.delete_entity(*dead).expect("Particle will not die");
+    }
+}
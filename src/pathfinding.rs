@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+
+use super::Map;
+
+// tiles the goals can't be reached from keep this distance
+pub const UNREACHABLE: i32 = i32::MAX;
+
+// Walking distance from every tile to the nearest goal, moving 8-way over anything
+// that isn't a wall or a closed door without cutting between two of them. Built once it can guide any number of creatures toward the
+// same goals, where a_star_search has to run again for each of them.
+pub struct DijkstraMap {
+    pub distances: Vec<i32>,
+    walls: Vec<bool>,
+    width: i32,
+    height: i32,
+}
+
+impl DijkstraMap {
+    pub fn build(map: &Map, goals: &[usize]) -> DijkstraMap {
+        let mut distances = vec![UNREACHABLE; map.tiles.len()];
+        let mut open: VecDeque<usize> = VecDeque::new();
+        for goal in goals.iter().filter(|goal| **goal < map.tiles.len()) {
+            distances[*goal] = 0;
+            open.push_back(*goal);
+        }
+
+        // the same rule creatures move by, so the field never leads into a closed door
+        let walls = (0..map.tiles.len()).map(|idx| map.is_impassable(idx)).collect();
+        let mut dijkstra = DijkstraMap{ distances, walls, width: map.width, height: map.height };
+
+        // every step costs the same, so a breadth first flood visits tiles in distance order
+        while let Some(idx) = open.pop_front() {
+            let next_distance = dijkstra.distances[idx] + 1;
+            for neighbour in dijkstra.neighbours(idx) {
+                if !dijkstra.walls[neighbour] && dijkstra.distances[neighbour] > next_distance {
+                    dijkstra.distances[neighbour] = next_distance;
+                    open.push_back(neighbour);
+                }
+            }
+        }
+
+        dijkstra
+    }
+
+    // the neighbouring tile closest to a goal, if any is closer than idx itself
+    pub fn find_lowest_exit(&self, idx: usize) -> Option<usize> {
+        self.exits_toward_goal(idx).into_iter().next()
+    }
+
+    // every neighbour closer to a goal than idx, closest first
+    pub fn exits_toward_goal(&self, idx: usize) -> Vec<usize> {
+        let mut exits: Vec<usize> = self
+            .neighbours(idx)
+            .into_iter()
+            .filter(|neighbour| self.distances[*neighbour] < self.distances[idx])
+            .collect();
+        exits.sort_by_key(|neighbour| self.distances[*neighbour]);
+        exits
+    }
+
+    fn neighbours(&self, idx: usize) -> Vec<usize> {
+        let x = idx as i32 % self.width;
+        let y = idx as i32 / self.width;
+        let mut neighbours = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x + dx, y + dy);
+                if (dx, dy) == (0, 0) || nx < 0 || nx >= self.width || ny < 0 || ny >= self.height {
+                    continue;
+                }
+                let cuts_corner = dx != 0 && dy != 0
+                    && self.walls[(y * self.width + nx) as usize]
+                    && self.walls[(ny * self.width + x) as usize];
+                if !cuts_corner {
+                    neighbours.push((ny * self.width + nx) as usize);
+                }
+            }
+        }
+        neighbours
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{corridor_map, open_map};
+    use crate::TileType;
+
+    #[test]
+    fn distances_fall_toward_the_goal() {
+        let map = open_map();
+        let goal = map.xy_idx(40, 25);
+        let field = DijkstraMap::build(&map, &[goal]);
+
+        // following the lowest exit from anywhere gets there one step closer each time
+        let mut idx = map.xy_idx(3, 3);
+        while idx != goal {
+            let next = field.find_lowest_exit(idx).expect("Stuck before the goal");
+            assert_eq!(field.distances[next], field.distances[idx] - 1);
+            idx = next;
+        }
+    }
+
+    #[test]
+    fn closed_doors_stop_the_field() {
+        let mut map = corridor_map(10, 1, 20);
+        let door = map.xy_idx(10, 10);
+        map.tiles[door] = TileType::Door;
+        map.populate_blocked();
+        let goal = map.xy_idx(15, 10);
+
+        let field = DijkstraMap::build(&map, &[goal]);
+        assert_eq!(field.distances[map.xy_idx(5, 10)], UNREACHABLE);
+
+        map.open_door(door);
+        let field = DijkstraMap::build(&map, &[goal]);
+        assert_eq!(field.distances[map.xy_idx(5, 10)], 10);
+    }
+}
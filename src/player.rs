@@ -1,12 +1,8 @@
-use crate::{CombatStats, Item, Monster, RunState, Viewshed, WantsToMelee, WantsToPickUpItem, gamelog::GameLog};
-use rltk::{Point, Rltk, VirtualKeyCode, console};
+use crate::{keybindings::{Action, KeyBindings}, pathfinding::DijkstraMap, CombatStats, EntityMoved, EntryTrigger, Hidden, Equippable, Fountain, Item, Monster, Renderable, Rooted, RunState, Viewshed, WantsToMelee, WantsToPickUpItem, WantsToSearch, WantsToUseItem, gamelog::{GameLog, LogCategory}};
+use rltk::{Point, Rltk, VirtualKeyCode, RGB};
 use specs::prelude::*;
 
-use super::{Map, Player, Position, State, TileType};
-use std::{
-    cmp::{max, min},
-    usize,
-};
+use super::{Map, Player, Position, Settings, State, TileType};
 
 pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
     let mut positions = ecs.write_storage::<Position>();
@@ -14,22 +10,52 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
     let mut viewsheds = ecs.write_storage::<Viewshed>();
     let mut player_pos = ecs.write_resource::<Point>();
 
-    let combat_stats = ecs.read_storage::<CombatStats>();
-    let map = ecs.fetch::<Map>();
+    let mut combat_stats = ecs.write_storage::<CombatStats>();
+    let mut fountains = ecs.write_storage::<Fountain>();
+    let mut renderables = ecs.write_storage::<Renderable>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+    let mut map = ecs.fetch_mut::<Map>();
 
     let entities = ecs.entities();
     let mut wants_to_melee = ecs.write_storage::<WantsToMelee>();
+    let mut entity_moved = ecs.write_storage::<EntityMoved>();
+    let mut rooted = ecs.write_storage::<Rooted>();
 
     for (entity, _player, pos, viewshed) in (&entities, &mut players, &mut positions, &mut viewsheds).join() {
         let dest_x  = pos.x + delta_x;
         let dest_y = pos.y + delta_y;
-        if out_of_bounds(dest_x, dest_y, &map) {
+        if map.is_out_of_bounds(dest_x, dest_y) {
             return;
         }
         
-        let destination_idx = map.xy_idx(pos.x + delta_x, pos.y + delta_y);
+        // no squeezing diagonally between two walls, not even to attack
+        if map.cuts_corner(pos.x, pos.y, delta_x, delta_y) {
+            return;
+        }
+
+        let destination_idx = map.xy_idx(dest_x, dest_y);
 
         for potential_target in map.tile_content[destination_idx].iter() {
+            // bumping into a fountain drinks from it instead of moving
+            if let Some(fountain) = fountains.get_mut(*potential_target) {
+                if fountain.uses > 0 {
+                    fountain.uses -= 1;
+                    if let Some(stats) = combat_stats.get_mut(entity) {
+                        stats.hp = stats.max_hp;
+                    }
+                    gamelog.push(LogCategory::Flavor, "You drink from the fountain.");
+
+                    if fountain.uses < 1 {
+                        if let Some(render) = renderables.get_mut(*potential_target) {
+                            render.fg = RGB::named(rltk::DIMGREY);
+                        }
+                    }
+                } else {
+                    gamelog.push(LogCategory::Flavor, "The fountain has run dry.");
+                }
+                return;
+            }
+
             let target = combat_stats.get(*potential_target);
 
             // attaches a WantsToMelee to the attacker
@@ -41,23 +67,41 @@ pub fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
             }
         }
 
+        // bumping a closed door opens it, which takes the turn
+        if map.is_closed_door(destination_idx) {
+            map.open_door(destination_idx);
+            viewshed.dirty = true;
+            gamelog.push(LogCategory::Flavor, "You open the door.");
+            return;
+        }
+
+        // stuck in a web, trying to move just wears it down
+        if let Some(root) = rooted.get_mut(entity) {
+            root.turns -= 1;
+            if root.turns < 1 {
+                rooted.remove(entity);
+                gamelog.push(LogCategory::Combat, "You tear free of the webbing.");
+            } else {
+                gamelog.push(LogCategory::Combat, "You struggle against the webbing.");
+            }
+            return;
+        }
+
         if !map.blocked[destination_idx] {
-            pos.x = min(79, max(0, pos.x + delta_x));
-            pos.y = min(49, max(0, pos.y + delta_y));
+            // already known to be on the map, whatever size it is
+            pos.x = dest_x;
+            pos.y = dest_y;
 
             viewshed.dirty = true;
             player_pos.x = pos.x;
             player_pos.y = pos.y;
+            entity_moved.insert(entity, EntityMoved{}).expect("Unable to insert marker");
         }
     }
 }
 
 
-fn out_of_bounds(dest_x: i32, dest_y: i32, map: &Map) -> bool {
-    dest_x < 1 || dest_x > map.width - 1 || dest_y < 1 || dest_y > map.height - 1
-}
-
-
+// picks up everything on the player's tile in one go
 fn get_item(ecs: &mut World) {
     let player_pos = ecs.fetch::<Point>();
     let player_entity = ecs.fetch::<Entity>();
@@ -66,66 +110,196 @@ fn get_item(ecs: &mut World) {
     let positions = ecs.read_storage::<Position>();
     let mut gamelog = ecs.fetch_mut::<GameLog>();
 
-    let mut target_item: Option<Entity> = None;
-    for (item_entity, _item, position) in (&entities, &items, &positions).join() {
-        if position.x == player_pos.x && position.y == player_pos.y {
-            target_item = Some(item_entity);
-        }
+    let target_items: Vec<Entity> = (&entities, &items, &positions)
+        .join()
+        .filter(|(_item_entity, _item, position)| position.x == player_pos.x && position.y == player_pos.y)
+        .map(|(item_entity, _item, _position)| item_entity)
+        .collect();
+
+    if target_items.is_empty() {
+        gamelog.push(LogCategory::System, "There is nothing here to pick up.");
+        return;
+    }
+
+    // the intent sits on each item, so a whole pile can be collected at once
+    let mut pickup = ecs.write_storage::<WantsToPickUpItem>();
+    for item in target_items {
+        pickup.insert(item, WantsToPickUpItem{
+            collected_by: *player_entity,
+            item
+        }).expect("Unable to insert want to pickup");
     }
+}
+
+
+// equips an item lying under the player without picking it up first,
+// the regular equip logic takes care of whatever is already in the slot
+fn equip_from_ground(ecs: &mut World) -> RunState {
+    let player_pos = ecs.fetch::<Point>();
+    let player_entity = ecs.fetch::<Entity>();
+    let entities = ecs.entities();
+    let equippables = ecs.read_storage::<Equippable>();
+    let positions = ecs.read_storage::<Position>();
+
+    let target_item = (&entities, &equippables, &positions)
+        .join()
+        .find(|(_item, _equippable, position)| position.x == player_pos.x && position.y == player_pos.y)
+        .map(|(item, _equippable, _position)| item);
 
     match target_item {
-        None => gamelog.entries.push("There is nothing here to pick up.".to_string()),
+        None => {
+            let mut gamelog = ecs.fetch_mut::<GameLog>();
+            gamelog.push(LogCategory::System, "There is nothing here to equip.");
+            RunState::AwaitingInput
+        }
         Some(item) => {
-            let mut pickup = ecs.write_storage::<WantsToPickUpItem>();
-            pickup.insert(*player_entity, WantsToPickUpItem{
-                collected_by: *player_entity,
-                item
-            }).expect("Unable to insert want to pickup");
+            let mut intent = ecs.write_storage::<WantsToUseItem>();
+            intent.insert(*player_entity, WantsToUseItem{
+                item,
+                target: None
+            }).expect("Unable to insert intent");
+            RunState::PlayerTurn
         }
     }
 }
 
+// One key for everything the player can do with what's around them.
+// Priority: pick up items underfoot, then drink from an adjacent fountain,
+// then open an adjacent closed door, then take the stairs.
+fn contextual_interact(ecs: &mut World) -> RunState {
+    let (item_underfoot, fountain_delta, door, tile_underfoot) = {
+        let player_pos = ecs.fetch::<Point>();
+        let map = ecs.fetch::<Map>();
+        let items = ecs.read_storage::<Item>();
+        let fountains = ecs.read_storage::<Fountain>();
+        let positions = ecs.read_storage::<Position>();
+
+        let item_underfoot = (&items, &positions)
+            .join()
+            .any(|(_item, pos)| pos.x == player_pos.x && pos.y == player_pos.y);
+
+        let fountain_delta = (&fountains, &positions)
+            .join()
+            .filter(|(fountain, _pos)| fountain.uses > 0)
+            .map(|(_fountain, pos)| (pos.x - player_pos.x, pos.y - player_pos.y))
+            .find(|(dx, dy)| i32::abs(*dx) <= 1 && i32::abs(*dy) <= 1);
+
+        let door = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| (player_pos.x + dx, player_pos.y + dy))
+            .filter(|(x, y)| !map.is_out_of_bounds(*x, *y))
+            .map(|(x, y)| map.xy_idx(x, y))
+            .find(|idx| map.is_closed_door(*idx));
+
+        let tile_underfoot = map.tiles[map.xy_idx(player_pos.x, player_pos.y)];
+
+        (item_underfoot, fountain_delta, door, tile_underfoot)
+    };
+
+    if item_underfoot {
+        get_item(ecs);
+        return RunState::PlayerTurn;
+    }
+
+    if let Some((dx, dy)) = fountain_delta {
+        // bumping the fountain is what drinks from it
+        try_move_player(dx, dy, ecs);
+        return RunState::PlayerTurn;
+    }
+
+    if let Some(door) = door {
+        ecs.fetch_mut::<Map>().open_door(door);
+        let player_entity = *ecs.fetch::<Entity>();
+        if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player_entity) {
+            viewshed.dirty = true;
+        }
+        ecs.fetch_mut::<GameLog>().push(LogCategory::Flavor, "You open the door.");
+        return RunState::PlayerTurn;
+    }
+
+    match tile_underfoot {
+        TileType::DownStairs => return RunState::NextLevel,
+        TileType::UpStairs => return RunState::PreviousLevel,
+        _ => {}
+    }
+
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+    gamelog.push(LogCategory::System, "There is nothing here to interact with.");
+    RunState::AwaitingInput
+}
 
 
 pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
-    // handle player movement
     match ctx.key {
-        None => return RunState::AwaitingInput, // no key -> Paused State
-        Some(key) => match key {
+        None => RunState::AwaitingInput, // no key -> Paused State
+        Some(key) => key_input(&mut gs.ecs, key),
+    }
+}
+
+// does whatever key is bound to, and says what state that leaves the game in
+fn key_input(ecs: &mut World, key: VirtualKeyCode) -> RunState {
+    let action = ecs.fetch::<KeyBindings>().action_for(key);
+
+    match action {
+        None => return RunState::AwaitingInput, //Non-used keys do nothing
+        Some(action) => match action {
 
             //Movement
-            VirtualKeyCode::Left | VirtualKeyCode::Numpad4 => try_move_player(-1, 0, &mut gs.ecs),
-            VirtualKeyCode::Right | VirtualKeyCode::Numpad6 => try_move_player(1, 0, &mut gs.ecs),
-            VirtualKeyCode::Up | VirtualKeyCode::Numpad8 => try_move_player(0, -1, &mut gs.ecs),
-            VirtualKeyCode::Down | VirtualKeyCode::Numpad2 => try_move_player(0, 1, &mut gs.ecs),
+            Action::MoveWest => try_move_player(-1, 0, ecs),
+            Action::MoveEast => try_move_player(1, 0, ecs),
+            Action::MoveNorth => try_move_player(0, -1, ecs),
+            Action::MoveSouth => try_move_player(0, 1, ecs),
 
             //Diagonals
-            VirtualKeyCode::Numpad9 => try_move_player(1, -1, &mut gs.ecs),
-            VirtualKeyCode::Numpad7 => try_move_player(-1, -1, &mut gs.ecs),
-            VirtualKeyCode::Numpad1 => try_move_player(-1, 1, &mut gs.ecs),
-            VirtualKeyCode::Numpad3 => try_move_player(1, 1, &mut gs.ecs),
+            Action::MoveNorthEast => try_move_player(1, -1, ecs),
+            Action::MoveNorthWest => try_move_player(-1, -1, ecs),
+            Action::MoveSouthWest => try_move_player(-1, 1, ecs),
+            Action::MoveSouthEast => try_move_player(1, 1, ecs),
 
 
             // Item handling
-            VirtualKeyCode::G => get_item(&mut gs.ecs),
-            VirtualKeyCode::I => return RunState::ShowInventory,
-            VirtualKeyCode::D => return RunState::ShowDropItem,
-            VirtualKeyCode::R => return RunState::ShowRemoveItem,
+            Action::PickUp => get_item(ecs),
+            Action::Inventory => return RunState::ShowInventory,
+            Action::Drop => return RunState::ShowDropItem,
+            Action::Throw => return RunState::ShowThrowItem,
+            Action::Remove => return RunState::ShowRemoveItem,
+            Action::EquipFromGround => return equip_from_ground(ecs),
+            Action::Interact => return contextual_interact(ecs),
 
             // Level Change
-            VirtualKeyCode::Period => {
-                if try_next_level(&mut gs.ecs) {
+            Action::Descend => {
+                if try_next_level(ecs) {
                     return RunState::NextLevel;
                 }
             }
+            Action::Ascend => {
+                if try_previous_level(ecs) {
+                    return RunState::PreviousLevel;
+                }
+            }
 
             // skip turn
-            VirtualKeyCode::Numpad5 => return skip_turn(&mut gs.ecs),
-            VirtualKeyCode::Space => return skip_turn(&mut gs.ecs),
-
-            VirtualKeyCode::Escape => return RunState::SaveGame,
+            Action::Wait => return skip_turn(ecs),
+            Action::Rest => return RunState::Resting{ turns_left: MAX_REST_TURNS },
+            Action::Search => search(ecs),
+            Action::AutoExplore => return RunState::AutoExploring,
+
+            Action::SaveMenu => return RunState::SaveSlotMenu { saving: true },
+            Action::Quit => return RunState::ConfirmQuit,
+
+            // display toggles, these don't take a turn
+            Action::ToggleThreatColors => {
+                let mut settings = ecs.fetch_mut::<Settings>();
+                settings.threat_colors = !settings.threat_colors;
+                return RunState::AwaitingInput;
+            }
 
-            _ => return RunState::AwaitingInput, //Non-used keys do nothing
+            // cycle which log categories are shown
+            Action::CycleLogFilter => {
+                ecs.fetch_mut::<GameLog>().cycle_filter();
+                return RunState::AwaitingInput;
+            }
+            Action::ShowLog => return RunState::ShowLog{ scroll: 0 },
         },
     }
 
@@ -140,37 +314,275 @@ pub fn try_next_level(ecs: &mut World) -> bool {
         true
     } else {
         let mut gamelog = ecs.fetch_mut::<GameLog>();
-        gamelog.entries.push("There is no way down from here.".to_string());
+        gamelog.push(LogCategory::System, "There is no way down from here.");
+        false
+    }
+}
+
+pub fn try_previous_level(ecs: &mut World) -> bool {
+    let player_pos = ecs.fetch::<Point>();
+    let map = ecs.fetch::<Map>();
+    let player_idx = map.xy_idx(player_pos.x, player_pos.y);
+    if map.tiles[player_idx] == TileType::UpStairs {
+        true
+    } else {
+        let mut gamelog = ecs.fetch_mut::<GameLog>();
+        gamelog.push(LogCategory::System, "There is no way up from here.");
         false
     }
 }
 
 
-fn skip_turn(ecs: &mut World) -> RunState {
+fn monster_in_view(ecs: &World) -> bool {
     let player_entity = ecs.fetch::<Entity>();
     let viewshed_components = ecs.read_storage::<Viewshed>();
     let monsters = ecs.read_storage::<Monster>();
 
     let worldmap_resource = ecs.fetch::<Map>();
 
-    let mut can_heal = true;
     let viewshed = viewshed_components.get(*player_entity).unwrap();
     for tile in viewshed.visible_tiles.iter() {
         let idx = worldmap_resource.xy_idx(tile.x, tile.y);
         for entity_id in worldmap_resource.tile_content[idx].iter() {
-            let mob = monsters.get(*entity_id);
-            match mob {
-                None => {},
-                Some(_) => can_heal = false,
+            if monsters.get(*entity_id).is_some() {
+                return true;
             }
         }
     }
 
-    if can_heal {
+    false
+}
+
+
+pub fn skip_turn(ecs: &mut World) -> RunState {
+    if monster_in_view(ecs) {
+        ecs.fetch_mut::<GameLog>().push(LogCategory::System, "You can't rest while enemies are near.");
+    } else {
+        let player_entity = ecs.fetch::<Entity>();
         let mut health_components = ecs.write_storage::<CombatStats>();
         let player_hp = health_components.get_mut(*player_entity).unwrap();
         player_hp.hp = i32::min(player_hp.hp + 1, player_hp.max_hp);
     }
 
     RunState::PlayerTurn
-}
\ No newline at end of file
+}
+
+
+// spends the turn searching the surrounding walls, SearchSystem does the looking
+fn search(ecs: &mut World) {
+    let player_entity = ecs.fetch::<Entity>();
+    let mut wants_search = ecs.write_storage::<WantsToSearch>();
+    wants_search.insert(*player_entity, WantsToSearch{}).expect("Unable to insert intent");
+}
+
+
+// Where auto-explore goes next, as a move for try_move_player, or why it should stop.
+// It heads for the closest revealed tile next to unexplored ground, opening closed
+// doors on the way but never stepping into a blocked tile or a trap the player has seen
+pub fn explore_step(ecs: &World) -> Result<(i32, i32), &'static str> {
+    if monster_in_view(ecs) {
+        return Err("Something nearby catches your eye.");
+    }
+
+    let player_pos = ecs.fetch::<Point>();
+    let map = ecs.fetch::<Map>();
+    let triggers = ecs.read_storage::<EntryTrigger>();
+    let hidden = ecs.read_storage::<Hidden>();
+
+    let unrevealed_next_to = |x: i32, y: i32| {
+        (-1..=1).any(|dy| {
+            (-1..=1).any(|dx| {
+                let (nx, ny) = (x + dx, y + dy);
+                nx >= 0 && nx < map.width && ny >= 0 && ny < map.height && !map.revealed_tiles[map.xy_idx(nx, ny)]
+            })
+        })
+    };
+    let frontier: Vec<usize> = (0..map.tiles.len())
+        .filter(|idx| map.revealed_tiles[*idx] && map.tiles[*idx] != TileType::Wall)
+        .filter(|idx| unrevealed_next_to(*idx as i32 % map.width, *idx as i32 / map.width))
+        .collect();
+    if frontier.is_empty() {
+        return Err("There is nothing left to explore.");
+    }
+
+    let known_trap = |idx: usize| {
+        map.tile_content[idx]
+            .iter()
+            .any(|entity| triggers.get(*entity).is_some() && hidden.get(*entity).is_none())
+    };
+    let field = DijkstraMap::build(&map, &frontier);
+    let next = field
+        .exits_toward_goal(map.xy_idx(player_pos.x, player_pos.y))
+        .into_iter()
+        .find(|idx| (!map.blocked[*idx] || map.is_closed_door(*idx)) && !known_trap(*idx));
+
+    match next {
+        Some(idx) => Ok((idx as i32 % map.width - player_pos.x, idx as i32 / map.width - player_pos.y)),
+        None => Err("You can't find a way on from here."),
+    }
+}
+
+
+// upper bound on a single rest, in case nothing ever heals the player
+pub const MAX_REST_TURNS: i32 = 100;
+
+// why the player should stop resting before taking another turn, if they should
+pub fn rest_interruption(ecs: &World, turns_left: i32) -> Option<&'static str> {
+    let player_entity = ecs.fetch::<Entity>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+
+    if monster_in_view(ecs) {
+        return Some("You stop resting, something is nearby.");
+    }
+
+    if let Some(stats) = combat_stats.get(*player_entity) {
+        if stats.hp >= stats.max_hp {
+            return Some("You feel fully rested.");
+        }
+    }
+
+    if turns_left < 1 {
+        return Some("You can't seem to rest any longer.");
+    }
+
+    None
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::damage_system::delete_the_dead;
+    use crate::inventory_system::InventorySystem;
+    use crate::{InBackpack, TileType};
+    use crate::test_support::{open_map, refresh_map, spawn_at, test_world};
+
+    fn spot(ecs: &World, entity: Entity) -> (i32, i32) {
+        let pos = ecs.read_storage::<Position>().get(entity).cloned().unwrap();
+        (pos.x, pos.y)
+    }
+
+    #[test]
+    fn interacting_beside_a_closed_door_opens_it() {
+        let mut map = open_map();
+        let door = map.xy_idx(11, 9);
+        map.tiles[door] = TileType::Door;
+        map.populate_blocked();
+        let (mut ecs, player) = test_world(map, 10, 10);
+        assert!(ecs.fetch::<Map>().is_closed_door(door));
+
+        assert!(contextual_interact(&mut ecs) == RunState::PlayerTurn);
+        let map = ecs.fetch::<Map>();
+        assert!(!map.is_closed_door(door));
+        assert!(!map.blocked[door]);
+        assert!(ecs.read_storage::<Viewshed>().get(player).unwrap().dirty);
+    }
+
+    #[test]
+    fn player_steps_straight_onto_a_fresh_corpse() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 11, 10, "Goblin");
+        refresh_map(&mut ecs);
+        ecs.write_storage::<CombatStats>().get_mut(goblin).unwrap().hp = 0;
+
+        delete_the_dead(&mut ecs);
+        try_move_player(1, 0, &mut ecs);
+
+        assert_eq!(spot(&ecs, player), (11, 10));
+    }
+
+    // a level smaller than the usual one and floor right up to its edge,
+    // so only the bounds check keeps the player on it
+    fn small_map(width: i32, height: i32) -> Map {
+        let count = (width * height) as usize;
+        let mut map = Map {
+            tiles: vec![TileType::Floor; count],
+            width,
+            height,
+            revealed_tiles: vec![false; count],
+            visible_tiles: vec![false; count],
+            blocked: vec![false; count],
+            tile_content: vec![Vec::new(); count],
+            ..Map::default()
+        };
+        map.populate_blocked();
+        map
+    }
+
+    #[test]
+    fn player_walks_to_the_edges_of_a_small_map_and_no_further() {
+        // floor right out to the edge, so only is_out_of_bounds stops the player
+        let (mut ecs, player) = test_world(small_map(20, 12), 17, 9);
+        let last = {
+            let map = ecs.fetch::<Map>();
+            let last = (map.width - 2, map.height - 2);
+            assert!(!map.is_out_of_bounds(last.0, last.1));
+            assert!(map.is_out_of_bounds(last.0 + 1, last.1) && map.is_out_of_bounds(last.0, last.1 + 1));
+            last
+        };
+
+        for _ in 0..3 {
+            try_move_player(1, 1, &mut ecs);
+        }
+        assert_eq!(spot(&ecs, player), last);
+
+        for _ in 0..25 {
+            try_move_player(-1, 0, &mut ecs);
+            try_move_player(0, -1, &mut ecs);
+        }
+        assert_eq!(spot(&ecs, player), (1, 1));
+    }
+
+    #[test]
+    fn picking_up_takes_the_whole_pile() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let potion = spawn_at(&mut ecs, 10, 10, "Health Potion");
+        let dagger = spawn_at(&mut ecs, 10, 10, "Dagger");
+
+        get_item(&mut ecs);
+        InventorySystem {}.run_now(&ecs);
+        ecs.maintain();
+
+        let backpack = ecs.read_storage::<InBackpack>();
+        for item in [potion, dagger] {
+            assert_eq!(backpack.get(item).map(|pack| pack.owner), Some(player));
+        }
+        let pickups = ecs.fetch::<GameLog>().entries.iter().filter(|entry| entry.text.starts_with("You pick up")).count();
+        assert_eq!(pickups, 2);
+    }
+
+    #[test]
+    fn a_remapped_pickup_key_picks_things_up() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let potion = spawn_at(&mut ecs, 10, 10, "Health Potion");
+        ecs.write_resource::<KeyBindings>().rebind(Action::PickUp, &[VirtualKeyCode::P]);
+
+        assert!(key_input(&mut ecs, VirtualKeyCode::G) == RunState::AwaitingInput);
+        assert!(ecs.read_storage::<WantsToPickUpItem>().get(potion).is_none());
+
+        assert!(key_input(&mut ecs, VirtualKeyCode::P) == RunState::PlayerTurn);
+        let wants = ecs.read_storage::<WantsToPickUpItem>();
+        assert_eq!(wants.get(potion).map(|pickup| pickup.collected_by), Some(player));
+    }
+
+    #[test]
+    fn diagonal_bumps_attack_unless_the_corner_is_closed() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 11, 9, "Goblin");
+        refresh_map(&mut ecs);
+
+        try_move_player(1, -1, &mut ecs);
+        assert_eq!(ecs.read_storage::<WantsToMelee>().get(player).map(|melee| melee.target), Some(goblin));
+        ecs.write_storage::<WantsToMelee>().clear();
+
+        // a wall on one side and a closed door on the other leave no gap to swing through
+        {
+            let mut map = ecs.fetch_mut::<Map>();
+            let wall = map.xy_idx(11, 10);
+            let door = map.xy_idx(10, 9);
+            map.tiles[wall] = TileType::Wall;
+            map.tiles[door] = TileType::Door;
+        }
+        try_move_player(1, -1, &mut ecs);
+        assert!(ecs.read_storage::<WantsToMelee>().get(player).is_none());
+        assert_eq!(spot(&ecs, player), (10, 10));
+    }
+}
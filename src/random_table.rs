@@ -40,17 +40,27 @@ impl RandomTable {
         }
 
         let mut roll = rng.roll_dice(1, self.total_weight) - 1;
-        let mut index: usize = 0;
-
-        while roll > 0 {
-            if roll < self.entries[index].weight {
-                return self.entries[index].name.clone();
+        for entry in self.entries.iter() {
+            if roll < entry.weight {
+                return entry.name.clone();
             }
-
-            roll -= self.entries[index].weight;
-            index += 1;
+            roll -= entry.weight;
         }
 
         "None".to_string()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TEST_SEED;
+
+    #[test]
+    fn a_roll_of_zero_lands_on_the_first_entry() {
+        let mut rng = RandomNumberGenerator::seeded(TEST_SEED);
+        let table = RandomTable::new().add("Goblin", 1);
+        for _i in 0..20 {
+            assert_eq!(table.roll(&mut rng), "Goblin");
+        }
+    }
+}
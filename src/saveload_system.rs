@@ -1,7 +1,12 @@
 use specs::saveload::{SimpleMarker, MarkedBuilder, SerializeComponents, DeserializeComponents};
 use crate::{SerializeMe, SerializationHelper, Position};
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use std::path::Path;
 use specs::{World, WorldExt, Builder};
 use specs::error::NoError;
@@ -17,40 +22,205 @@ macro_rules! serialize_individually {
             &$data.0,
             &$data.1,
             &mut $ser,
-        )
-        .unwrap();
+        )?;
         )*
     };
 }
 
-pub fn save_game(ecs: &mut World) {
-    // Create helper
-    let mapcopy = ecs.get_mut::<super::map::Map>().unwrap().clone();
-    let savehelper = ecs.create_entity().with(SerializationHelper{map: mapcopy}).marked::<SimpleMarker<SerializeMe>>().build();
+// how many slots the save menu offers, slot 0 is where a plain save goes
+pub const MANUAL_SLOTS: usize = 3;
 
+#[derive(PartialEq, Copy, Clone)]
+pub enum SaveSlot {
+    Manual(usize),
+    Autosave,
+}
 
-    // Actual serialization
-    {
-        let data = (ecs.entities(), ecs.read_storage::<SimpleMarker<SerializeMe>>());
+impl SaveSlot {
+    fn path(&self) -> String {
+        format!("{}.gz", self.legacy_path())
+    }
+
+    // where the slot was written before saves were compressed
+    fn legacy_path(&self) -> String {
+        match self {
+            SaveSlot::Manual(slot) => format!("./save_{}.json", slot),
+            SaveSlot::Autosave => "./autosave.json".to_string(),
+        }
+    }
 
-        let writer = File::create("./savegame.json").unwrap();
-        let mut serializer = serde_json::Serializer::new(writer);
+    fn exists(&self) -> bool {
+        Path::new(&self.path()).exists() || Path::new(&self.legacy_path()).exists()
+    }
 
+    // The slot's contents as plain json. A compressed save is preferred over an
+    // uncompressed one, and either is recognised by its first bytes rather than its name
+    fn open(&self) -> std::io::Result<Box<dyn Read>> {
+        let file = File::open(self.path()).or_else(|_| File::open(self.legacy_path()))?;
+        let mut reader = BufReader::new(file);
+        if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Ok(Box::new(GzDecoder::new(reader)))
+        } else {
+            Ok(Box::new(reader))
+        }
+    }
 
-        // this is kind of messy, but it works
-        serialize_individually!(ecs, serializer, data, Position, Renderable, Player, Viewshed, Monster,
-            Name, BlocksTile, CombatStats, SufferDamage, WantsToMelee, Item, Consumable, Ranged, InflictsDamage,
-            AreaOfEffect, Confusion, ProvidesHealing, InBackpack, WantsToPickUpItem, WantsToUseItem,
-            WantsToDropItem, WantsToRemoveItem, SerializationHelper, Equippable, Equipped, MeleePowerBonus, DefenseBonus
-        );
+    // every manual slot followed by the autosave
+    pub fn all() -> Vec<SaveSlot> {
+        (0..MANUAL_SLOTS).map(SaveSlot::Manual).chain(std::iter::once(SaveSlot::Autosave)).collect()
+    }
+}
+
+// every gzip stream starts with these
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// the turn the run was last written to disk on, None if it hasn't been
+pub struct SavedAtTurn(pub Option<u64>);
+
+// Bump this whenever a change to the saved components makes older saves unreadable.
+// Components added to the end of the lists don't, a save from before them just has none
+const SAVE_VERSION: u32 = 20;
+
+// the oldest save that can still be loaded, everything since only added components at the end
+const OLDEST_SAVE_VERSION: u32 = 18;
+
+fn readable_version(version: u32) -> bool {
+    (OLDEST_SAVE_VERSION..=SAVE_VERSION).contains(&version)
+}
+
+// written ahead of everything else, so a load can bail out before touching the world
+// and the slot menu can describe a save without reading all of it
+#[derive(Serialize, Deserialize)]
+struct SaveHeader {
+    save_version: u32,
+    depth: i32,
+    level: i32,
+    #[serde(default)]
+    run_id: u64,
+}
+
+// what the slot menu shows for a save
+#[derive(PartialEq, Copy, Clone)]
+pub struct SlotSummary {
+    pub depth: i32,
+    pub level: i32,
+    pub run_id: u64,
+}
+
+// a slot and what slot_summary had to say about it
+pub type SlotEntry = (SaveSlot, Option<Result<SlotSummary, LoadError>>);
+
+// Every slot's summary, read once when the slot menu opens rather than on every
+// frame it's drawn. None while the menu is closed
+pub struct SlotListing(pub Option<Vec<SlotEntry>>);
+
+impl SlotListing {
+    pub fn read() -> Vec<SlotEntry> {
+        SaveSlot::all().into_iter().map(|slot| (slot, slot_summary(slot))).collect()
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum LoadError {
+    // the file couldn't be read at all
+    Io,
+    // no header, or one from another version
+    Incompatible,
+    // the contents stopped making sense part way through
+    Json,
+    // parsed fine but the map and log or the player never turned up
+    MissingHelper,
+    MissingPlayer,
+}
+
+impl LoadError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            LoadError::Io => "The save file could not be read.",
+            LoadError::Incompatible => "Save incompatible with this version.",
+            LoadError::Json => "The save file is damaged.",
+            LoadError::MissingHelper => "The save file has no map in it.",
+            LoadError::MissingPlayer => "The save file has no player in it.",
+        }
     }
 
-    // clean up
+    // whether the world was already cleared out before this went wrong
+    pub fn world_touched(&self) -> bool {
+        !matches!(self, LoadError::Io | LoadError::Incompatible)
+    }
+}
+
+// why the last load failed, shown on the main menu until something else is picked
+pub struct LastLoadError(pub Option<LoadError>);
+
+// writes the run to slot, it only counts as saved once the whole file is written out
+pub fn save_game(ecs: &mut World, slot: SaveSlot) -> std::io::Result<()> {
+    let writer = GzEncoder::new(File::create(slot.path())?, Compression::default());
+    // the trailer has to be written out, dropping the encoder would swallow any error
+    write_save(ecs, writer)?.finish()?;
+    let turns = ecs.fetch::<TurnCount>().0;
+    *ecs.write_resource::<SavedAtTurn>() = SavedAtTurn(Some(turns));
+    Ok(())
+}
+
+// writes the header and every marked entity to writer as json, then hands writer back
+fn write_save<W: Write>(ecs: &mut World, writer: W) -> std::io::Result<W> {
+    // Create helper
+    let mapcopy = ecs.get_mut::<super::map::Map>().unwrap().clone();
+    let logcopy = (*ecs.fetch::<gamelog::GameLog>()).clone();
+    let turns = ecs.fetch::<TurnCount>().0;
+    let depth = mapcopy.depth;
+    let level = ecs.read_storage::<Experience>().get(*ecs.fetch::<Entity>()).map_or(1, |xp| xp.level);
+    let masks = (*ecs.fetch::<identification::MagicItemMasks>()).clone();
+    let dungeon = (*ecs.fetch::<master_dungeon::MasterDungeon>()).clone();
+    let stats = (*ecs.fetch::<Stats>()).clone();
+    let run_id = stats.run_id;
+    let savehelper = ecs.create_entity().with(SerializationHelper{map: mapcopy, log: logcopy, turns, masks, dungeon, stats}).marked::<SimpleMarker<SerializeMe>>().build();
+
+
+    let header = SaveHeader{ save_version: SAVE_VERSION, depth, level, run_id };
+    let written = serialize_world(ecs, writer, header);
+
+    // clean up, whether or not it all got written
     ecs.delete_entity(savehelper).expect("Crash on cleanup");
+    written
+}
+
+// the header, then every marked entity's components
+fn serialize_world<W: Write>(ecs: &World, writer: W, header: SaveHeader) -> std::io::Result<W> {
+    let data = (ecs.entities(), ecs.read_storage::<SimpleMarker<SerializeMe>>());
+
+    let mut serializer = serde_json::Serializer::new(writer);
+    header.serialize(&mut serializer)?;
+
+    // this is kind of messy, but it works
+    serialize_individually!(ecs, serializer, data, Position, Renderable, Player, Viewshed, Monster,
+        Name, BlocksTile, CombatStats, SufferDamage, WantsToMelee, Item, Consumable, Ranged, InflictsDamage,
+        AreaOfEffect, Confusion, ProvidesHealing, InBackpack, WantsToPickUpItem, WantsToUseItem,
+        WantsToDropItem, WantsToRemoveItem, SerializationHelper, Equippable, Equipped, MeleePowerBonus, DefenseBonus, Fountain, Cursed, HarmsCaster, ShattersOnThrow, BlocksVision, Faction, Tags, Thorns, IgnoresWalls, Size, Durability, AggroRange, RangedAttack, CanFlee, HungerClock, ProvidesFood, EntryTrigger, Hidden, SingleActivation, WantsToSearch, Experience, DamageOverTime, Venomous, Regeneration, GrantsRegeneration, MagicMapper, Teleport, ObfuscatedName, Identify, WantsToIdentify, LightSource, OtherLevelPosition, Initiative, MyTurn, ProvidesHaste, Hasted, Slowed, Wallet, GoldDrop, Gold, Weight, TwoHanded, Summoner, WantsToSummon, Rooted, Resistances, Vulnerabilities, CritChance, Summoned
+    );
+    Ok(serializer.into_inner())
 }
 
 pub fn save_exists() -> bool {
-    Path::new("./savegame.json").exists()
+    SaveSlot::all().iter().any(|slot| slot.exists())
+}
+
+// None for an empty slot, otherwise the header or why it couldn't be read
+pub fn slot_summary(slot: SaveSlot) -> Option<Result<SlotSummary, LoadError>> {
+    if !slot.exists() {
+        return None;
+    }
+    let reader = match slot.open() {
+        Ok(reader) => reader,
+        Err(_) => return Some(Err(LoadError::Io)),
+    };
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let summary = match SaveHeader::deserialize(&mut de) {
+        Ok(header) if readable_version(header.save_version) => Ok(SlotSummary{ depth: header.depth, level: header.level, run_id: header.run_id }),
+        _ => Err(LoadError::Incompatible),
+    };
+    Some(summary)
 }
 
 // macro needed for loading
@@ -59,19 +229,46 @@ pub fn save_exists() -> bool {
 macro_rules! deserialize_individually {
     ($ecs: expr, $de: expr, $data: expr, $($type: ty), *) => {
         $(
-            DeserializeComponents::<NoError, _>::deserialize(
-                &mut (&mut $ecs.write_storage::<$type>(), ),
-                &mut $data.0, // entities
-                &mut $data.1, // marker
-                &mut $data.2, // allocater
-                &mut $de,
-            )
-            .unwrap();
+            // an older save stops before the components added since
+            if $de.end().is_err() {
+                DeserializeComponents::<NoError, _>::deserialize(
+                    &mut (&mut $ecs.write_storage::<$type>(), ),
+                    &mut $data.0, // entities
+                    &mut $data.1, // marker
+                    &mut $data.2, // allocater
+                    &mut $de,
+                )
+                .map_err(|_| LoadError::Json)?;
+            }
         )*
     };
 }
 
-pub fn load_game(ecs: &mut World) {
+// Replaces the world with the save in slot. The header is checked before anything is
+// deleted, a file that breaks further in leaves the world half loaded for the caller to reset
+pub fn load_game(ecs: &mut World, slot: SaveSlot) -> Result<(), LoadError> {
+    let reader = slot.open().map_err(|_| LoadError::Io)?;
+    load_save(ecs, &read_save(reader)?)
+}
+
+// the whole save as text, a broken gzip stream counts as damage rather than a failed read
+fn read_save<R: Read>(mut reader: R) -> Result<String, LoadError> {
+    let mut data = String::new();
+    reader.read_to_string(&mut data).map_err(|error| match error.kind() {
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput | std::io::ErrorKind::UnexpectedEof => LoadError::Json,
+        _ => LoadError::Io,
+    })?;
+    Ok(data)
+}
+
+// replaces the whole world with the one a save wrote out as data
+fn load_save(ecs: &mut World, data: &str) -> Result<(), LoadError> {
+    let mut de = serde_json::Deserializer::from_str(data);
+    let header = SaveHeader::deserialize(&mut de).map_err(|_| LoadError::Incompatible)?;
+    if !readable_version(header.save_version) {
+        return Err(LoadError::Incompatible);
+    }
+
     { // keep the borrow checker happy
         // delete everything
         let mut to_delete = Vec::new();
@@ -83,19 +280,17 @@ pub fn load_game(ecs: &mut World) {
         }
     }
 
-    let data = fs::read_to_string("./savegame.json").unwrap();
-    let mut de = serde_json::Deserializer::from_str(&data);
-
     {
         let mut d = (&mut ecs.entities(), &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(), &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>());
         deserialize_individually!(ecs, de, d, Position, Renderable, Player, Viewshed, Monster, 
             Name, BlocksTile, CombatStats, SufferDamage, WantsToMelee, Item, Consumable, Ranged, InflictsDamage, 
             AreaOfEffect, Confusion, ProvidesHealing, InBackpack, WantsToPickUpItem, WantsToUseItem,
-            WantsToDropItem, WantsToRemoveItem, SerializationHelper, Equippable, Equipped, MeleePowerBonus, DefenseBonus
+            WantsToDropItem, WantsToRemoveItem, SerializationHelper, Equippable, Equipped, MeleePowerBonus, DefenseBonus, Fountain, Cursed, HarmsCaster, ShattersOnThrow, BlocksVision, Faction, Tags, Thorns, IgnoresWalls, Size, Durability, AggroRange, RangedAttack, CanFlee, HungerClock, ProvidesFood, EntryTrigger, Hidden, SingleActivation, WantsToSearch, Experience, DamageOverTime, Venomous, Regeneration, GrantsRegeneration, MagicMapper, Teleport, ObfuscatedName, Identify, WantsToIdentify, LightSource, OtherLevelPosition, Initiative, MyTurn, ProvidesHaste, Hasted, Slowed, Wallet, GoldDrop, Gold, Weight, TwoHanded, Summoner, WantsToSummon, Rooted, Resistances, Vulnerabilities, CritChance, Summoned
         );
     }
 
     let mut delete_me: Option<Entity> = None;
+    let mut found_player = false;
     { // avoid borrow conflicts
         let entities = ecs.entities();
         let helper = ecs.read_storage::<SerializationHelper>();
@@ -106,6 +301,12 @@ pub fn load_game(ecs: &mut World) {
             let mut worldmap = ecs.write_resource::<super::map::Map>();
             *worldmap = h.map.clone();
             worldmap.tile_content = vec![Vec::new(); super::map::MAPCOUNT];
+            *ecs.write_resource::<gamelog::GameLog>() = h.log.clone();
+            *ecs.write_resource::<TurnCount>() = TurnCount(h.turns);
+            *ecs.write_resource::<identification::MagicItemMasks>() = h.masks.clone();
+            *ecs.write_resource::<master_dungeon::MasterDungeon>() = h.dungeon.clone();
+            *ecs.write_resource::<Stats>() = h.stats.clone();
+            *ecs.write_resource::<SavedAtTurn>() = SavedAtTurn(Some(h.turns));
             delete_me = Some(e);
         }
 
@@ -114,14 +315,186 @@ pub fn load_game(ecs: &mut World) {
             *ppos = rltk::Point::new(pos.x, pos.y);
             let mut player_resource = ecs.write_resource::<Entity>();
             *player_resource = e;
+            found_player = true;
         }
     }
-    ecs.delete_entity(delete_me.unwrap()).expect("Unable to delete helper");
+    ecs.delete_entity(delete_me.ok_or(LoadError::MissingHelper)?).expect("Unable to delete helper");
+    if !found_player {
+        return Err(LoadError::MissingPlayer);
+    }
+    Ok(())
 }
 
-// for permadeath
-pub fn delete_save() {
-    if Path::new("./savegame.json").exists() {
-        std::fs::remove_file("./savegame.json").expect("Error deleting saved game");
+// for permadeath, every save the run left behind in any slot, so it can't be picked up again.
+// Saves from before runs were told apart can't be matched, so only slot and the autosave go for those
+pub fn delete_run(slot: SaveSlot, run_id: u64) -> std::io::Result<()> {
+    delete_save(slot)?;
+    if run_id == 0 {
+        return delete_save(SaveSlot::Autosave);
+    }
+    for other in SaveSlot::all() {
+        if let Some(Ok(summary)) = slot_summary(other) {
+            if summary.run_id == run_id {
+                delete_save(other)?;
+            }
+        }
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// the single save file the game used before it had slots
+const LEGACY_SAVE: &str = "./savegame.json";
+
+// moves a save from before slots existed into the first free manual slot, it's left
+// where it is if they're all taken
+pub fn migrate_legacy_save() {
+    if !Path::new(LEGACY_SAVE).exists() {
+        return;
+    }
+    let free = SaveSlot::all().into_iter().find(|slot| *slot != SaveSlot::Autosave && !slot.exists());
+    if let Some(slot) = free {
+        // only a failed rename leaves it behind, and then there's nothing better to do with it
+        let _ = fs::rename(LEGACY_SAVE, slot.legacy_path());
+    }
+}
+
+// for permadeath, so a run can't be picked up again from slot
+pub fn delete_save(slot: SaveSlot) -> std::io::Result<()> {
+    for path in [slot.path(), slot.legacy_path()].iter() {
+        if Path::new(path).exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{give, open_map, spawn_at, test_world};
+
+    // saves ecs into memory and loads it back into a fresh world
+    fn round_trip(ecs: &mut World) -> World {
+        let compressed = write_save(ecs, GzEncoder::new(Vec::new(), Compression::default())).unwrap().finish().unwrap();
+        let data = read_save(GzDecoder::new(&compressed[..])).unwrap();
+        let (mut loaded, _player) = test_world(open_map(), 1, 1);
+        load_save(&mut loaded, &data).expect("The save didn't load");
+        loaded
+    }
+
+    #[test]
+    fn remembered_items_survive_a_round_trip() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        {
+            let mut map = ecs.write_resource::<Map>();
+            let idx = map.xy_idx(12, 10);
+            map.remembered_items.insert(idx, rltk::to_cp437('!'));
+        }
+        let loaded = round_trip(&mut ecs);
+        assert_eq!(loaded.fetch::<Map>().remembered_items, ecs.fetch::<Map>().remembered_items);
+        assert_eq!(*loaded.fetch::<rltk::Point>(), rltk::Point::new(10, 10));
+    }
+
+    #[test]
+    fn damaged_saves_are_errors() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        assert_eq!(load_save(&mut ecs, "not a save at all"), Err(LoadError::Incompatible));
+
+        // cut off part way through, after the header
+        let compressed = write_save(&mut ecs, GzEncoder::new(Vec::new(), Compression::default())).unwrap().finish().unwrap();
+        let data = read_save(GzDecoder::new(&compressed[..])).unwrap();
+        assert_eq!(load_save(&mut ecs, &data[..data.len() / 2]), Err(LoadError::Json));
+
+        // a gzip stream that goes bad is damage, not a missing file
+        let mut garbled = compressed.clone();
+        for byte in garbled.iter_mut().skip(20) {
+            *byte = !*byte;
+        }
+        assert_eq!(read_save(GzDecoder::new(&garbled[..])), Err(LoadError::Json));
+    }
+
+    // how many entities have a C
+    fn count<C: Component>(ecs: &World) -> usize {
+        ecs.read_storage::<C>().join().count()
+    }
+
+    #[test]
+    fn compressed_round_trip_keeps_everything() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        spawn_at(&mut ecs, 14, 10, "Goblin");
+        spawn_at(&mut ecs, 16, 12, "Ogre");
+        spawn_at(&mut ecs, 12, 12, "Health Potion");
+        give(&mut ecs, player, "Fire Potion");
+
+        let loaded = round_trip(&mut ecs);
+        assert_eq!(count::<Position>(&loaded), count::<Position>(&ecs));
+        assert_eq!(count::<Monster>(&loaded), 2);
+        assert_eq!(count::<Item>(&loaded), 2);
+        assert_eq!(count::<InBackpack>(&loaded), 1);
+        assert_eq!(count::<CombatStats>(&loaded), count::<CombatStats>(&ecs));
+        assert_eq!(count::<Size>(&loaded), 1);
+    }
+
+    // a disk that fills up part way through
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("no space left"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_writes_are_errors() {
+        let (mut ecs, _player) = test_world(open_map(), 10, 10);
+        let entities_before = ecs.entities().join().count();
+        assert!(write_save(&mut ecs, FullDisk).is_err());
+        // the helper holding the map doesn't get left behind
+        ecs.maintain();
+        assert_eq!(ecs.entities().join().count(), entities_before);
+    }
+
+    // drops every "kind" field anywhere inside value
+    fn strip_kinds(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(fields) => {
+                fields.remove("kind");
+                fields.values_mut().for_each(strip_kinds);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip_kinds),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn saves_from_before_damage_types_still_load() {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        spawn_at(&mut ecs, 14, 10, "Ice Golem");
+        give(&mut ecs, player, "Fireball Scroll");
+        let mut data = Vec::new();
+        write_save(&mut ecs, &mut data).unwrap();
+
+        // what version 18 wrote: no damage kinds, and it stopped after Rooted
+        let mut values: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&data)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        values[0]["save_version"] = serde_json::json!(18);
+        // the header comes first, then Position, so InflictsDamage is the 14th component
+        strip_kinds(&mut values[14]);
+        // Resistances, Vulnerabilities, CritChance and Summoned came later
+        values.truncate(values.len() - 4);
+        let old: String = values.iter().map(|value| value.to_string()).collect();
+
+        let (mut loaded, _player) = test_world(open_map(), 1, 1);
+        load_save(&mut loaded, &old).expect("The old save didn't load");
+        assert_eq!(count::<Monster>(&loaded), 1);
+        assert_eq!(count::<Resistances>(&loaded), 0);
+        let damage = loaded.read_storage::<InflictsDamage>();
+        let kinds: Vec<DamageType> = damage.join().map(|damage| damage.kind).collect();
+        assert_eq!(kinds, vec![DamageType::Physical]);
+    }
+}
@@ -0,0 +1,44 @@
+use rltk::RandomNumberGenerator;
+use specs::prelude::*;
+
+use crate::{gamelog::{GameLog, LogCategory}, Map, Position, TileType, WantsToSearch};
+
+// Looks for secret doors next to whoever is searching, each one
+// nearby has a one in three chance of turning up per search
+pub struct SearchSystem {}
+
+impl<'a> System<'a> for SearchSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        WriteExpect<'a, Map>,
+        WriteExpect<'a, RandomNumberGenerator>,
+        WriteExpect<'a, GameLog>,
+        ReadExpect<'a, Entity>,
+        Entities<'a>,
+        WriteStorage<'a, WantsToSearch>,
+        ReadStorage<'a, Position>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut map, mut rng, mut log, player_entity, entities, mut wants_search, positions) = data;
+
+        for (entity, _search, pos) in (&entities, &wants_search, &positions).join() {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let idx = map.xy_idx(pos.x + dx, pos.y + dy);
+                    if !map.secret_doors.contains(&idx) || rng.roll_dice(1, 3) != 1 {
+                        continue;
+                    }
+                    map.secret_doors.remove(&idx);
+                    map.tiles[idx] = TileType::Door;
+                    map.revealed_tiles[idx] = true;
+                    if entity == *player_entity {
+                        log.push(LogCategory::System, "You find a hidden passage!");
+                    }
+                }
+            }
+        }
+
+        wants_search.clear();
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::map::MapGenerator;
+
+const SETTINGS_FILE: &str = "./settings.json";
+
+// Gameplay and display options, read from settings.json at startup
+// (missing fields fall back to the defaults) and some can be flipped while playing
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    // tint visible monsters green to red by how hard they hit the player
+    pub threat_colors: bool,
+    // write an autosave every time the player goes down a level
+    pub autosave_on_descent: bool,
+    // loading a game or dying deletes the saves
+    pub permadeath: bool,
+    // creatures with BlocksVision hide what's behind them like a wall would
+    pub creatures_block_vision: bool,
+    // prefix log lines with the turn they happened on, like "[T42]"
+    pub log_timestamps: bool,
+    // build every level this way, null picks one at random per level
+    pub map_generator: Option<MapGenerator>,
+    // how bright explored tiles out of sight are drawn, 1.0 is as bright as visible ones
+    pub fog_dim: f32,
+    // start every run from this seed so it plays out the same, null picks a new one each run
+    pub seed: Option<u64>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            threat_colors: true,
+            autosave_on_descent: true,
+            permadeath: true,
+            creatures_block_vision: false,
+            log_timestamps: false,
+            map_generator: None,
+            fog_dim: 0.5,
+            seed: None,
+        }
+    }
+}
+
+impl Settings {
+    // what settings.json says, with why it couldn't be used if it didn't parse,
+    // either way anything it doesn't cover is left at the default
+    pub fn load() -> (Settings, Option<String>) {
+        match fs::read_to_string(SETTINGS_FILE) {
+            Ok(data) => match serde_json::from_str(&data) {
+                Ok(settings) => (settings, None),
+                Err(error) => (Settings::default(), Some(format!("Couldn't read {}: {}", SETTINGS_FILE, error))),
+            },
+            Err(_) => (Settings::default(), None),
+        }
+    }
+}
@@ -1,15 +1,236 @@
-use std::collections::HashMap;
-
 use crate::random_table::RandomTable;
-use crate::{AreaOfEffect, Confusion, Consumable, DefenseBonus, EquipmentSlot, Equippable, Item, MAPWIDTH, MeleePowerBonus, ProvidesHealing, Rect, SerializeMe};
+use crate::{AreaOfEffect, Confusion, CritChance, Consumable, Cursed, DefenseBonus, Durability, EquipmentSlot, Equippable, Fountain, GrantsRegeneration, HarmsCaster, IgnoresWalls, Identify, Item, LightSource, MagicMapper, identification::MagicItemMasks, ShattersOnThrow, Teleport, MAPWIDTH, Map, MeleePowerBonus, ProvidesHealing, Rect, SerializeMe, TileType};
 
-use super::{BlocksTile, CombatStats, Monster, Name, Player, Position, Renderable, Viewshed, Ranged, InflictsDamage};
-use rltk::{RandomNumberGenerator, RGB};
+use super::{footprint, initiative_system::TICKS_PER_TURN, AggroRange, Gold, GoldDrop, Initiative, ProvidesHaste, Slowed, Wallet, Weight, TwoHanded, Summoner, Summoned, WantsToSummon, Rooted, DamageType, Resistances, Vulnerabilities, BlocksTile, BlocksVision, CombatStats, Faction, Size, Tags, Thorns, Monster, RangedAttack, CanFlee, Venomous, HungerClock, HungerState, ProvidesFood, EntryTrigger, Experience, Hidden, SingleActivation, hunger_system::WELL_FED_TURNS, Name, Player, Position, Renderable, Viewshed, Ranged, InflictsDamage};
+use rltk::{Point, RandomNumberGenerator, RGB};
 use specs::prelude::*;
 use specs::saveload::{MarkedBuilder, SimpleMarker};
 
 const MAX_MONSTERS: i32 = 4;
-const MAX_ITEMS: i32 = 2;
+
+// On the first few depths the monsters placed near the player's start
+// can't add up to more than this much power, so a run can't open on a mob
+const START_THREAT_DEPTHS: i32 = 3;
+const START_THREAT_RADIUS: f32 = 15.0;
+const START_THREAT_CAP: i32 = 7;
+
+// lower numbers are drawn on top
+const RENDER_ORDER_PLAYER: i32 = 0;
+const RENDER_ORDER_MONSTER: i32 = 1;
+const RENDER_ORDER_ITEM: i32 = 2;
+
+// Everything that sets one kind of monster apart, adding a monster
+// is a matter of adding an entry here and a weight in room_table
+struct MonsterTemplate {
+    name: &'static str,
+    glyph: char,
+    fg: (u8, u8, u8),
+    max_hp: i32,
+    defense: i32,
+    power: i32,
+    vision_range: i32,
+    blocks_vision: bool,
+    faction: &'static str,
+    tags: &'static [&'static str],
+    // tiles wide and high, anything bigger than 1x1 gets a Size
+    size: (i32, i32),
+    // senses creatures this close without seeing them, 0 for sight only
+    aggro_range: i32,
+    // (range, damage) for monsters that can shoot
+    ranged: Option<(i32, i32)>,
+    // runs away below this percentage of its hp, 0 to fight to the death
+    flee_below: i32,
+    // (damage, turns) of the poison its bite leaves behind
+    venom: Option<(i32, i32)>,
+    // turns between actions, 1 keeps up with the player
+    speed: i32,
+    // (min, max) gold left behind when it dies
+    gold: Option<(i32, i32)>,
+    // kinds of damage it takes half or double of
+    resists: &'static [DamageType],
+    vulnerable: &'static [DamageType],
+    // (monster, turns between summons) it calls up while it sees a foe
+    summons: Option<(&'static str, i32)>,
+}
+
+const MONSTERS: &[MonsterTemplate] = &[
+    MonsterTemplate {
+        name: "Goblin",
+        glyph: 'g',
+        fg: rltk::CHARTREUSE,
+        max_hp: 8,
+        defense: 1,
+        power: 3,
+        vision_range: 8,
+        blocks_vision: false,
+        faction: "Goblins",
+        tags: &["flammable"],
+        size: (1, 1),
+        aggro_range: 0,
+        ranged: None,
+        flee_below: 30,
+        venom: None,
+        speed: 1,
+        gold: Some((1, 6)),
+        resists: &[],
+        vulnerable: &[],
+        summons: None,
+    },
+    MonsterTemplate {
+        name: "Orc",
+        glyph: 'o',
+        fg: rltk::RED,
+        max_hp: 16,
+        defense: 1,
+        power: 4,
+        vision_range: 8,
+        blocks_vision: true,
+        faction: "Orcs",
+        tags: &[],
+        size: (1, 1),
+        aggro_range: 0,
+        ranged: None,
+        flee_below: 0,
+        venom: None,
+        speed: 1,
+        gold: Some((2, 10)),
+        resists: &[],
+        vulnerable: &[],
+        summons: None,
+    },
+    MonsterTemplate {
+        name: "Ogre",
+        glyph: 'O',
+        fg: rltk::SANDY_BROWN,
+        max_hp: 30,
+        defense: 2,
+        power: 7,
+        vision_range: 8,
+        blocks_vision: true,
+        faction: "Ogres",
+        tags: &[],
+        size: (2, 2),
+        aggro_range: 0,
+        ranged: None,
+        flee_below: 0,
+        venom: None,
+        speed: 2,
+        gold: Some((5, 20)),
+        resists: &[],
+        vulnerable: &[],
+        summons: None,
+    },
+    MonsterTemplate {
+        name: "Cave Crawler",
+        glyph: 'c',
+        fg: rltk::LIGHTSLATEGRAY,
+        max_hp: 10,
+        defense: 0,
+        power: 3,
+        vision_range: 1,
+        blocks_vision: false,
+        faction: "Crawlers",
+        tags: &[],
+        size: (1, 1),
+        aggro_range: 6,
+        ranged: None,
+        flee_below: 0,
+        venom: None,
+        speed: 2,
+        gold: None,
+        resists: &[],
+        vulnerable: &[],
+        summons: None,
+    },
+    MonsterTemplate {
+        name: "Kobold Archer",
+        glyph: 'k',
+        fg: rltk::ORANGE,
+        max_hp: 6,
+        defense: 0,
+        power: 2,
+        vision_range: 8,
+        blocks_vision: false,
+        faction: "Kobolds",
+        tags: &[],
+        size: (1, 1),
+        aggro_range: 0,
+        ranged: Some((6, 2)),
+        flee_below: 40,
+        venom: None,
+        speed: 1,
+        gold: Some((1, 5)),
+        resists: &[],
+        vulnerable: &[],
+        summons: None,
+    },
+    MonsterTemplate {
+        name: "Giant Spider",
+        glyph: 's',
+        fg: rltk::PURPLE,
+        max_hp: 8,
+        defense: 0,
+        power: 2,
+        vision_range: 6,
+        blocks_vision: false,
+        faction: "Spiders",
+        tags: &[],
+        size: (1, 1),
+        aggro_range: 0,
+        ranged: None,
+        flee_below: 0,
+        venom: Some((1, 4)),
+        speed: 1,
+        gold: None,
+        resists: &[DamageType::Poison],
+        vulnerable: &[],
+        summons: None,
+    },
+    MonsterTemplate {
+        name: "Goblin Shaman",
+        glyph: 'g',
+        fg: rltk::MEDIUM_PURPLE,
+        max_hp: 10,
+        defense: 0,
+        power: 2,
+        vision_range: 8,
+        blocks_vision: false,
+        faction: "Goblins",
+        tags: &["flammable"],
+        size: (1, 1),
+        aggro_range: 0,
+        ranged: None,
+        flee_below: 50,
+        venom: None,
+        speed: 1,
+        gold: Some((3, 12)),
+        resists: &[],
+        vulnerable: &[],
+        summons: Some(("Goblin", 8)),
+    },
+    MonsterTemplate {
+        name: "Ice Golem",
+        glyph: 'G',
+        fg: rltk::LIGHT_CYAN,
+        max_hp: 24,
+        defense: 3,
+        power: 6,
+        vision_range: 6,
+        blocks_vision: false,
+        faction: "Golems",
+        tags: &[],
+        size: (1, 1),
+        aggro_range: 0,
+        ranged: None,
+        flee_below: 0,
+        venom: None,
+        speed: 2,
+        gold: None,
+        resists: &[DamageType::Cold],
+        vulnerable: &[DamageType::Fire],
+        summons: None,
+    },
+];
 
 pub fn spawn_player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
     ecs.create_entity()
@@ -21,7 +242,7 @@ pub fn spawn_player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
             glyph: rltk::to_cp437('@'),
             fg: RGB::named(rltk::YELLOW),
             bg: RGB::named(rltk::BLACK),
-            render_order: 0,
+            render_order: RENDER_ORDER_PLAYER,
         })
         .with(Player {})
         .with(Viewshed {
@@ -32,129 +253,479 @@ pub fn spawn_player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
         .with(Name {
             name: "Player".to_string(),
         })
+        .with(Faction {
+            name: "Player".to_string(),
+        })
         .with(CombatStats {
             max_hp: 30,
             hp: 30,
             defense: 2,
             power: 5,
         })
+        .with(Experience {
+            xp: 0,
+            level: 1,
+        })
+        .with(HungerClock {
+            state: HungerState::WellFed,
+            duration: WELL_FED_TURNS,
+        })
+        .with(Initiative {
+            current: TICKS_PER_TURN,
+            speed: TICKS_PER_TURN,
+        })
+        .with(Wallet { gold: 0 })
         .marked::<SimpleMarker<SerializeMe>>()
         .build()
 }
 
-pub fn random_monster(ecs: &mut World, x: i32, y: i32) {
-    let roll: i32;
-    {
-        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
-        roll = rng.roll_dice(1, 2);
-    }
-    match roll {
-        1 => orc(ecs, x, y),
-        _ => goblin(ecs, x, y),
-    }
-}
-
-fn orc(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, rltk::to_cp437('o'), "Orc");
+// slow monsters start partway through their wait, so a group doesn't move in step
+fn starting_initiative(template: &MonsterTemplate, rng: &mut RandomNumberGenerator) -> Initiative {
+    let speed = template.speed * TICKS_PER_TURN;
+    Initiative{current: rng.range(1, speed + 1), speed}
 }
 
-fn goblin(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, rltk::to_cp437('g'), "Goblin");
+// a fresh initiative for the monster called name, when it's back in play after a while away
+pub fn monster_initiative(name: &str, rng: &mut RandomNumberGenerator) -> Option<Initiative> {
+    MONSTERS.iter().find(|m| m.name == name).map(|template| starting_initiative(template, rng))
 }
 
-
-fn monster<S : ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharType, name : S) {
-    ecs.create_entity()
+fn monster(ecs: &mut World, x: i32, y: i32, template: &MonsterTemplate) -> Entity {
+    let initiative = starting_initiative(template, &mut ecs.write_resource::<RandomNumberGenerator>());
+    let mut builder = ecs.create_entity()
         .with(Position {x, y})
         .with(Renderable{
-            glyph,
-            fg: RGB::named(rltk::RED),
+            glyph: rltk::to_cp437(template.glyph),
+            fg: RGB::named(template.fg),
             bg: RGB::named(rltk::BLACK),
-            render_order: 1,
+            render_order: RENDER_ORDER_MONSTER,
         })
         .with(Viewshed{
             visible_tiles: Vec::new(),
-            range: 8,
+            range: template.vision_range,
             dirty: true,
         })
         .with(Monster{})
-        .with(Name{name: name.to_string()})
+        .with(Name{name: template.name.to_string()})
+        .with(Faction{name: template.faction.to_string()})
+        .with(initiative)
         .with(BlocksTile{})
         .with(CombatStats{
-            max_hp: 16,
-            hp: 16,
-            defense: 1,
-            power: 4,
-        })
+            max_hp: template.max_hp,
+            hp: template.max_hp,
+            defense: template.defense,
+            power: template.power,
+        });
+
+    if template.blocks_vision {
+        builder = builder.with(BlocksVision{});
+    }
+
+    if !template.tags.is_empty() {
+        builder = builder.with(Tags::new(template.tags));
+    }
+
+    if template.aggro_range > 0 {
+        builder = builder.with(AggroRange{tiles: template.aggro_range});
+    }
+
+    if let Some((range, damage)) = template.ranged {
+        builder = builder.with(RangedAttack{range, damage});
+    }
+
+    if let Some((damage, turns)) = template.venom {
+        builder = builder.with(Venomous{damage, turns});
+    }
+
+    if let Some((min, max)) = template.gold {
+        builder = builder.with(GoldDrop{min, max});
+    }
+
+    if let Some((spawn_name, every)) = template.summons {
+        builder = builder.with(Summoner{spawn_name: spawn_name.to_string(), every, cooldown: every});
+    }
+
+    if !template.resists.is_empty() {
+        builder = builder.with(Resistances{types: template.resists.to_vec()});
+    }
+
+    if !template.vulnerable.is_empty() {
+        builder = builder.with(Vulnerabilities{types: template.vulnerable.to_vec()});
+    }
+
+    if template.flee_below > 0 {
+        builder = builder.with(CanFlee{threshold_pct: template.flee_below, fleeing: false});
+    }
+
+    if template.size != (1, 1) {
+        builder = builder.with(Size{w: template.size.0, h: template.size.1});
+    }
+
+    builder
         .marked::<SimpleMarker<SerializeMe>>()
-        .build();
+        .build()
+}
+
+// Rolls what goes in every room but the first, where the player starts, and maybe
+// a fountain, as (tile index, name) pairs for spawn_from_list. Only open floor gets
+// anything, and never the player's own starting tile
+pub fn fill_rooms(map: &Map, start: &Position, rng: &mut RandomNumberGenerator, spawn_list: &mut Vec<(usize, String)>) {
+    let start = Point::new(start.x, start.y);
+    let mut start_threat = 0;
+    for room in map.rooms.iter().skip(1) {
+        spawn_room(map, room, rng, start, &mut start_threat, spawn_list);
+    }
+    spawn_fountain(map, rng, start, spawn_list);
 }
 
-// fills a room with stuff
-#[allow(clippy::clippy::map_entry)]
-pub fn spawn_room(ecs: &mut World, room: &Rect, map_depth: i32) {
+// Whether name may be spawned on idx: every tile it covers has to be open floor,
+// off the player's start and clear of whatever spawn_list already holds
+fn is_spawnable(map: &Map, idx: usize, name: &str, start: Point, spawn_list: &[(usize, String)]) -> bool {
+    let start_idx = map.xy_idx(start.x, start.y);
+    spawn_tiles(idx, name).into_iter().all(|tile| {
+        let x = (tile % MAPWIDTH) as i32;
+        let y = (tile / MAPWIDTH) as i32;
+        !map.is_out_of_bounds(x, y) && map.tiles[tile] == TileType::Floor && tile != start_idx && !is_taken(spawn_list, tile)
+    })
+}
+
+// the tiles the named spawn covers when placed on idx, big monsters take up more than one
+fn spawn_tiles(idx: usize, name: &str) -> Vec<usize> {
+    let x = (idx % MAPWIDTH) as i32;
+    let y = (idx / MAPWIDTH) as i32;
+    let size = MONSTERS
+        .iter()
+        .find(|m| m.name == name)
+        .map(|template| Size{w: template.size.0, h: template.size.1});
+    footprint(size.as_ref(), x, y)
+        .iter()
+        .map(|tile| tile.y as usize * MAPWIDTH + tile.x as usize)
+        .collect()
+}
+
+// whether something already in spawn_list covers idx
+fn is_taken(spawn_list: &[(usize, String)], idx: usize) -> bool {
+    spawn_list.iter().any(|(spawned, name)| spawn_tiles(*spawned, name).contains(&idx))
+}
+
+// rolls the stuff for a room, start_threat is the monster power already placed near the start
+fn spawn_room(
+    map: &Map,
+    room: &Rect,
+    rng: &mut RandomNumberGenerator,
+    start: Point,
+    start_threat: &mut i32,
+    spawn_list: &mut Vec<(usize, String)>,
+) {
+    let map_depth = map.depth;
     let spawn_table = room_table(map_depth);
-    let mut spawn_points: HashMap<usize, String> = HashMap::new();
-
-    {
-        let mut rng = ecs.write_resource::<RandomNumberGenerator>();
-        let num_spawns = rng.roll_dice(1, MAX_MONSTERS + 3) + (map_depth - 1) - 3;
-
-        for _i in 0..num_spawns {
-            let mut added = false;
-            let mut tries = 0;
-            while !added && tries < 20 {
-                let x = (room.x1 + rng.roll_dice(1, i32::abs(room.x2 - room.x1))) as usize;
-                let y = (room.y1 + rng.roll_dice(1, i32::abs(room.y2 - room.y1))) as usize;
-                let idx = (y * MAPWIDTH) + x;
-                if !spawn_points.contains_key(&idx) {
-                    spawn_points.insert(idx, spawn_table.roll(&mut rng));
-                    added = true
-                } else {
-                    tries += 1;
-                }
+    let mut spawn_points: Vec<(usize, String)> = Vec::new();
+
+    let num_spawns = rng.roll_dice(1, MAX_MONSTERS + 3) + (map_depth - 1) - 3;
+    for _i in 0..num_spawns {
+        let name = spawn_table.roll(rng);
+        for _try in 0..20 {
+            let x = (room.x1 + rng.roll_dice(1, i32::abs(room.x2 - room.x1))) as usize;
+            let y = (room.y1 + rng.roll_dice(1, i32::abs(room.y2 - room.y1))) as usize;
+            let idx = (y * MAPWIDTH) + x;
+            // a big monster can't overlap anything, from this room or an earlier one
+            let overlaps = spawn_tiles(idx, &name)
+                .iter()
+                .any(|tile| is_taken(spawn_list, *tile) || is_taken(&spawn_points, *tile));
+            if !overlaps {
+                spawn_points.push((idx, name));
+                break;
+            }
+        }
+    }
+
+    for (idx, name) in spawn_points.into_iter() {
+        // cave rooms aren't all floor, nothing goes inside a wall, big monsters included
+        if !is_spawnable(map, idx, &name, start, spawn_list) {
+            continue;
+        }
+        let x = (idx % MAPWIDTH) as i32;
+        let y = (idx / MAPWIDTH) as i32;
+
+        let template = match MONSTERS.iter().find(|m| m.name == name) {
+            Some(template) => template,
+            None => {
+                spawn_list.push((idx, name));
+                continue;
             }
+        };
+
+        let near_start = map_depth <= START_THREAT_DEPTHS
+            && rltk::DistanceAlg::Pythagoras.distance2d(start, Point::new(x, y)) < START_THREAT_RADIUS;
+        if !near_start {
+            spawn_list.push((idx, name));
+            continue;
+        }
+
+        // over budget: fall back to the strongest weaker monster that still fits, or nothing
+        let fitting = MONSTERS
+            .iter()
+            .filter(|m| m.power <= template.power && *start_threat + m.power <= START_THREAT_CAP)
+            .max_by_key(|m| m.power);
+        if let Some(fitting) = fitting {
+            *start_threat += fitting.power;
+            spawn_list.push((idx, fitting.name.to_string()));
         }
     }
+}
 
-    //Actually spawn monsters and items
-    for spawn in spawn_points.iter() {
-        let x = (*spawn.0 % MAPWIDTH) as i32;
-        let y = (*spawn.0 / MAPWIDTH) as i32;
+// places everything a map builder rolled up
+pub fn spawn_from_list(ecs: &mut World, spawn_list: &[(usize, String)]) {
+    for (idx, name) in spawn_list.iter() {
+        let x = (*idx % MAPWIDTH) as i32;
+        let y = (*idx / MAPWIDTH) as i32;
 
-        match spawn.1.as_ref() {
-            "Goblin" => goblin(ecs, x, y),
-            "Orc" => orc(ecs, x, y),
+        if let Some(template) = MONSTERS.iter().find(|m| m.name == name) {
+            monster(ecs, x, y, template);
+            continue;
+        }
+
+        match name.as_ref() {
+            "Fountain" => fountain(ecs, x, y),
             "Health Potion" => spawn_health_potion(ecs, x, y),
+            "Ration" => ration(ecs, x, y),
+            "Bear Trap" => bear_trap(ecs, x, y),
+            "Web" => web(ecs, x, y),
+            "Fire Potion" => fire_potion(ecs, x, y),
+            "Haste Potion" => haste_potion(ecs, x, y),
             "Fireball Scroll" => fireball_scroll(ecs, x, y),
             "Confusion Scroll" => confusion_scroll(ecs, x, y),
+            "Stinking Cloud Scroll" => stinking_cloud_scroll(ecs, x, y),
+            "Inferno Scroll" => inferno_scroll(ecs, x, y),
             "Magic Missile Scroll" => magic_missile_scroll(ecs, x, y),
+            "Frost Bolt Scroll" => frost_bolt_scroll(ecs, x, y),
+            "Magic Mapping Scroll" => magic_mapping_scroll(ecs, x, y),
+            "Scroll of Identify" => identify_scroll(ecs, x, y),
+            "Torch" => torch(ecs, x, y),
+            "Teleport Scroll" => teleport_scroll(ecs, x, y),
+            "Blink Scroll" => blink_scroll(ecs, x, y),
             "Dagger" => dagger(ecs, x, y),
+            "Poison Dagger" => poison_dagger(ecs, x, y),
             "Shield" => shield(ecs, x, y),
+            "Ring of Regeneration" => ring_of_regeneration(ecs, x, y),
+            "Spiked Shield" => spiked_shield(ecs, x, y),
+            "Leather Armor" => leather_armor(ecs, x, y),
+            "Leather Cap" => leather_cap(ecs, x, y),
             "Greataxe" => greataxe(ecs, x, y),
             "Longsword" => longsword(ecs, x, y),
-            "Tower Shield" => tower_shield(ecs, x, y), 
+            "Tower Shield" => tower_shield(ecs, x, y),
+            "Blackened Blade" => blackened_blade(ecs, x, y),
             _ => {}
         }
     }
 }
 
 
-fn spawn_health_potion(ecs: &mut World, x: i32, y: i32) {
+// spawns whatever the monster AI decided to summon this turn, without the gold
+// the same monster would have had if it was found on the level
+pub fn summon_minions(ecs: &mut World) {
+    let summons: Vec<(i32, i32, String)> = {
+        let mut wants_summon = ecs.write_storage::<WantsToSummon>();
+        let summons = wants_summon
+            .join()
+            .map(|summon| (summon.x, summon.y, summon.spawn_name.clone()))
+            .collect();
+        wants_summon.clear();
+        summons
+    };
+    for (x, y, name) in summons.iter() {
+        let template = match MONSTERS.iter().find(|m| m.name == name) {
+            Some(template) => template,
+            None => continue,
+        };
+        let minion = monster(ecs, *x, *y, template);
+        ecs.write_storage::<GoldDrop>().remove(minion);
+        ecs.write_storage::<Summoned>().insert(minion, Summoned{}).expect("Unable to insert summoned");
+    }
+}
+
+// at most one fountain on the level, never in the starting room
+fn spawn_fountain(map: &Map, rng: &mut RandomNumberGenerator, start: Point, spawn_list: &mut Vec<(usize, String)>) {
+    if map.rooms.len() < 2 || rng.roll_dice(1, 2) == 1 {
+        return;
+    }
+
+    let room = map.rooms[rng.range(1, map.rooms.len() as i32) as usize];
+    let x = room.x1 + rng.roll_dice(1, i32::abs(room.x2 - room.x1));
+    let y = room.y1 + rng.roll_dice(1, i32::abs(room.y2 - room.y1));
+    let idx = map.xy_idx(x, y);
+    if is_spawnable(map, idx, "Fountain", start, spawn_list) {
+        spawn_list.push((idx, "Fountain".to_string()));
+    }
+}
+
+fn fountain(ecs: &mut World, x: i32, y: i32) {
     ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('≈'),
+            fg: RGB::named(rltk::CYAN),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Fountain".to_string()})
+        .with(BlocksTile{})
+        .with(Fountain{uses: 1})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+fn spawn_health_potion(ecs: &mut World, x: i32, y: i32) {
+    let mask = ecs.fetch::<MagicItemMasks>().mask_for("Health Potion");
+    let mut builder = ecs.create_entity()
         .with(Position {x, y})
         .with(Renderable{
             glyph: rltk::to_cp437('¡'),
             fg: RGB::named(rltk::MAGENTA),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2,
+            render_order: RENDER_ORDER_ITEM,
         })
         .with(Name{name: "Health Potion".to_string()})
         .with(Item{})
+        .with(Weight{pounds: 0.5})
         .with(Consumable{})
         .with(ProvidesHealing{
             heal_amount: 8
         })
+        .with(ShattersOnThrow{radius: 1});
+
+    if let Some(mask) = mask {
+        builder = builder.with(mask);
+    }
+
+    builder
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// hidden until something steps in it, then it's spent
+fn bear_trap(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position {x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('^'),
+            fg: RGB::named(rltk::RED),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Bear Trap".to_string()})
+        .with(Hidden{})
+        .with(EntryTrigger{})
+        .with(SingleActivation{})
+        .with(InflictsDamage{damage: 6, kind: DamageType::Physical})
+        .with(Slowed{turns: 6})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+// out in the open, but whatever walks into it is stuck for a while and tears it
+fn web(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position {x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('≈'),
+            fg: RGB::named(rltk::LIGHT_GRAY),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Web".to_string()})
+        .with(EntryTrigger{})
+        .with(SingleActivation{})
+        .with(Rooted{turns: 4})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+pub fn gold_pile(ecs: &mut World, x: i32, y: i32, amount: i32) {
+    ecs.create_entity()
+        .with(Position {x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('$'),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Gold".to_string()})
+        .with(Gold{amount})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+fn ration(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position {x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('%'),
+            fg: RGB::named(rltk::GREEN),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Ration".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 1.0})
+        .with(Consumable{})
+        .with(ProvidesFood{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+fn haste_potion(ecs: &mut World, x: i32, y: i32) {
+    let mask = ecs.fetch::<MagicItemMasks>().mask_for("Haste Potion");
+    let mut builder = ecs.create_entity()
+        .with(Position {x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('¡'),
+            fg: RGB::named(rltk::LIGHT_BLUE),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Haste Potion".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(ProvidesHaste{turns: 20});
+
+    if let Some(mask) = mask {
+        builder = builder.with(mask);
+    }
+
+    builder
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+// meant to be thrown, using it from the inventory also asks for a target
+fn fire_potion(ecs: &mut World, x: i32, y: i32) {
+    let mask = ecs.fetch::<MagicItemMasks>().mask_for("Fire Potion");
+    let mut builder = ecs.create_entity()
+        .with(Position {x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('¡'),
+            fg: RGB::named(rltk::ORANGE),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Fire Potion".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Ranged{range: 6})
+        .with(InflictsDamage{damage: 10, kind: DamageType::Fire})
+        .with(ShattersOnThrow{radius: 1});
+
+    if let Some(mask) = mask {
+        builder = builder.with(mask);
+    }
+
+    builder
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
@@ -167,20 +738,40 @@ fn magic_missile_scroll(ecs: &mut World, x: i32, y: i32) {
             glyph: 0x25D9,
             fg: RGB::named(rltk::CYAN),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2
+            render_order: RENDER_ORDER_ITEM
         })
         .with(Name{
             name: "Magic Missile Scroll".to_string()
         })
         .with(Item{})
+        .with(Weight{pounds: 0.5})
         .with(Consumable{})
         .with(Ranged{range: 6})
-        .with(InflictsDamage{damage: 8})
+        .with(InflictsDamage{damage: 8, kind: DamageType::Physical})
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
 
 
+fn frost_bolt_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::LIGHT_BLUE),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Frost Bolt Scroll".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Ranged{range: 6})
+        .with(InflictsDamage{damage: 12, kind: DamageType::Cold})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
 fn fireball_scroll(ecs: &mut World, x: i32, y: i32) {
     ecs.create_entity()
         .with(Position{x, y})
@@ -188,19 +779,43 @@ fn fireball_scroll(ecs: &mut World, x: i32, y: i32) {
             glyph: 0x25D9,
             fg: RGB::named(rltk::ORANGE),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2,
+            render_order: RENDER_ORDER_ITEM,
         })
         .with(Name{name: "Fireball Scroll".to_string()})
         .with(Item{})
+        .with(Weight{pounds: 0.5})
         .with(Consumable{})
         .with(Ranged {range: 6})
-        .with(InflictsDamage{damage: 20})
+        .with(InflictsDamage{damage: 20, kind: DamageType::Fire})
         .with(AreaOfEffect{radius: 3})
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
 
 
+// a bigger fireball that doesn't care who is standing in it
+fn inferno_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::CRIMSON),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Inferno Scroll".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Ranged {range: 6})
+        .with(InflictsDamage{damage: 30, kind: DamageType::Fire})
+        .with(AreaOfEffect{radius: 4})
+        .with(HarmsCaster{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
 fn confusion_scroll(ecs: &mut World, x: i32, y: i32) {
     ecs.create_entity()
         .with(Position{x, y})
@@ -208,10 +823,11 @@ fn confusion_scroll(ecs: &mut World, x: i32, y: i32) {
             glyph: 0x25D9,
             fg: RGB::named(rltk::PINK),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2,
+            render_order: RENDER_ORDER_ITEM,
         })
         .with(Name{name: "Confusion Scroll".to_string()})
         .with(Item{})
+        .with(Weight{pounds: 0.5})
         .with(Consumable{})
         .with(Ranged{range: 6})
         .with(Confusion{turns: 4})
@@ -220,6 +836,129 @@ fn confusion_scroll(ecs: &mut World, x: i32, y: i32) {
 }
 
 
+// shows the layout of the whole level, read on the spot like a potion is drunk
+fn magic_mapping_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::CYAN3),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Magic Mapping Scroll".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(MagicMapper{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// lights the way a little further than the eye can see in the dark
+fn torch(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('!'),
+            fg: RGB::named(rltk::YELLOW),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Torch".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 1.0})
+        .with(LightSource{radius: 10, color: RGB::named(rltk::ORANGE)})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// tells the reader what one unknown item in their backpack really is
+fn identify_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::WHEAT),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Scroll of Identify".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Identify{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// throws the reader somewhere random on the level, a way out when cornered
+fn teleport_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::MEDIUMPURPLE),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Teleport Scroll".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Teleport{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// like a teleport scroll, but the reader picks where to land
+fn blink_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::VIOLET),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Blink Scroll".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Ranged{range: 8})
+        .with(Teleport{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// a cloud of gas that seeps around corners and through walls
+fn stinking_cloud_scroll(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: 0x25D9,
+            fg: RGB::named(rltk::OLIVE),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM,
+        })
+        .with(Name{name: "Stinking Cloud Scroll".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 0.5})
+        .with(Consumable{})
+        .with(Ranged{range: 6})
+        .with(Confusion{turns: 3})
+        .with(AreaOfEffect{radius: 2})
+        .with(IgnoresWalls{})
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
 fn dagger(ecs: &mut World, x: i32, y: i32) {
     ecs.create_entity()
         .with(Position{ x, y })
@@ -227,14 +966,61 @@ fn dagger(ecs: &mut World, x: i32, y: i32) {
             glyph: rltk::to_cp437('/'),
             fg: RGB::named(rltk::CYAN),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2
+            render_order: RENDER_ORDER_ITEM
         })
         .with(Name{ name : "Dagger".to_string() })
         .with(Item{})
+        .with(Weight{pounds: 1.0})
         .with(Equippable{ slot: EquipmentSlot::Melee })
         .with(MeleePowerBonus{
             power: 2
         })
+        .with(CritChance{ percent: 10 })
+        .with(Durability{ current: 30, max: 30 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// weaker than a plain dagger, but whatever it cuts keeps bleeding poison for a while
+fn poison_dagger(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{ x, y })
+        .with(Renderable{
+            glyph: rltk::to_cp437('/'),
+            fg: RGB::named(rltk::GREEN),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM
+        })
+        .with(Name{ name : "Poison Dagger".to_string() })
+        .with(Item{})
+        .with(Weight{pounds: 1.0})
+        .with(Equippable{ slot: EquipmentSlot::Melee })
+        .with(MeleePowerBonus{
+            power: 1
+        })
+        .with(Venomous{ damage: 1, turns: 4 })
+        .with(Durability{ current: 30, max: 30 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// slowly heals whoever wears it
+fn ring_of_regeneration(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{ x, y })
+        .with(Renderable{
+            glyph: rltk::to_cp437('='),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM
+        })
+        .with(Name{ name : "Ring of Regeneration".to_string() })
+        .with(Item{})
+        .with(Weight{pounds: 0.1})
+        .with(Equippable{ slot: EquipmentSlot::Ring })
+        .with(GrantsRegeneration{ amount: 1 })
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
@@ -247,14 +1033,86 @@ fn shield(ecs: &mut World, x: i32, y: i32) {
             glyph: rltk::to_cp437('('),
             fg: RGB::named(rltk::CYAN),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2
+            render_order: RENDER_ORDER_ITEM
         })
         .with(Name{ name : "Shield".to_string() })
         .with(Item{})
+        .with(Weight{pounds: 6.0})
         .with(Equippable{ slot: EquipmentSlot::Shield })
         .with(DefenseBonus{
             defense: 1
         })
+        .with(Durability{ current: 30, max: 30 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+// a shield covered in spikes, hitting whoever holds it hurts
+fn spiked_shield(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{ x, y })
+        .with(Renderable{
+            glyph: rltk::to_cp437('('),
+            fg: RGB::named(rltk::SILVER),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM
+        })
+        .with(Name{ name : "Spiked Shield".to_string() })
+        .with(Item{})
+        .with(Weight{pounds: 8.0})
+        .with(Equippable{ slot: EquipmentSlot::Shield })
+        .with(DefenseBonus{
+            defense: 1
+        })
+        .with(Thorns{
+            amount: 2
+        })
+        .with(Durability{ current: 30, max: 30 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+fn leather_armor(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{ x, y })
+        .with(Renderable{
+            glyph: rltk::to_cp437('['),
+            fg: RGB::named(rltk::BROWN1),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM
+        })
+        .with(Name{ name : "Leather Armor".to_string() })
+        .with(Item{})
+        .with(Weight{pounds: 10.0})
+        .with(Equippable{ slot: EquipmentSlot::Body })
+        .with(DefenseBonus{
+            defense: 1
+        })
+        .with(Durability{ current: 40, max: 40 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+
+fn leather_cap(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{ x, y })
+        .with(Renderable{
+            glyph: rltk::to_cp437('^'),
+            fg: RGB::named(rltk::BROWN1),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM
+        })
+        .with(Name{ name : "Leather Cap".to_string() })
+        .with(Item{})
+        .with(Weight{pounds: 2.0})
+        .with(Equippable{ slot: EquipmentSlot::Head })
+        .with(DefenseBonus{
+            defense: 1
+        })
+        .with(Durability{ current: 25, max: 25 })
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
@@ -267,18 +1125,20 @@ fn longsword(ecs: &mut World, x: i32, y: i32) {
             glyph: rltk::to_cp437('!'),
             fg: RGB::named(rltk::YELLOW),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2
+            render_order: RENDER_ORDER_ITEM
         })
         .with(Name{
             name: "Longsword".to_string()
         })
         .with(Item{})
+        .with(Weight{pounds: 3.0})
         .with(Equippable{
             slot: EquipmentSlot::Melee
         })
         .with(MeleePowerBonus{
             power: 4
         })
+        .with(Durability{ current: 40, max: 40 })
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
@@ -290,18 +1150,20 @@ fn tower_shield(ecs: &mut World, x: i32, y: i32) {
             glyph: rltk::to_cp437('H'),
             fg: RGB::named(rltk::YELLOW),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2,
+            render_order: RENDER_ORDER_ITEM,
         })
         .with(Name{
             name: "Tower Shield".to_string()
         })
         .with(Item{})
+        .with(Weight{pounds: 15.0})
         .with(Equippable{
             slot: EquipmentSlot::Shield
         })
         .with(DefenseBonus{
             defense: 3
         })
+        .with(Durability{ current: 50, max: 50 })
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
@@ -314,29 +1176,181 @@ fn greataxe(ecs: &mut World, x: i32, y: i32) {
             glyph: rltk::to_cp437('P'),
             fg: RGB::named(rltk::CRIMSON),
             bg: RGB::named(rltk::BLACK),
-            render_order: 2
+            render_order: RENDER_ORDER_ITEM
         })
         .with(Name{name: "Greataxe".to_string()})
         .with(Item{})
+        .with(Weight{pounds: 12.0})
         .with(Equippable{slot: EquipmentSlot::Melee})
+        .with(TwoHanded{})
         .with(MeleePowerBonus{
             power: 6
         })
+        .with(Durability{ current: 50, max: 50 })
+        .marked::<SimpleMarker<SerializeMe>>()
+        .build();
+}
+
+// hits hard, but won't let go of its wielder
+fn blackened_blade(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position{x, y})
+        .with(Renderable{
+            glyph: rltk::to_cp437('/'),
+            fg: RGB::named(rltk::DIMGREY),
+            bg: RGB::named(rltk::BLACK),
+            render_order: RENDER_ORDER_ITEM
+        })
+        .with(Name{name: "Blackened Blade".to_string()})
+        .with(Item{})
+        .with(Weight{pounds: 3.0})
+        .with(Equippable{slot: EquipmentSlot::Melee})
+        .with(MeleePowerBonus{
+            power: 5
+        })
+        .with(Cursed{})
         .marked::<SimpleMarker<SerializeMe>>()
         .build();
 }
 
+// items whose weight slides with depth reach their deep weight here and stay there
+const WEIGHT_SCALING_DEPTH: i32 = 10;
+
+// a weight that goes from shallow on the first level to deep at WEIGHT_SCALING_DEPTH
+fn by_depth(map_depth: i32, shallow: i32, deep: i32) -> i32 {
+    let depth = map_depth.clamp(1, WEIGHT_SCALING_DEPTH);
+    shallow + (deep - shallow) * (depth - 1) / (WEIGHT_SCALING_DEPTH - 1)
+}
+
 fn room_table(map_depth: i32) -> RandomTable{
     RandomTable::new()
         .add("Goblin", 10)
-        .add("Oric", 1 + map_depth)
-        .add("Health Potion", 7)
-        .add("Fireball Scroll", 2 + map_depth)
-        .add("Confusion Scroll", 2 + map_depth)
-        .add("Magic Missile Scroll", 4)
+        .add("Orc", 1 + map_depth)
+        .add("Ogre", map_depth - 3)
+        .add("Cave Crawler", map_depth - 1)
+        .add("Kobold Archer", map_depth)
+        .add("Giant Spider", map_depth - 1)
+        .add("Goblin Shaman", map_depth - 2)
+        .add("Ice Golem", map_depth - 4)
+        .add("Health Potion", by_depth(map_depth, 7, 3))
+        .add("Ration", 6)
+        .add("Bear Trap", 2)
+        .add("Web", map_depth)
+        .add("Fire Potion", by_depth(map_depth, 1, 6))
+        .add("Haste Potion", by_depth(map_depth, 1, 3))
+        .add("Fireball Scroll", by_depth(map_depth, 1, 10))
+        .add("Confusion Scroll", by_depth(map_depth, 2, 8))
+        .add("Stinking Cloud Scroll", map_depth - 1)
+        .add("Inferno Scroll", map_depth - 4)
+        .add("Magic Missile Scroll", by_depth(map_depth, 4, 2))
+        .add("Frost Bolt Scroll", map_depth - 2)
+        .add("Magic Mapping Scroll", 2)
+        .add("Scroll of Identify", 3)
+        .add("Torch", 3)
+        .add("Teleport Scroll", 2)
+        .add("Blink Scroll", map_depth - 1)
         .add("Dagger", 3)
+        .add("Poison Dagger", map_depth - 1)
         .add("Shield", 3)
+        .add("Ring of Regeneration", map_depth - 2)
+        .add("Spiked Shield", map_depth - 2)
+        .add("Leather Armor", 3)
+        .add("Leather Cap", 3)
         .add("Longsword", 2 + map_depth - 4)
         .add("Tower Shield", 2 + map_depth - 4)
         .add("Greataxe", 1 + map_depth - 6)
-}
\ No newline at end of file
+        .add("Blackened Blade", map_depth - 3)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{open_map, TEST_SEED};
+
+    #[test]
+    fn start_threat_stays_under_the_cap() {
+        let mut map = open_map();
+        map.depth = START_THREAT_DEPTHS;
+        map.rooms = vec![
+            Rect::new(2, 2, 6, 6),
+            Rect::new(12, 2, 8, 8),
+            Rect::new(2, 12, 8, 8),
+            Rect::new(12, 12, 8, 8),
+        ];
+        let start = Position{x: 5, y: 5};
+        for seed in TEST_SEED..TEST_SEED + 50 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let mut spawn_list = Vec::new();
+            fill_rooms(&map, &start, &mut rng, &mut spawn_list);
+
+            let threat: i32 = spawn_list
+                .iter()
+                .filter_map(|(idx, name)| MONSTERS.iter().find(|m| m.name == name).map(|m| (idx, m)))
+                .filter(|(idx, _template)| {
+                    let spot = Point::new((**idx % MAPWIDTH) as i32, (**idx / MAPWIDTH) as i32);
+                    rltk::DistanceAlg::Pythagoras.distance2d(Point::new(start.x, start.y), spot) < START_THREAT_RADIUS
+                })
+                .map(|(_idx, template)| template.power)
+                .sum();
+            assert!(threat <= START_THREAT_CAP, "seed {} put {} power by the start", seed, threat);
+        }
+    }
+
+    #[test]
+    fn big_monsters_never_overlap_other_spawns() {
+        let mut map = open_map();
+        map.depth = 10;
+        map.rooms = (0..6).map(|i| Rect::new(2 + i * 12, 10, 8, 8)).collect();
+        let start = Position{x: 5, y: 13};
+        for seed in TEST_SEED..TEST_SEED + 50 {
+            let mut rng = RandomNumberGenerator::seeded(seed);
+            let mut spawn_list = Vec::new();
+            fill_rooms(&map, &start, &mut rng, &mut spawn_list);
+
+            let mut covered = Vec::new();
+            for (idx, name) in spawn_list.iter() {
+                for tile in spawn_tiles(*idx, name) {
+                    assert!(!covered.contains(&tile), "seed {} put two spawns on {}", seed, tile);
+                    covered.push(tile);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn an_ogre_needs_all_four_of_its_tiles_free() {
+        let mut map = open_map();
+        let wall = map.xy_idx(11, 11);
+        map.tiles[wall] = TileType::Wall;
+        let start = Point::new(21, 21);
+        let spawned = vec![(map.xy_idx(31, 31), "Goblin".to_string())];
+
+        assert!(is_spawnable(&map, map.xy_idx(40, 40), "Ogre", start, &spawned));
+        // the ogre's corner tile, not the one it's placed on, is the trouble each time
+        assert!(!is_spawnable(&map, map.xy_idx(10, 10), "Ogre", start, &spawned));
+        assert!(!is_spawnable(&map, map.xy_idx(20, 20), "Ogre", start, &spawned));
+        assert!(!is_spawnable(&map, map.xy_idx(30, 30), "Ogre", start, &spawned));
+        assert!(is_spawnable(&map, map.xy_idx(30, 30), "Goblin", start, &spawned));
+    }
+
+    // how many of rolls rolls on the depth's room table come up name
+    fn share_of(name: &str, depth: i32, rolls: i32) -> f32 {
+        let mut rng = RandomNumberGenerator::seeded(TEST_SEED);
+        let table = room_table(depth);
+        let hits = (0..rolls).filter(|_roll| table.roll(&mut rng) == name).count();
+        hits as f32 / rolls as f32
+    }
+
+    #[test]
+    fn deeper_rooms_hold_more_fireballs_and_fewer_health_potions() {
+        let rolls = 5000;
+        let fireballs = (share_of("Fireball Scroll", 1, rolls), share_of("Fireball Scroll", 8, rolls));
+        let potions = (share_of("Health Potion", 1, rolls), share_of("Health Potion", 8, rolls));
+
+        // weight 1 of 60 at depth 1 against 8 of 174 at depth 8
+        assert!((fireballs.0 - 1.0 / 60.0).abs() < 0.01, "{:?}", fireballs);
+        assert!((fireballs.1 - 8.0 / 174.0).abs() < 0.015, "{:?}", fireballs);
+        // weight 7 of 60 at depth 1 against 4 of 174 at depth 8
+        assert!((potions.0 - 7.0 / 60.0).abs() < 0.015, "{:?}", potions);
+        assert!((potions.1 - 4.0 / 174.0).abs() < 0.01, "{:?}", potions);
+    }
+}
@@ -0,0 +1,128 @@
+use specs::prelude::*;
+
+use crate::{
+    gamelog::{GameLog, LogCategory},
+    CombatStats, DamageOverTime, DamageType, Equipped, GrantsRegeneration, OtherLevelPosition, Player, Regeneration, RunState, SufferDamage,
+};
+
+// Ticks poison, regeneration and the like once per turn, on the same turns hunger counts down.
+// Whoever is waiting on another level keeps theirs paused until the player comes back
+pub struct StatusEffectSystem {}
+
+impl<'a> System<'a> for StatusEffectSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, DamageOverTime>,
+        ReadStorage<'a, Player>,
+        ReadExpect<'a, RunState>,
+        WriteStorage<'a, SufferDamage>,
+        WriteExpect<'a, GameLog>,
+        WriteStorage<'a, Regeneration>,
+        ReadStorage<'a, GrantsRegeneration>,
+        ReadStorage<'a, Equipped>,
+        WriteStorage<'a, CombatStats>,
+        ReadStorage<'a, OtherLevelPosition>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            entities,
+            mut damage_over_time,
+            players,
+            runstate,
+            mut inflict_damage,
+            mut log,
+            mut regeneration,
+            grants_regeneration,
+            equipped,
+            mut combat_stats,
+            away,
+        ) = data;
+
+        let new_turn = matches!(*runstate, RunState::PlayerTurn | RunState::Resting { .. });
+        if !new_turn {
+            return;
+        }
+
+        let mut worn_off: Vec<Entity> = Vec::new();
+        for (entity, dot, _here) in (&entities, &mut damage_over_time, !&away).join() {
+            if players.get(entity).is_some() && !dot.announced {
+                log.push(LogCategory::Combat, "The poison courses through you!");
+                dot.announced = true;
+            }
+
+            SufferDamage::new_typed_damage(&mut inflict_damage, entity, dot.damage, DamageType::Poison, dot.from_player);
+            dot.turns -= 1;
+            if dot.turns <= 0 {
+                worn_off.push(entity);
+            }
+        }
+
+        for entity in worn_off.iter() {
+            damage_over_time.remove(*entity);
+            if players.get(*entity).is_some() {
+                log.push(LogCategory::System, "The poison wears off.");
+            }
+        }
+
+        // worn gear grants regeneration one turn at a time, so it stops by itself
+        // the turn after the item is taken off, swapped out or breaks
+        for (grant, equipped_by) in (&grants_regeneration, &equipped).join() {
+            match regeneration.get_mut(equipped_by.owner) {
+                Some(regen) => {
+                    regen.amount = i32::max(regen.amount, grant.amount);
+                    regen.turns = i32::max(regen.turns, 1);
+                }
+                None => {
+                    regeneration
+                        .insert(equipped_by.owner, Regeneration{ amount: grant.amount, turns: 1 })
+                        .expect("Unable to insert regeneration");
+                }
+            }
+        }
+
+        let mut expired: Vec<Entity> = Vec::new();
+        for (entity, regen, stats, _here) in (&entities, &mut regeneration, &mut combat_stats, !&away).join() {
+            stats.hp = i32::min(stats.max_hp, stats.hp + regen.amount);
+            regen.turns -= 1;
+            if regen.turns <= 0 {
+                expired.push(entity);
+            }
+        }
+
+        for entity in expired.iter() {
+            regeneration.remove(*entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::damage_system::DamageSystem;
+    use crate::test_support::{open_map, spawn_at, test_world};
+    use crate::Experience;
+
+    // the xp the player has once poison finishes off a goblin on its last hp
+    fn xp_for_poison_kill(from_player: bool) -> i32 {
+        let (mut ecs, player) = test_world(open_map(), 10, 10);
+        let goblin = spawn_at(&mut ecs, 14, 10, "Goblin");
+        ecs.write_storage::<CombatStats>().get_mut(goblin).unwrap().hp = 1;
+        ecs.write_storage::<DamageOverTime>()
+            .insert(goblin, DamageOverTime{ damage: 2, turns: 3, announced: false, from_player })
+            .unwrap();
+        *ecs.write_resource::<RunState>() = RunState::PlayerTurn;
+
+        StatusEffectSystem{}.run_now(&ecs);
+        DamageSystem{}.run_now(&ecs);
+        let xp = ecs.read_storage::<Experience>().get(player).unwrap().xp;
+        xp
+    }
+
+    #[test]
+    fn poison_kills_count_for_whoever_applied_it() {
+        assert!(xp_for_poison_kill(true) > 0);
+        assert_eq!(xp_for_poison_kill(false), 0);
+    }
+}
@@ -0,0 +1,116 @@
+use rltk::{Point, RandomNumberGenerator};
+use specs::prelude::*;
+use specs::saveload::SimpleMarkerAllocator;
+
+use super::{
+    gamelog::GameLog, identification::MagicItemMasks, inventory_system::ItemUseSystem, keybindings::KeyBindings,
+    map_indexing_system::MapIndexingSystem, master_dungeon::MasterDungeon, particle_system::ParticleBuilder,
+    register_components, saveload_system, Equippable, Equipped, spawner, visibility_system::VisibilitySystem, InBackpack, Map, Position,
+    RunState, SerializeMe, Settings, Stats, SufferDamage, TileType, TurnCount, WantsToUseItem, MAPHEIGHT, MAPWIDTH,
+};
+
+// the seed every test world's rng starts from
+pub const TEST_SEED: u64 = 42;
+
+// a level of the usual size that's open floor inside its outer wall
+pub fn open_map() -> Map {
+    let mut map = Map::solid(1);
+    for y in 1..MAPHEIGHT as i32 - 1 {
+        for x in 1..MAPWIDTH as i32 - 1 {
+            let idx = map.xy_idx(x, y);
+            map.tiles[idx] = TileType::Floor;
+        }
+    }
+    map.populate_blocked();
+    map
+}
+
+// solid rock apart from a one tile wide corridor along row y from x0 to x1
+pub fn corridor_map(y: i32, x0: i32, x1: i32) -> Map {
+    let mut map = Map::solid(1);
+    for x in x0..=x1 {
+        let idx = map.xy_idx(x, y);
+        map.tiles[idx] = TileType::Floor;
+    }
+    map.populate_blocked();
+    map
+}
+
+// Every component registered and the resources the systems look for, like main
+// sets them up, with the player standing at (x, y) on map and a seeded rng
+pub fn test_world(map: Map, x: i32, y: i32) -> (World, Entity) {
+    let mut ecs = World::new();
+    register_components(&mut ecs);
+    ecs.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+    ecs.insert(Settings::default());
+    ecs.insert(KeyBindings::default());
+    let mut rng = RandomNumberGenerator::seeded(TEST_SEED);
+    ecs.insert(MagicItemMasks::new(&mut rng));
+    ecs.insert(rng);
+    ecs.insert(MasterDungeon::default());
+    ecs.insert(map);
+    ecs.insert(Point::new(x, y));
+    ecs.insert(RunState::AwaitingInput);
+    ecs.insert(GameLog::new());
+    ecs.insert(TurnCount(0));
+    ecs.insert(Stats::default());
+    ecs.insert(saveload_system::SavedAtTurn(None));
+    ecs.insert(saveload_system::LastLoadError(None));
+    ecs.insert(saveload_system::SlotListing(None));
+    ecs.insert(ParticleBuilder::new());
+
+    let player = spawner::spawn_player(&mut ecs, x, y);
+    ecs.insert(player);
+    (ecs, player)
+}
+
+// spawns what spawn_from_list knows as name at (x, y) and hands it back
+pub fn spawn_at(ecs: &mut World, x: i32, y: i32, name: &str) -> Entity {
+    let idx = y as usize * MAPWIDTH + x as usize;
+    spawner::spawn_from_list(ecs, &[(idx, name.to_string())]);
+    let entities = ecs.entities();
+    entities.join().max_by_key(|entity| entity.id()).expect("Nothing was spawned")
+}
+
+// what the systems run before the player gets to look around: sight and the tile index
+pub fn refresh_map(ecs: &mut World) {
+    VisibilitySystem {}.run_now(ecs);
+    MapIndexingSystem {}.run_now(ecs);
+    ecs.maintain();
+}
+
+// spawns name straight into owner's backpack
+pub fn give(ecs: &mut World, owner: Entity, name: &str) -> Entity {
+    let item = spawn_at(ecs, 1, 1, name);
+    ecs.write_storage::<Position>().remove(item);
+    ecs.write_storage::<InBackpack>().insert(item, InBackpack{ owner }).unwrap();
+    item
+}
+
+// spawns name and has owner wear or wield it
+pub fn equip(ecs: &mut World, owner: Entity, name: &str) -> Entity {
+    let item = spawn_at(ecs, 1, 1, name);
+    ecs.write_storage::<Position>().remove(item);
+    let slot = ecs.read_storage::<Equippable>().get(item).expect("Not equippable").slot;
+    ecs.write_storage::<Equipped>().insert(item, Equipped{ owner, slot }).unwrap();
+    item
+}
+
+// user uses item, aimed at target if it's given, and the item system runs once
+pub fn use_item(ecs: &mut World, user: Entity, item: Entity, target: Option<Point>) {
+    ecs.write_storage::<WantsToUseItem>().insert(user, WantsToUseItem{ item, target }).unwrap();
+    ItemUseSystem {}.run_now(ecs);
+    ecs.maintain();
+}
+
+// all the damage queued up for entity, before resistances are counted
+pub fn damage_queued(ecs: &World, entity: Entity) -> i32 {
+    ecs.read_storage::<SufferDamage>()
+        .get(entity)
+        .map_or(0, |damage| damage.amount.iter().map(|(amount, _kind, _by_player)| amount).sum())
+}
+
+// whether any line in the game log mentions text
+pub fn logged(ecs: &World, text: &str) -> bool {
+    ecs.fetch::<GameLog>().entries.iter().any(|entry| entry.text.contains(text))
+}
@@ -0,0 +1,163 @@
+use rltk::RGB;
+use specs::prelude::*;
+
+use crate::{
+    gamelog::{GameLog, LogCategory},
+    inventory_system::name_of,
+    particle_system::{ParticleBuilder, PARTICLE_LIFETIME_MS},
+    EntityMoved, EntryTrigger, Hidden, InflictsDamage, Map, Name, Player, Position, SingleActivation,
+    Rooted, Size, Slowed, SufferDamage, Gold, Wallet, footprint,
+};
+
+// Sets off whatever lies on the tile of anything that moved this turn.
+// Runs after map indexing so tile_content already has everyone's new spot
+pub struct TriggerSystem {}
+
+impl<'a> System<'a> for TriggerSystem {
+    #[allow(clippy::type_complexity)]
+    type SystemData = (
+        ReadExpect<'a, Map>,
+        WriteStorage<'a, EntityMoved>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, EntryTrigger>,
+        WriteStorage<'a, Hidden>,
+        ReadStorage<'a, Name>,
+        Entities<'a>,
+        WriteExpect<'a, GameLog>,
+        ReadStorage<'a, InflictsDamage>,
+        WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, SingleActivation>,
+        ReadStorage<'a, Player>,
+        WriteExpect<'a, ParticleBuilder>,
+        WriteStorage<'a, Slowed>,
+        ReadStorage<'a, Gold>,
+        WriteStorage<'a, Wallet>,
+        WriteStorage<'a, Rooted>,
+        ReadStorage<'a, Size>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            map,
+            mut entity_moved,
+            positions,
+            entry_triggers,
+            mut hidden,
+            names,
+            entities,
+            mut log,
+            inflicts_damage,
+            mut inflict_damage,
+            single_activation,
+            players,
+            mut particle_builder,
+            mut slowed,
+            gold,
+            mut wallets,
+            mut rooted,
+            sizes,
+        ) = data;
+
+        let mut spent: Vec<Entity> = Vec::new();
+        for (entity, _moved, pos) in (&entities, &entity_moved, &positions).join() {
+            // a big creature sets off whatever is under any of its tiles
+            for tile in footprint(sizes.get(entity), pos.x, pos.y) {
+                let idx = map.xy_idx(tile.x, tile.y);
+                for trap in map.tile_content[idx].iter() {
+                    if *trap == entity || entry_triggers.get(*trap).is_none() || spent.contains(trap) {
+                        continue;
+                    }
+
+                    hidden.remove(*trap);
+
+                    if players.get(entity).is_some() && rooted.get(*trap).is_some() {
+                        log.push(LogCategory::Combat, format!(
+                            "You are caught in a {}!",
+                            name_of(&names, *trap).to_lowercase()
+                        ));
+                    } else if players.get(entity).is_some() {
+                        log.push(LogCategory::Combat, format!(
+                            "A {} snaps shut on you!",
+                            name_of(&names, *trap).to_lowercase()
+                        ));
+                    } else if map.visible_tiles[idx] {
+                        log.push(LogCategory::Combat, format!(
+                            "The {} is caught in a {}!",
+                            name_of(&names, entity),
+                            name_of(&names, *trap).to_lowercase()
+                        ));
+                    }
+
+                    if let Some(damage) = inflicts_damage.get(*trap) {
+                        SufferDamage::new_typed_damage(&mut inflict_damage, entity, damage.damage, damage.kind, false);
+                        particle_builder.request(tile.x, tile.y, RGB::named(rltk::ORANGE), rltk::to_cp437('‼'), PARTICLE_LIFETIME_MS);
+                    }
+
+                    if let Some(slow) = slowed.get(*trap).cloned() {
+                        slowed.insert(entity, slow).expect("Unable to insert slow");
+                        if players.get(entity).is_some() {
+                            log.push(LogCategory::Combat, "You feel sluggish.");
+                        }
+                    }
+
+                    if let Some(root) = rooted.get(*trap).cloned() {
+                        rooted.insert(entity, root).expect("Unable to insert root");
+                    }
+
+                    if single_activation.get(*trap).is_some() {
+                        spent.push(*trap);
+                    }
+                }
+            }
+        }
+
+        // gold is scooped up by anyone with a wallet who walks over it
+        let mut collected: Vec<Entity> = Vec::new();
+        for (entity, _moved, pos, wallet) in (&entities, &entity_moved, &positions, &mut wallets).join() {
+            let idx = map.xy_idx(pos.x, pos.y);
+            for pile in map.tile_content[idx].iter() {
+                if let Some(pile_gold) = gold.get(*pile) {
+                    if collected.contains(pile) {
+                        continue;
+                    }
+                    wallet.gold += pile_gold.amount;
+                    collected.push(*pile);
+                    if players.get(entity).is_some() {
+                        log.push(LogCategory::System, format!("You found {} gold.", pile_gold.amount));
+                    }
+                }
+            }
+        }
+
+        for pile in collected.iter() {
+            entities.delete(*pile).expect("Unable to delete gold");
+        }
+
+        for trap in spent.iter() {
+            entities.delete(*trap).expect("Unable to delete trap");
+        }
+
+        entity_moved.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{damage_queued, open_map, refresh_map, spawn_at, test_world};
+
+    #[test]
+    fn an_ogre_sets_off_a_trap_under_any_of_its_tiles() {
+        let (mut ecs, _player) = test_world(open_map(), 5, 5);
+        let trap = spawn_at(&mut ecs, 21, 21, "Bear Trap");
+        let ogre = spawn_at(&mut ecs, 20, 20, "Ogre");
+        ecs.write_storage::<EntityMoved>().insert(ogre, EntityMoved{}).unwrap();
+        refresh_map(&mut ecs);
+
+        TriggerSystem {}.run_now(&ecs);
+        ecs.maintain();
+        assert_eq!(damage_queued(&ecs, ogre), 6);
+        assert!(ecs.read_storage::<Slowed>().get(ogre).is_some());
+        assert!(!ecs.is_alive(trap));
+    }
+}
@@ -1,30 +1,121 @@
-use crate::Player;
+use crate::{footprint, BlocksVision, Equipped, InBackpack, Item, LightSource, Player, Renderable, Settings};
 use specs::prelude::*;
 
-use super::{Map, Position, Viewshed};
-use rltk::{field_of_view, Point};
+use super::{Map, Position, Size, Viewshed};
+use rltk::{field_of_view, Algorithm2D, BaseMap, Point};
 
 pub struct VisibilitySystem {}
 
+// the brightest light owner has on them, whether wielded or just in the pack
+pub fn carried_light(
+    owner: Entity,
+    entities: &Entities,
+    lights: &ReadStorage<LightSource>,
+    equipped: &ReadStorage<Equipped>,
+    backpack: &ReadStorage<InBackpack>,
+) -> Option<LightSource> {
+    (entities, lights)
+        .join()
+        .filter(|(item, _light)| {
+            equipped.get(*item).is_some_and(|e| e.owner == owner)
+                || backpack.get(*item).is_some_and(|b| b.owner == owner)
+        })
+        .map(|(_item, light)| light.clone())
+        .max_by_key(|light| light.radius)
+}
+
+// The map with vision-blocking creatures treated as opaque, fed to field_of_view
+struct OcclusionMap<'a> {
+    map: &'a Map,
+    blockers: Vec<bool>,
+}
+
+impl<'a> BaseMap for OcclusionMap<'a> {
+    fn is_opaque(&self, idx: usize) -> bool {
+        self.map.is_opaque(idx) || self.blockers[idx]
+    }
+}
+
+impl<'a> Algorithm2D for OcclusionMap<'a> {
+    fn dimensions(&self) -> Point {
+        self.map.dimensions()
+    }
+}
+
 impl<'a> System<'a> for VisibilitySystem {
+    #[allow(clippy::type_complexity)]
     type SystemData = (
         WriteExpect<'a, Map>,
         Entities<'a>,
         WriteStorage<'a, Viewshed>,
         WriteStorage<'a, Position>,
         ReadStorage<'a, Player>,
+        ReadStorage<'a, Item>,
+        ReadStorage<'a, Renderable>,
+        ReadStorage<'a, BlocksVision>,
+        ReadExpect<'a, Settings>,
+        ReadStorage<'a, LightSource>,
+        ReadStorage<'a, Equipped>,
+        ReadStorage<'a, InBackpack>,
+        ReadStorage<'a, Size>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut map, entities, mut viewshed, pos, player) = data;
+        let (
+            mut map,
+            entities,
+            mut viewshed,
+            positions,
+            player,
+            items,
+            renderables,
+            vision_blockers,
+            settings,
+            lights,
+            equipped,
+            backpack,
+            sizes,
+        ) = data;
+
+        // blockers move around, so every viewshed has to be redone each turn,
+        // a big creature blocks sight through all of its tiles
+        let blockers_on = settings.creatures_block_vision;
+        let mut blocker_positions: Vec<(Entity, usize)> = Vec::new();
+        if blockers_on {
+            for (blocker, pos, _blocks) in (&entities, &positions, &vision_blockers).join() {
+                for tile in footprint(sizes.get(blocker), pos.x, pos.y) {
+                    blocker_positions.push((blocker, map.xy_idx(tile.x, tile.y)));
+                }
+            }
+        }
 
-        for (ent, viewshed, pos) in (&entities, &mut viewshed, &pos).join() {
-            if viewshed.dirty {
+        for (ent, viewshed, pos) in (&entities, &mut viewshed, &positions).join() {
+            let is_player = player.get(ent).is_some();
+            if viewshed.dirty || blockers_on {
                 viewshed.dirty = false;
 
+                // a light only ever helps, it doesn't shrink what the eyes can already see
+                let range = match carried_light(ent, &entities, &lights, &equipped, &backpack) {
+                    Some(light) if is_player => i32::max(viewshed.range, light.radius),
+                    _ => viewshed.range,
+                };
+
                 viewshed.visible_tiles.clear();
-                viewshed.visible_tiles =
-                    field_of_view(Point::new(pos.x, pos.y), viewshed.range, &*map);
+                if blockers_on {
+                    // a creature never blocks its own view
+                    let mut blockers = vec![false; map.tiles.len()];
+                    for (blocker, idx) in blocker_positions.iter() {
+                        if *blocker != ent {
+                            blockers[*idx] = true;
+                        }
+                    }
+                    let occlusion = OcclusionMap { map: &map, blockers };
+                    viewshed.visible_tiles =
+                        field_of_view(Point::new(pos.x, pos.y), range, &occlusion);
+                } else {
+                    // only the walls matter here, and those stay put
+                    viewshed.visible_tiles = map.cached_field_of_view(Point::new(pos.x, pos.y), range);
+                }
 
                 // deletes any entries that don't meet the specified criteria
                 viewshed
@@ -47,9 +138,83 @@ impl<'a> System<'a> for VisibilitySystem {
                         let idx = map.xy_idx(vis.x, vis.y);
                         map.revealed_tiles[idx] = true;
                         map.visible_tiles[idx] = true;
+                        map.remembered_items.remove(&idx);
+                    }
+
+                    // remember what's lying on the floor in view
+                    for (_item, item_pos, render) in (&items, &positions, &renderables).join() {
+                        let idx = map.xy_idx(item_pos.x, item_pos.y);
+                        if map.visible_tiles[idx] {
+                            map.remembered_items.insert(idx, render.glyph);
+                        }
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory_system::InventorySystem;
+    use crate::test_support::{corridor_map, open_map, refresh_map, spawn_at, test_world};
+    use crate::WantsToPickUpItem;
+
+    // whether the player can see (x, 10) down a corridor with an orc at (8, 10)
+    fn player_sees(creatures_block_vision: bool, x: i32) -> bool {
+        let (mut ecs, _player) = test_world(corridor_map(10, 1, 20), 5, 10);
+        ecs.write_resource::<Settings>().creatures_block_vision = creatures_block_vision;
+        spawn_at(&mut ecs, 8, 10, "Orc");
+        refresh_map(&mut ecs);
+        let map = ecs.fetch::<Map>();
+        map.visible_tiles[map.xy_idx(x, 10)]
+    }
+
+    #[test]
+    fn orc_in_a_corridor_hides_what_is_behind_it() {
+        assert!(player_sees(true, 8));
+        assert!(!player_sees(true, 10));
+        assert!(player_sees(false, 10));
+    }
+
+    #[test]
+    fn all_of_an_ogre_blocks_the_view() {
+        // two rows of corridor, the ogre's anchor is on the upper one
+        let mut map = corridor_map(10, 1, 20);
+        for x in 1..=20 {
+            let idx = map.xy_idx(x, 11);
+            map.tiles[idx] = crate::TileType::Floor;
+        }
+        map.populate_blocked();
+        let (mut ecs, _player) = test_world(map, 5, 11);
+        ecs.write_resource::<Settings>().creatures_block_vision = true;
+        spawn_at(&mut ecs, 8, 10, "Ogre");
+        refresh_map(&mut ecs);
+        let map = ecs.fetch::<Map>();
+        assert!(map.visible_tiles[map.xy_idx(8, 11)]);
+        assert!(!map.visible_tiles[map.xy_idx(12, 11)]);
+    }
+
+    #[test]
+    fn picking_up_a_torch_lights_up_further() {
+        let (mut ecs, player) = test_world(open_map(), 40, 25);
+        let torch = spawn_at(&mut ecs, 40, 25, "Torch");
+        refresh_map(&mut ecs);
+        let seen_before = ecs.read_storage::<Viewshed>().get(player).unwrap().visible_tiles.len();
+        {
+            let map = ecs.fetch::<Map>();
+            assert!(!map.visible_tiles[map.xy_idx(50, 25)]);
+        }
+
+        // standing still, so only the light can make the view worth redoing
+        ecs.write_storage::<WantsToPickUpItem>().insert(player, WantsToPickUpItem{ collected_by: player, item: torch }).unwrap();
+        InventorySystem {}.run_now(&ecs);
+        refresh_map(&mut ecs);
+
+        let seen_after = ecs.read_storage::<Viewshed>().get(player).unwrap().visible_tiles.len();
+        assert!(seen_after > seen_before);
+        let map = ecs.fetch::<Map>();
+        assert!(map.visible_tiles[map.xy_idx(50, 25)]);
+    }
+}